@@ -3,8 +3,6 @@ use crate::camera_controler::CameraController;
 use crate::color::*;
 use crate::pipeline::AppRenderPipeline;
 use cgmath::*;
-use hashbrown::HashMap;
-use rayon::prelude::*;
 use std::sync::Arc;
 use std::sync::RwLock;
 use steamengine_renderer::Renderer;
@@ -20,17 +18,19 @@ use steamengine_renderer_util::camera::prespective::PrespectiveCamera;
 use steamengine_renderer_util::depth_texture::DefaultDepthTexture;
 use steamengine_renderer_util::depth_texture::DepthTexture;
 use steamengine_renderer_util::depth_texture::RenderPassCreateDepthTexture;
+use steamengine_renderer_util::draw_queue::DrawQueueBuilder;
+use steamengine_renderer_util::instance_buffer::InstanceBuffer;
 use steamengine_renderer_util::resources::Identifier;
 use steamengine_renderer_util::resources::model::ModelResourceLoader;
 use steamengine_renderer_util::resources::model::Models;
 use steamengine_renderer_util::resources::model::RenderPassAttachModels;
 use steamengine_renderer_util::resources::texture::TextureResourceLoader;
 use steamengine_renderer_util::simple_buffer::DrawQueueBuffer;
+use steamengine_renderer_util::simple_buffer::RenderPassDrawQueue;
 use steamengine_renderer_util::simple_buffer::SimpleBuffer;
 use wgpu::BindGroup;
 use wgpu::BindGroupLayout;
 use wgpu::Buffer;
-use wgpu::util::DrawIndexedIndirectArgs;
 use winit::application::ApplicationHandler;
 use winit::event::WindowEvent;
 use winit::event_loop::ActiveEventLoop;
@@ -41,9 +41,9 @@ pub struct State<'a> {
     renderer: Option<Arc<Renderer<'a>>>,
     atlas_bindings: Option<Bindings>,
     models: Option<Models>,
-    models_keys: Option<HashMap<Identifier, DrawIndexedIndirectArgs>>,
+    draw_queue: Option<DrawQueueBuilder<RawInstance>>,
     pipeline: Option<wgpu::RenderPipeline>,
-    instances: Option<Arc<InstanceBuffer<'a>>>,
+    instances: Option<Arc<InstanceBuffer<'a, RawInstance>>>,
     bg_color: Arc<RwLock<Color>>,
     commands: Option<Arc<DrawQueueBuffer<'a>>>,
     camera_buffer: Option<CameraBuffer<'a, PrespectiveCamera>>,
@@ -60,7 +60,7 @@ impl Default for State<'_> {
             window: None,
             renderer: None,
             models: None,
-            models_keys: None,
+            draw_queue: None,
             pipeline: None,
             instances: None,
             commands: None,
@@ -84,7 +84,9 @@ impl ApplicationHandler<()> for State<'static> {
         let size = (size.width, size.height);
         let renderer = pollster::block_on(
             RendererBuilder::new()
-                .required_features(wgpu::Features::MULTI_DRAW_INDIRECT)
+                .optional_features(
+                    wgpu::Features::MULTI_DRAW_INDIRECT | wgpu::Features::INDIRECT_FIRST_INSTANCE,
+                )
                 .build(window.clone(), size),
         )
         .unwrap();
@@ -166,11 +168,6 @@ impl ApplicationHandler<()> for State<'static> {
             .unwrap()
             .clone();
 
-        let models_keys: HashMap<Identifier, DrawIndexedIndirectArgs> = models_keys
-            .par_iter()
-            .map(|(k, v)| (k.clone(), v.first().unwrap().clone()))
-            .collect();
-
         let pipeline = AppRenderPipeline::new(&[&camera_bindings.layout(), &textures.layout()])
             .to_wgpu(&renderer);
 
@@ -181,48 +178,31 @@ impl ApplicationHandler<()> for State<'static> {
 
         *self.bg_color.write().unwrap() = Color::new(1.0, 1.0, 1.0, 1.0);
 
-        instances.set(
-            0,
-            Instance::new(
-                Color::new(1.0, 0.0, 0.0, 1.0),
-                Matrix4::from_translation(Vector3::new(0.0, 0.0, 1.0)),
-                tree_bounds,
-            ),
-        );
-        instances.set(
-            1,
-            Instance::new(
-                Color::new(0.0, 1.0, 0.0, 1.0),
-                Matrix4::from_translation(Vector3::new(3.0, 0.0, 0.0)),
-                tree_bounds,
-            ),
+        let mut draw_queue = DrawQueueBuilder::new(models_keys);
+        draw_queue.draw(
+            &Identifier::parse_from_str("resources/models/quit.obj"),
+            &[
+                Instance::new(
+                    Color::new(1.0, 0.0, 0.0, 1.0),
+                    Matrix4::from_translation(Vector3::new(0.0, 0.0, 1.0)),
+                    tree_bounds,
+                ),
+                Instance::new(
+                    Color::new(0.0, 1.0, 0.0, 1.0),
+                    Matrix4::from_translation(Vector3::new(3.0, 0.0, 0.0)),
+                    tree_bounds,
+                ),
+            ],
         );
-        instances.set(
-            2,
-            Instance::new(
+        draw_queue.draw(
+            &Identifier::parse_from_str("resources/models/triangle.obj"),
+            &[Instance::new(
                 WHITE,
                 Matrix4::from_translation(vec3(3.0, 2.0, 1.0)),
                 tree_bounds,
-            ),
+            )],
         );
-
-        let mut model = models_keys
-            .get(&Identifier::parse_from_str("resources/models/quit.obj"))
-            .unwrap()
-            .clone();
-
-        model.instance_count = 2;
-        model.first_instance = 0;
-        commands.set(0, model);
-
-        let mut model = models_keys
-            .get(&Identifier::parse_from_str("resources/models/triangle.obj"))
-            .unwrap()
-            .clone();
-
-        model.instance_count = 3;
-        model.first_instance = 2;
-        commands.set(1, model);
+        draw_queue.build(&instances, 0, commands.as_ref());
 
         let depth_texture = renderer.create_depth_texture::<DefaultDepthTexture>();
 
@@ -230,7 +210,7 @@ impl ApplicationHandler<()> for State<'static> {
         self.window = Some(window);
         self.pipeline = Some(pipeline);
         self.models = Some(models);
-        self.models_keys = Some(models_keys);
+        self.draw_queue = Some(draw_queue);
         self.instances = Some(instances);
         self.commands = Some(commands);
         self.camera_bindings = Some(camera_bindings);
@@ -274,6 +254,7 @@ impl<'a> State<'a> {
         let models = self.models.as_ref().unwrap();
         let color = self.bg_color.read().unwrap();
         let commands = self.commands.as_ref().unwrap();
+        let draw_queue = self.draw_queue.as_ref().unwrap();
         let binding = self.camera_bindings.as_ref().unwrap();
         let atlas_binding = self.atlas_bindings.as_ref().unwrap();
         let depth_texture = self.depth_texture.as_ref().unwrap();
@@ -300,11 +281,11 @@ impl<'a> State<'a> {
 
             render_pass.set_pipeline(pipeline);
             render_pass.set_models(models);
-            render_pass.set_vertex_buffer(1, instances.buffer().slice(..));
+            render_pass.set_vertex_buffer(1, instances.slice());
             render_pass.set_bind_group(0, binding.bind().clone().as_ref(), &[]);
             render_pass.set_bind_group(1, atlas_binding.bind().clone().as_ref(), &[]);
 
-            render_pass.multi_draw_indexed_indirect(&commands.buffer(), 0, 2);
+            render_pass.draw_queue(commands, draw_queue.count());
         }
 
         renderer.queue().submit(std::iter::once(encoder.finish()));
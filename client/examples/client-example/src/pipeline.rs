@@ -1,8 +1,10 @@
 use crate::buffers::RawInstance;
 use steamengine_renderer::render_pipeline::RenderPipeline;
+use steamengine_renderer::shader::ShaderSource;
 use steamengine_renderer::vertex::Vertex;
 use steamengine_renderer_util::depth_texture::DefaultDepthTexture;
 use steamengine_renderer_util::depth_texture::DepthTexture;
+use steamengine_renderer_util::instance_buffer::InstanceBuffer;
 use wgpu::BindGroupLayout;
 use wgpu::PipelineLayoutDescriptor;
 
@@ -19,22 +21,22 @@ impl<'a> RenderPipeline for AppRenderPipeline<'a> {
         "Render pipeline"
     }
 
-    fn source(&self) -> &str {
-        include_str!("shader.wgsl")
+    fn source(&self) -> ShaderSource<'_> {
+        include_str!("shader.wgsl").into()
     }
 
     fn buffers(&self) -> Vec<wgpu::VertexBufferLayout> {
         vec![
             steamengine_renderer_util::resources::model::Vertex::desc(),
-            RawInstance::desc(),
+            InstanceBuffer::<RawInstance>::desc(),
         ]
     }
-    fn layout(&self) -> wgpu::PipelineLayoutDescriptor {
-        PipelineLayoutDescriptor {
+    fn layout(&self) -> Option<wgpu::PipelineLayoutDescriptor> {
+        Some(PipelineLayoutDescriptor {
             label: Some("Render pipeline layout"),
             bind_group_layouts: self.bind_group_layouts,
             push_constant_ranges: &[],
-        }
+        })
     }
     fn depth_stencil(&self) -> Option<wgpu::DepthStencilState> {
         Some(DefaultDepthTexture::pipeline_stencil())
@@ -174,7 +174,7 @@ struct State<'a> {
     bg_color: Arc<RwLock<Color>>,
     commands: Option<Arc<DrawQueueBuffer<'a>>>,
     camera: PrespectiveCamera,
-    camera_bind_group: Option<(BindGroupLayout, BindGroup)>,
+    camera_bind_group: Option<(std::sync::Arc<BindGroupLayout>, BindGroup)>,
     view_camera_buffer: Option<Buffer>,
     projection_camera_buffer: Option<Buffer>,
     yaw: f32,
@@ -0,0 +1,442 @@
+/// This crate contrains the derive macros of steamengine-renderer
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{
+    Data, DeriveInput, Expr, Fields, Ident, Lit, LitInt, Type, parse_macro_input, spanned::Spanned,
+};
+
+/// Implements `steamengine_renderer::vertex::Vertex` from the fields of the struct
+///
+/// The offsets and the `VertexFormat` of every field are computed from its type, the locations
+/// start at 0 and follow the order of the fields
+/// - `#[vertex(instance)]` in the struct uses `VertexStepMode::Instance`
+/// - `#[location(n)]` in a field sets its location, the next fields continue from it
+/// - `#[format(Unorm8x4)]` in a field overrides the format of its type
+///
+/// Matrices (`[[f32; 4]; 4]`, `glam::Mat4`...) are split in one attribute per column
+/// ## Example
+/// ```rust
+/// use steamengine_renderer::vertex::Vertex;
+/// use steamengine_renderer::wgpu::{VertexFormat, VertexStepMode};
+///
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
+/// struct ModelVertex {
+///     position: [f32; 3],
+///     uv: [f32; 2],
+///     #[location(4)]
+///     normal: [f32; 3],
+///     tangent: [f32; 4],
+///     #[format(Unorm8x4)]
+///     color: [u8; 4],
+///     bone: u32,
+/// }
+///
+/// let layout = ModelVertex::desc();
+/// assert_eq!(layout.array_stride, 56);
+/// assert_eq!(layout.step_mode, VertexStepMode::Vertex);
+/// let attributes: Vec<_> = layout
+///     .attributes
+///     .iter()
+///     .map(|attribute| (attribute.offset, attribute.shader_location, attribute.format))
+///     .collect();
+/// assert_eq!(
+///     attributes,
+///     [
+///         (0, 0, VertexFormat::Float32x3),
+///         (12, 1, VertexFormat::Float32x2),
+///         (20, 4, VertexFormat::Float32x3),
+///         (32, 5, VertexFormat::Float32x4),
+///         (48, 6, VertexFormat::Unorm8x4),
+///         (52, 7, VertexFormat::Uint32),
+///     ]
+/// );
+/// ```
+/// The columns of the matrices have consecutive locations and offsets
+/// ```rust
+/// use steamengine_renderer::vertex::Vertex;
+/// use steamengine_renderer::wgpu::{VertexFormat, VertexStepMode};
+///
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
+/// #[vertex(instance)]
+/// struct Instance {
+///     #[location(5)]
+///     model: [[f32; 4]; 4],
+///     normal: [[f32; 3]; 3],
+///     layer: [i32; 2],
+/// }
+///
+/// let layout = Instance::desc();
+/// assert_eq!(layout.array_stride, 108);
+/// assert_eq!(layout.step_mode, VertexStepMode::Instance);
+/// let attributes: Vec<_> = layout
+///     .attributes
+///     .iter()
+///     .map(|attribute| (attribute.offset, attribute.shader_location, attribute.format))
+///     .collect();
+/// assert_eq!(
+///     attributes,
+///     [
+///         (0, 5, VertexFormat::Float32x4),
+///         (16, 6, VertexFormat::Float32x4),
+///         (32, 7, VertexFormat::Float32x4),
+///         (48, 8, VertexFormat::Float32x4),
+///         (64, 9, VertexFormat::Float32x3),
+///         (76, 10, VertexFormat::Float32x3),
+///         (88, 11, VertexFormat::Float32x3),
+///         (100, 12, VertexFormat::Sint32x2),
+///     ]
+/// );
+/// ```
+/// Unknown options, types without a format and repeated locations don't compile
+/// ```rust,compile_fail
+/// # use steamengine_renderer::vertex::Vertex;
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
+/// #[vertex(per_instance)]
+/// struct Point {
+///     position: [f32; 3],
+/// }
+/// ```
+/// ```rust,compile_fail
+/// # use steamengine_renderer::vertex::Vertex;
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
+/// struct Point {
+///     position: [f32; 3],
+///     // the 8 bit formats don't have 3 components
+///     color: [u8; 3],
+/// }
+/// ```
+/// ```rust,compile_fail
+/// # use steamengine_renderer::vertex::Vertex;
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
+/// struct Point {
+///     position: [f32; 3],
+///     #[location(0)]
+///     color: [f32; 4],
+/// }
+/// ```
+/// ```rust,compile_fail
+/// # use steamengine_renderer::vertex::Vertex;
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
+/// struct Point {
+///     #[location(first)]
+///     position: [f32; 3],
+/// }
+/// ```
+/// ```rust,compile_fail
+/// # use steamengine_renderer::vertex::Vertex;
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
+/// struct Point([f32; 3]);
+/// ```
+#[proc_macro_derive(Vertex, attributes(vertex, location, format))]
+pub fn derive_vertex(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input, quote!(::steamengine_renderer::vertex::Vertex), false)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `steamengine_renderer::instances::RawInstance` from the fields of the struct
+///
+/// Works like `#[derive(Vertex)]` but the layout always uses `VertexStepMode::Instance`
+/// ## Example
+/// ```rust
+/// use steamengine_renderer::instances::RawInstance;
+/// use steamengine_renderer::wgpu::{VertexFormat, VertexStepMode};
+///
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, RawInstance)]
+/// struct Sprite {
+///     #[location(2)]
+///     uv_offset: [f32; 2],
+///     uv_scale: [f32; 2],
+/// }
+///
+/// let layout = Sprite::desc();
+/// assert_eq!(layout.step_mode, VertexStepMode::Instance);
+/// assert_eq!(layout.attributes[1].offset, 8);
+/// assert_eq!(layout.attributes[1].shader_location, 3);
+/// assert_eq!(layout.attributes[1].format, VertexFormat::Float32x2);
+/// ```
+/// The instances don't accept the `vertex` options
+/// ```rust,compile_fail
+/// # use steamengine_renderer::instances::RawInstance;
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, RawInstance)]
+/// #[vertex(instance)]
+/// struct Sprite {
+///     uv_offset: [f32; 2],
+/// }
+/// ```
+#[proc_macro_derive(RawInstance, attributes(location, format))]
+pub fn derive_raw_instance(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(
+        input,
+        quote!(::steamengine_renderer::instances::RawInstance),
+        true,
+    )
+    .unwrap_or_else(syn::Error::into_compile_error)
+    .into()
+}
+
+fn expand(
+    input: DeriveInput,
+    trait_path: TokenStream2,
+    instance: bool,
+) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "vertex layouts cannot be derived for generic structs",
+        ));
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    name.span(),
+                    "vertex layouts can only be derived for structs with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                name.span(),
+                "vertex layouts can only be derived for structs",
+            ));
+        }
+    };
+
+    let mut instance = instance;
+    for attr in &input.attrs {
+        if attr.path().is_ident("vertex") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("instance") {
+                    instance = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown vertex option, expected `instance`"))
+                }
+            })?;
+        }
+    }
+
+    let mut attributes = Vec::new();
+    let mut locations: Vec<u32> = Vec::new();
+    let mut location = 0u32;
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let mut format = None;
+        for attr in &field.attrs {
+            if attr.path().is_ident("location") {
+                location = attr.parse_args::<LitInt>()?.base10_parse()?;
+            } else if attr.path().is_ident("format") {
+                format = Some(attr.parse_args::<Ident>()?);
+            }
+        }
+        let columns = match format {
+            Some(format) => vec![format],
+            None => formats_of(&field.ty)?,
+        };
+        for (column, format) in columns.iter().enumerate() {
+            if locations.contains(&location) {
+                return Err(syn::Error::new(
+                    field.span(),
+                    format!("the location {} is used by other field", location),
+                ));
+            }
+            locations.push(location);
+            let column = column as u64;
+            attributes.push(quote! {
+                ::steamengine_renderer::wgpu::VertexAttribute {
+                    offset: ::core::mem::offset_of!(#name, #ident) as u64
+                        + #column * ::steamengine_renderer::wgpu::VertexFormat::#format.size(),
+                    shader_location: #location,
+                    format: ::steamengine_renderer::wgpu::VertexFormat::#format,
+                }
+            });
+            location += 1;
+        }
+    }
+
+    let step_mode = if instance {
+        quote!(::steamengine_renderer::wgpu::VertexStepMode::Instance)
+    } else {
+        quote!(::steamengine_renderer::wgpu::VertexStepMode::Vertex)
+    };
+    Ok(quote! {
+        impl #trait_path for #name {
+            fn desc() -> ::steamengine_renderer::wgpu::VertexBufferLayout<'static> {
+                const ATTRIBUTES: &[::steamengine_renderer::wgpu::VertexAttribute] = &[#(#attributes),*];
+                ::steamengine_renderer::wgpu::VertexBufferLayout {
+                    array_stride: ::core::mem::size_of::<#name>() as ::steamengine_renderer::wgpu::BufferAddress,
+                    step_mode: #step_mode,
+                    attributes: ATTRIBUTES,
+                }
+            }
+        }
+    })
+}
+
+/// returns the formats of a field type, one per column if it is a matrix
+fn formats_of(ty: &Type) -> syn::Result<Vec<Ident>> {
+    let unsupported = || {
+        syn::Error::new(
+            ty.span(),
+            "unsupported vertex field type, set the format with `#[format(...)]`",
+        )
+    };
+    match ty {
+        Type::Array(array) => {
+            let len = array_len(&array.len).ok_or_else(unsupported)?;
+            match &*array.elem {
+                // matrix
+                Type::Array(column) => {
+                    let rows = array_len(&column.len).ok_or_else(unsupported)?;
+                    let scalar = scalar_name(&column.elem).ok_or_else(unsupported)?;
+                    let format = format_name(&scalar, rows).ok_or_else(unsupported)?;
+                    Ok(vec![format; len as usize])
+                }
+                elem => {
+                    let scalar = scalar_name(elem).ok_or_else(unsupported)?;
+                    Ok(vec![format_name(&scalar, len).ok_or_else(unsupported)?])
+                }
+            }
+        }
+        Type::Path(path) => {
+            let segment = path.path.segments.last().ok_or_else(unsupported)?;
+            let name = segment.ident.to_string();
+            let (format, columns) = match name.as_str() {
+                "f32" | "u32" | "i32" | "f64" | "f16" | "u16" | "i16" | "u8" | "i8" => {
+                    (format_name(&name, 1), 1)
+                }
+                "Vec2" => (format_name("f32", 2), 1),
+                "Vec3" => (format_name("f32", 3), 1),
+                "Vec4" => (format_name("f32", 4), 1),
+                "UVec2" => (format_name("u32", 2), 1),
+                "UVec3" => (format_name("u32", 3), 1),
+                "UVec4" => (format_name("u32", 4), 1),
+                "IVec2" => (format_name("i32", 2), 1),
+                "IVec3" => (format_name("i32", 3), 1),
+                "IVec4" => (format_name("i32", 4), 1),
+                "Mat2" => (format_name("f32", 2), 2),
+                "Mat3" => (format_name("f32", 3), 3),
+                "Mat4" => (format_name("f32", 4), 4),
+                _ => (None, 0),
+            };
+            Ok(vec![format.ok_or_else(unsupported)?; columns])
+        }
+        _ => Err(unsupported()),
+    }
+}
+
+/// returns the name of a primitive type, the last segment of the path, Ex: `f16` for `half::f16`
+fn scalar_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => {
+            let segment = path.path.segments.last()?;
+            segment
+                .arguments
+                .is_none()
+                .then(|| segment.ident.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// returns the length of an array if it is a literal
+fn array_len(len: &Expr) -> Option<u32> {
+    match len {
+        Expr::Lit(lit) => match &lit.lit {
+            Lit::Int(int) => int.base10_parse().ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// returns the name of the `VertexFormat` of `len` components of `scalar`
+fn format_name(scalar: &str, len: u32) -> Option<Ident> {
+    let base = match scalar {
+        "f32" => "Float32",
+        "f64" => "Float64",
+        "f16" => "Float16",
+        "u32" => "Uint32",
+        "i32" => "Sint32",
+        "u16" => "Uint16",
+        "i16" => "Sint16",
+        "u8" => "Uint8",
+        "i8" => "Sint8",
+        _ => return None,
+    };
+    // 8 and 16 bits formats don't have 3 components
+    let small = matches!(scalar, "f16" | "u16" | "i16" | "u8" | "i8");
+    let name = match len {
+        1 => base.to_string(),
+        2 | 4 => format!("{}x{}", base, len),
+        3 if !small => format!("{}x{}", base, len),
+        _ => return None,
+    };
+    Some(Ident::new(&name, Span::call_site()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formats(ty: &str) -> syn::Result<Vec<String>> {
+        let ty: Type = syn::parse_str(ty).expect("valid type");
+        formats_of(&ty).map(|formats| formats.iter().map(Ident::to_string).collect())
+    }
+
+    #[test]
+    fn formats_of_scalars_and_arrays() {
+        assert_eq!(formats("f32").unwrap(), ["Float32"]);
+        assert_eq!(formats("[f32; 3]").unwrap(), ["Float32x3"]);
+        assert_eq!(formats("[u16; 2]").unwrap(), ["Uint16x2"]);
+        assert_eq!(formats("[i8; 4]").unwrap(), ["Sint8x4"]);
+        assert_eq!(formats("glam::Vec2").unwrap(), ["Float32x2"]);
+        assert_eq!(formats("UVec4").unwrap(), ["Uint32x4"]);
+    }
+
+    #[test]
+    fn matrices_are_split_in_columns() {
+        assert_eq!(formats("[[f32; 4]; 4]").unwrap(), ["Float32x4"; 4]);
+        assert_eq!(formats("[[f32; 3]; 2]").unwrap(), ["Float32x3"; 2]);
+        assert_eq!(formats("glam::Mat3").unwrap(), ["Float32x3"; 3]);
+    }
+
+    #[test]
+    fn scalars_can_be_paths() {
+        assert_eq!(formats("half::f16").unwrap(), ["Float16"]);
+        assert_eq!(formats("[half::f16; 4]").unwrap(), ["Float16x4"]);
+        assert_eq!(formats("[[half::f16; 2]; 3]").unwrap(), ["Float16x2"; 3]);
+        assert_eq!(formats("[core::primitive::f32; 3]").unwrap(), ["Float32x3"]);
+        assert!(formats("[half::f16; 3]").is_err());
+        assert!(formats("[Wrapper<f32>; 2]").is_err());
+    }
+
+    #[test]
+    fn unsupported_types_are_errors() {
+        for ty in [
+            "[u8; 3]",
+            "[f32; 5]",
+            "bool",
+            "String",
+            "(f32, f32)",
+            "[f32; N]",
+        ] {
+            assert!(formats(ty).is_err(), "{}", ty);
+        }
+    }
+}
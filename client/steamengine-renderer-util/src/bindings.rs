@@ -19,9 +19,12 @@ impl Bindings {
         self.bind_group_layout.clone()
     }
     /// create a new bind group
-    pub fn new(bind_group: wgpu::BindGroup, bind_group_layout: wgpu::BindGroupLayout) -> Self {
+    pub fn new(
+        bind_group: wgpu::BindGroup,
+        bind_group_layout: impl Into<Arc<wgpu::BindGroupLayout>>,
+    ) -> Self {
         let bind_group = Arc::new(bind_group);
-        let bind_group_layout = Arc::new(bind_group_layout);
+        let bind_group_layout = bind_group_layout.into();
 
         Self {
             bind_group,
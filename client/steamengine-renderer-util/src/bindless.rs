@@ -0,0 +1,349 @@
+use std::num::NonZero;
+use std::sync::{Arc, Mutex};
+use steamengine_renderer::Renderer;
+use steamengine_renderer::bind_group::BindGroupEntryBuilder;
+use steamengine_renderer::sampler::SamplerPreset;
+use steamengine_renderer::texture::{Texture, TextureBuilder, TextureDimensions};
+use tracing::*;
+use wgpu::{
+    BindGroup, BindGroupLayout, BindingResource, Features, Sampler, ShaderStages, TextureFormat,
+    TextureSampleType, TextureView, TextureViewDimension,
+};
+
+use crate::errors::Error;
+
+/// Slots of the table
+struct TableState {
+    views: Vec<Option<TextureView>>,
+    /// free slots, the last one is reused first
+    free: Vec<u32>,
+    /// bind group with the current views, None if a slot changed
+    bind_group: Option<Arc<BindGroup>>,
+}
+
+/// Table of textures bound in a single `binding_array<texture_2d<f32>>`
+/// Every texture gets an index that the shaders use to sample it, the empty slots are bound to a
+/// white placeholder, so the table doesn't need `PARTIALLY_BOUND_BINDING_ARRAY`
+/// Requires `Features::TEXTURE_BINDING_ARRAY` and a `max_binding_array_elements_per_shader_stage`
+/// limit bigger than the capacity, check it with `TextureTable::supported`
+/// The index must be uniform, Ex: a push constant or a uniform, the indices that change between
+/// the invocations, Ex: a vertex output or an instance attribute, also need
+/// `Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING`, check it with
+/// `TextureTable::supports_non_uniform_indexing`
+/// ## Example
+/// ```rust,ignore
+/// let table = TextureTable::new(renderer.clone(), "Textures", 256)?;
+/// let index = table.insert(&texture)?;
+/// // declare the table in the shader
+/// let source = format!("{}\n{}", table.wgsl(1, "textures"), SHADER);
+///
+/// render_pass.set_bind_group(1, table.bind_group().as_ref(), &[]);
+/// ```
+pub struct TextureTable<'a> {
+    renderer: Arc<Renderer<'a>>,
+    label: String,
+    capacity: u32,
+    placeholder: TextureView,
+    sampler: Sampler,
+    layout: Arc<BindGroupLayout>,
+    state: Mutex<TableState>,
+}
+impl<'a> TextureTable<'a> {
+    /// returns true if the renderer can bind a table with `capacity` textures
+    pub fn supported(renderer: &Renderer, capacity: u32) -> bool {
+        Self::check(renderer, capacity).is_ok()
+    }
+    /// returns true if the shaders can index the table with values that aren't uniform
+    /// Ex: an index from the vertex output or an instance attribute
+    pub fn supports_non_uniform_indexing(renderer: &Renderer) -> bool {
+        renderer
+            .features()
+            .contains(Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING)
+    }
+    fn check(renderer: &Renderer, capacity: u32) -> Result<(), Error> {
+        if !renderer
+            .features()
+            .contains(Features::TEXTURE_BINDING_ARRAY)
+        {
+            return Err(Error::BindlessUnsupported(
+                "missing feature TEXTURE_BINDING_ARRAY",
+            ));
+        }
+        if renderer
+            .device()
+            .limits()
+            .max_binding_array_elements_per_shader_stage
+            < capacity
+        {
+            return Err(Error::BindlessUnsupported(
+                "capacity bigger than max_binding_array_elements_per_shader_stage",
+            ));
+        }
+        Ok(())
+    }
+    /// create a new table with `capacity` slots, visible in the fragment shader
+    pub fn new(renderer: Arc<Renderer<'a>>, label: &str, capacity: u32) -> Result<Self, Error> {
+        Self::check(&renderer, capacity)?;
+        let count = NonZero::new(capacity).ok_or(Error::BindlessUnsupported(
+            "a table needs at least one slot",
+        ))?;
+        let placeholder = renderer
+            .init_texture(
+                "Texture Table Placeholder",
+                None,
+                TextureBuilder::new()
+                    .dimension(TextureDimensions::D2(1, 1))
+                    .data(vec![255; 4]),
+            )
+            .create_view(wgpu::TextureViewDescriptor::default());
+        let sampler = renderer.sampler_preset(SamplerPreset::BILINEAR.repeat());
+        let layout = renderer.bind_group_layout(
+            label,
+            &[
+                BindGroupEntryBuilder::new(0)
+                    .on(ShaderStages::FRAGMENT)
+                    .texture()
+                    .has(count)
+                    .layout_entry(),
+                BindGroupEntryBuilder::new(1)
+                    .on(ShaderStages::FRAGMENT)
+                    .sampler()
+                    .layout_entry(),
+            ],
+        );
+        Ok(Self {
+            renderer,
+            label: label.to_owned(),
+            capacity,
+            placeholder,
+            sampler,
+            layout,
+            state: Mutex::new(TableState {
+                views: Vec::new(),
+                free: Vec::new(),
+                bind_group: None,
+            }),
+        })
+    }
+    /// Adds a texture to the table, returns the index of the texture in the shader
+    /// uses the view of the texture or the default view if it doesn't have one
+    /// The texture must be a 2D texture that can be sampled with filtering
+    pub fn insert(&self, texture: &Texture) -> Result<u32, Error> {
+        self.check_texture(texture)?;
+        self.insert_view(view_of(texture))
+    }
+    /// Adds a view to the table, returns the index of the view in the shader
+    /// The view must be a 2D view of a texture that can be sampled with filtering
+    pub fn insert_view(&self, view: TextureView) -> Result<u32, Error> {
+        let mut state = self.state.lock().expect("Cannot lock texture table");
+        let index = match state.free.pop() {
+            Some(index) => index,
+            None if (state.views.len() as u32) < self.capacity => {
+                state.views.push(None);
+                state.views.len() as u32 - 1
+            }
+            None => {
+                error!(
+                    "attempt to nest a texture outside the capacity of the table, TextureTable Overflow"
+                );
+                return Err(Error::TableFull(self.capacity));
+            }
+        };
+        state.views[index as usize] = Some(view);
+        state.bind_group = None;
+        Ok(index)
+    }
+    /// Replaces the texture of an index, Ex: a texture that was reloaded
+    pub fn replace(&self, index: u32, texture: &Texture) -> Result<(), Error> {
+        self.check_texture(texture)?;
+        let mut state = self.state.lock().expect("Cannot lock texture table");
+        match state.views.get_mut(index as usize) {
+            Some(slot @ Some(_)) => *slot = Some(view_of(texture)),
+            _ => {
+                warn!("Replacing a texture that isn't in the table -- {}", index);
+                return Ok(());
+            }
+        }
+        state.bind_group = None;
+        Ok(())
+    }
+    fn check_texture(&self, texture: &Texture) -> Result<(), Error> {
+        check_texture(
+            texture.texture.format(),
+            texture.view_dimension,
+            texture.texture.sample_count(),
+            self.renderer.features(),
+        )
+        .inspect_err(|err| error!("attempt to nest a texture in the table, {}", err))
+    }
+    /// Removes the texture of an index, the index can be returned by the next insert
+    pub fn remove(&self, index: u32) {
+        let mut state = self.state.lock().expect("Cannot lock texture table");
+        match state.views.get_mut(index as usize) {
+            Some(slot @ Some(_)) => *slot = None,
+            _ => {
+                warn!("Removing a texture that isn't in the table -- {}", index);
+                return;
+            }
+        }
+        state.free.push(index);
+        state.bind_group = None;
+    }
+    /// returns true if the index has a texture
+    pub fn contains(&self, index: u32) -> bool {
+        self.state
+            .lock()
+            .expect("Cannot lock texture table")
+            .views
+            .get(index as usize)
+            .is_some_and(Option::is_some)
+    }
+    /// Number of textures in the table
+    pub fn len(&self) -> u32 {
+        let state = self.state.lock().expect("Cannot lock texture table");
+        (state.views.len() - state.free.len()) as u32
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+    /// Gets the layout of the table for the pipelines
+    pub fn layout(&self) -> Arc<BindGroupLayout> {
+        self.layout.clone()
+    }
+    /// Gets the bind group with the current textures, it is rebuilt if the table changed
+    pub fn bind_group(&self) -> Arc<BindGroup> {
+        let mut state = self.state.lock().expect("Cannot lock texture table");
+        if let Some(bind_group) = &state.bind_group {
+            return bind_group.clone();
+        }
+        trace!("Rebuilding texture table -- {}", self.label);
+        let views: Vec<&TextureView> = (0..self.capacity as usize)
+            .map(|index| {
+                state
+                    .views
+                    .get(index)
+                    .and_then(Option::as_ref)
+                    .unwrap_or(&self.placeholder)
+            })
+            .collect();
+        let count = NonZero::new(self.capacity).expect("Capacity checked before");
+        let bind_group = self
+            .renderer
+            .bind_group_with_layout(
+                &self.label,
+                &self.layout,
+                &[
+                    BindGroupEntryBuilder::new(0)
+                        .on(ShaderStages::FRAGMENT)
+                        .texture()
+                        .has(count)
+                        .with(BindingResource::TextureViewArray(&views)),
+                    BindGroupEntryBuilder::new(1)
+                        .on(ShaderStages::FRAGMENT)
+                        .sampler()
+                        .with(BindingResource::Sampler(&self.sampler)),
+                ],
+            )
+            .unwrap_or_else(|err| panic!("Cannot create texture table {}, {}", self.label, err));
+        let bind_group = Arc::new(bind_group);
+        state.bind_group = Some(bind_group.clone());
+        bind_group
+    }
+    /// returns the WGSL declaration of the table in the group, the sampler is `{name}_sampler`
+    /// Ex: `textureSample(textures[index], textures_sampler, uv)`
+    /// the index must be uniform unless `supports_non_uniform_indexing` is true
+    pub fn wgsl(&self, group: u32, name: &str) -> String {
+        format!(
+            "@group({group}) @binding(0) var {name}: binding_array<texture_2d<f32>, {}>;\n@group({group}) @binding(1) var {name}_sampler: sampler;",
+            self.capacity
+        )
+    }
+}
+
+/// checks that a texture can be bound as a `texture_2d<f32>` with a filtering sampler
+fn check_texture(
+    format: TextureFormat,
+    view_dimension: TextureViewDimension,
+    sample_count: u32,
+    features: Features,
+) -> Result<(), Error> {
+    if view_dimension != TextureViewDimension::D2 {
+        return Err(Error::TableMismatch("the texture isn't 2D"));
+    }
+    if sample_count > 1 {
+        return Err(Error::TableMismatch("the texture is multisampled"));
+    }
+    match format.sample_type(None, Some(features)) {
+        Some(TextureSampleType::Float { filterable: true }) => Ok(()),
+        _ => Err(Error::TableMismatch(
+            "the format can't be sampled with filtering",
+        )),
+    }
+}
+
+fn view_of(texture: &Texture) -> TextureView {
+    texture
+        .texture_view
+        .clone()
+        .unwrap_or_else(|| texture.create_view(wgpu::TextureViewDescriptor::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(format: TextureFormat, features: Features) -> Result<(), Error> {
+        check_texture(format, TextureViewDimension::D2, 1, features)
+    }
+
+    #[test]
+    fn filterable_float_textures_are_accepted() {
+        for format in [
+            TextureFormat::Rgba8UnormSrgb,
+            TextureFormat::Rgba8Snorm,
+            TextureFormat::Rgba16Float,
+            TextureFormat::Bc7RgbaUnormSrgb,
+        ] {
+            assert!(check(format, Features::empty()).is_ok(), "{format:?}");
+        }
+        // the 32 bit floats are filterable with the feature
+        assert!(check(TextureFormat::Rgba32Float, Features::FLOAT32_FILTERABLE).is_ok());
+    }
+
+    #[test]
+    fn textures_that_cant_be_filtered_are_rejected() {
+        for format in [
+            TextureFormat::Rgba32Float,
+            TextureFormat::R32Float,
+            TextureFormat::Rgba8Uint,
+            TextureFormat::R16Sint,
+            TextureFormat::Depth32Float,
+            TextureFormat::Depth24PlusStencil8,
+        ] {
+            assert!(
+                matches!(
+                    check(format, Features::empty()),
+                    Err(Error::TableMismatch(_))
+                ),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn textures_must_be_single_sampled_2d() {
+        let features = Features::empty();
+        let format = TextureFormat::Rgba8Unorm;
+        for dimension in [
+            TextureViewDimension::D2Array,
+            TextureViewDimension::Cube,
+            TextureViewDimension::D3,
+        ] {
+            assert!(check_texture(format, dimension, 1, features).is_err());
+        }
+        assert!(check_texture(format, TextureViewDimension::D2, 4, features).is_err());
+    }
+}
@@ -1,7 +1,6 @@
 use glam::*;
 
 /// Use and create cameras easily
-
 /// Implementation for orthographic camera
 /// Enable it with feature "orthographic-camera"
 #[cfg(feature = "orthographic-camera")]
@@ -27,8 +26,7 @@ pub trait Camera: Send + Sync + Clone {
     fn matrix(&self) -> Mat4 {
         let projection = self.projection();
         let view = self.view();
-        let matrix = OPENGL_TO_WGPU_MATRIX * projection * view;
-        matrix
+        OPENGL_TO_WGPU_MATRIX * projection * view
     }
 }
 
@@ -63,7 +61,7 @@ impl<'a> crate::simple_buffer::SimpleBuffer<'a, [[f32; 4]; 4]> for CameraBuffer<
             limit,
         }
     }
-    fn as_entrie(&self) -> wgpu::BindingResource {
+    fn as_entrie(&self) -> wgpu::BindingResource<'_> {
         self.buffer.as_entire_binding()
     }
     fn buffer(&self) -> &wgpu::Buffer {
@@ -95,12 +93,11 @@ pub fn create_bindings(
 ) -> crate::bindings::Bindings {
     use crate::bindings::CreateBindings;
     use steamengine_renderer::bind_group::BindGroupEntryBuilder;
-    let bindings = renderer.new_bindings(
+    renderer.new_bindings(
         "Camera Bindings",
         &[BindGroupEntryBuilder::new(0)
             .uniform()
             .with(buffer)
             .on(wgpu::ShaderStages::VERTEX)],
-    );
-    bindings
+    )
 }
@@ -4,22 +4,24 @@ use glam::*;
 /// Implementation of camera for Orthographic Camera
 /// ## Example
 /// ```rust
-/// use cgmath::point3;
-/// use steamengine_renderer_util::camera::create_bindings;
-/// use steamengine_renderer_util::camera::CameraBuffer;
+/// use std::sync::Arc;
 ///
-/// let camera = OrthographicCamera::default();
-/// // set the aspect ratio with window properties
-/// *camera.aspect_ratio() = width / height;
+/// use glam::vec3;
+/// use steamengine_renderer::Renderer;
+/// use steamengine_renderer_util::camera::{Camera, CameraBuffer};
+/// use steamengine_renderer_util::camera::orthographic::OrthographicCamera;
+/// use steamengine_renderer_util::simple_buffer::SimpleBuffer;
+///
+/// let mut camera = OrthographicCamera::default();
 /// // sets the camera location
-/// *camera.eye() = point3(0.0, 0.0, -2.0);
+/// *camera.eye() = vec3(0.0, 0.0, -2.0);
 ///
 /// // upload the camera to the shader
-/// let buffer = CameraBuffer::new(&renderer, 1);
-/// let binding = create_bindings(&renderer, buffer.as_entrie());
-///
-/// buffer.set(0, camera);
-///
+/// fn upload(renderer: Arc<Renderer<'static>>, camera: OrthographicCamera) {
+///     let buffer = CameraBuffer::new(renderer, 1);
+///     buffer.set_camera(camera);
+/// }
+/// # let _ = upload;
 /// ```
 
 #[derive(Clone, Debug)]
@@ -37,6 +39,7 @@ pub struct OrthographicCamera {
     far: f32,
 }
 impl OrthographicCamera {
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Self {
         let eye = vec3(0.0, 0.0, 5.0);
         let target = vec3(0.0, 0.0, 0.0);
@@ -4,22 +4,26 @@ use glam::*;
 /// Implementation of camera for Prespective Camera
 /// ## Example
 /// ```rust
-/// use cgmath::point3;
-/// use steamengine_renderer_util::camera::create_bindings;
-/// use steamengine_renderer_util::camera::CameraBuffer;
+/// use std::sync::Arc;
 ///
-/// let camera = PrespectiveCamera::default();
+/// use glam::vec3;
+/// use steamengine_renderer::Renderer;
+/// use steamengine_renderer_util::camera::{Camera, CameraBuffer};
+/// use steamengine_renderer_util::camera::prespective::PrespectiveCamera;
+/// use steamengine_renderer_util::simple_buffer::SimpleBuffer;
+///
+/// let mut camera = PrespectiveCamera::default();
 /// // set the aspect ratio with window properties
-/// *camera.aspect_ratio() = width / height;
+/// *camera.aspect_ratio() = 1280.0 / 720.0;
 /// // sets the camera location
-/// *camera.eye() = point3(0.0, 0.0, -2.0);
+/// *camera.eye() = vec3(0.0, 0.0, -2.0);
 ///
 /// // upload the camera to the shader
-/// let buffer = CameraBuffer::new(&renderer, 1);
-/// let binding = create_bindings(&renderer, buffer.as_entrie());
-///
-/// buffer.set(0, camera);
-///
+/// fn upload(renderer: Arc<Renderer<'static>>, camera: PrespectiveCamera) {
+///     let buffer = CameraBuffer::new(renderer, 1);
+///     buffer.set_camera(camera);
+/// }
+/// # let _ = upload;
 /// ```
 
 #[derive(Clone, Debug)]
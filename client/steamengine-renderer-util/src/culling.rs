@@ -0,0 +1,672 @@
+use glam::Mat4;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use steamengine_renderer::Renderer;
+use steamengine_renderer::instances::RawInstance;
+use tracing::*;
+use wgpu::util::DrawIndexedIndirectArgs;
+use wgpu::{
+    BindGroup, Buffer, BufferUsages, CommandEncoder, ComputePipeline, DownlevelFlags, Features,
+};
+
+use crate::instance_buffer::InstanceBuffer;
+use crate::simple_buffer::{DrawQueueBuffer, DrawQueueMode, RenderPassDrawQueue, SimpleBuffer};
+
+const WORKGROUP_SIZE: u32 = 64;
+/// Range of the instances that are not drawn by any command
+const NO_RANGE: u32 = u32::MAX;
+
+/// Bounding sphere of an instance in world space
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BoundingSphere {
+    pub center: [f32; 3],
+    pub radius: f32,
+}
+impl BoundingSphere {
+    pub fn new(center: [f32; 3], radius: f32) -> Self {
+        Self { center, radius }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullInstance {
+    sphere: BoundingSphere,
+    range: u32,
+    first: u32,
+    _pad: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct CommandTemplate {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+    range: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullParams {
+    planes: [[f32; 4]; 6],
+    instance_count: u32,
+    range_count: u32,
+    command_count: u32,
+    stride: u32,
+    compact: u32,
+    _pad: [u32; 3],
+}
+
+struct CullingState {
+    instances: Vec<CullInstance>,
+    ranges: u32,
+    commands: u32,
+}
+
+impl CullingState {
+    /// Assigns an instance range to every command and the range of every instance,
+    /// the commands with the same `first_instance` and `instance_count` share the range
+    /// Returns the templates of the commands
+    fn set_commands(
+        &mut self,
+        commands: &[DrawIndexedIndirectArgs],
+        limit: usize,
+    ) -> Vec<CommandTemplate> {
+        let mut ranges: HashMap<(u32, u32), u32> = HashMap::new();
+        let templates: Vec<CommandTemplate> = commands
+            .iter()
+            .map(|command| {
+                let next = ranges.len() as u32;
+                let range = *ranges
+                    .entry((command.first_instance, command.instance_count))
+                    .or_insert(next);
+                CommandTemplate {
+                    index_count: command.index_count,
+                    instance_count: command.instance_count,
+                    first_index: command.first_index,
+                    base_vertex: command.base_vertex,
+                    first_instance: command.first_instance,
+                    range,
+                }
+            })
+            .collect();
+
+        let instance_count = ranges
+            .keys()
+            .map(|(first, count)| (first + count) as usize)
+            .max()
+            .unwrap_or(0)
+            .max(self.instances.len())
+            .min(limit);
+        self.instances.resize(
+            instance_count,
+            CullInstance {
+                range: NO_RANGE,
+                ..Default::default()
+            },
+        );
+        for instance in self.instances.iter_mut() {
+            instance.range = NO_RANGE;
+        }
+        for (&(first, count), &range) in ranges.iter() {
+            let end = ((first + count) as usize).min(instance_count);
+            // the ranges past the limit have no instances
+            let start = (first as usize).min(end);
+            for instance in &mut self.instances[start..end] {
+                instance.range = range;
+                instance.first = first;
+            }
+        }
+        self.ranges = ranges.len() as u32;
+        self.commands = commands.len() as u32;
+        templates
+    }
+}
+
+struct CullingPass {
+    reset: ComputePipeline,
+    cull: ComputePipeline,
+    compact: ComputePipeline,
+    reset_bind_group: BindGroup,
+    cull_bind_group: BindGroup,
+    compact_bind_group: BindGroup,
+    params: Buffer,
+    cull_instances: Buffer,
+    templates: Buffer,
+    counts: Buffer,
+    culled: Buffer,
+    visible: Buffer,
+}
+
+/// GPU frustum culling of instances
+/// A compute pass tests the bounding sphere of every instance against the camera frustum,
+/// copies the visible instances into a compacted instance buffer, writes their indices and
+/// the `DrawIndexedIndirectArgs` into the buffer of the `DrawQueueBuffer`
+///
+/// Needs compute shaders, indirect draws and 5 storage buffers per stage
+/// (build the renderer with `Limits::default()`), otherwise the instances are drawn without culling.
+/// With `Features::MULTI_DRAW_INDIRECT_COUNT` the empty commands are removed
+/// ## Example
+/// ```rust,ignore
+/// let culling = GpuCulling::new(renderer.clone(), &instances, &draw_queue_buffer);
+/// let count = draw_queue.build(&instances, 0, &draw_queue_buffer);
+/// culling.set_commands(&draw_queue_buffer.commands()[..count as usize]);
+/// culling.set_bounds(0, &spheres);
+///
+/// culling.cull(&mut encoder, camera.matrix());
+/// // in the render pass
+/// culling.draw(&mut render_pass, 1, &draw_queue_buffer);
+/// ```
+pub struct GpuCulling<'a> {
+    renderer: Arc<Renderer<'a>>,
+    source: Buffer,
+    stride: u32,
+    limit: u64,
+    command_limit: u64,
+    pass: Option<CullingPass>,
+    state: Mutex<CullingState>,
+}
+impl<'a> GpuCulling<'a> {
+    /// create the culling pass of the instances and commands
+    pub fn new<T: RawInstance>(
+        renderer: Arc<Renderer<'a>>,
+        instances: &InstanceBuffer<'a, T>,
+        commands: &DrawQueueBuffer<'a>,
+    ) -> Self {
+        let size = std::mem::size_of::<T>() as u64;
+        assert!(
+            size.is_multiple_of(4),
+            "The size of a culled instance must be a multiple of 4"
+        );
+        let limit = instances.limit();
+        let command_limit = commands.limit();
+        let pass = if Self::supported(&renderer) {
+            Some(Self::create_pass(
+                &renderer,
+                instances.buffer(),
+                commands.buffer(),
+                limit,
+                command_limit,
+                size,
+            ))
+        } else {
+            warn!(
+                "The device doesn't support GPU culling, the instances are drawn without culling"
+            );
+            None
+        };
+        Self {
+            source: instances.buffer().clone(),
+            renderer,
+            stride: (size / 4) as u32,
+            limit,
+            command_limit,
+            pass,
+            state: Mutex::new(CullingState {
+                instances: Vec::new(),
+                ranges: 0,
+                commands: 0,
+            }),
+        }
+    }
+    /// returns true if the renderer can cull in the GPU
+    pub fn supported(renderer: &Renderer) -> bool {
+        renderer
+            .downlevel_flags()
+            .contains(DownlevelFlags::COMPUTE_SHADERS)
+            && renderer
+                .device()
+                .limits()
+                .max_storage_buffers_per_shader_stage
+                >= 5
+            && DrawQueueMode::of(renderer) != DrawQueueMode::Direct
+    }
+    /// returns true if the culling runs in the GPU
+    pub fn is_enabled(&self) -> bool {
+        self.pass.is_some()
+    }
+    /// Sets the bounding spheres of the instances from an index
+    pub fn set_bounds(&self, first: u64, bounds: &[BoundingSphere]) {
+        let end = first + bounds.len() as u64;
+        if end > self.limit {
+            error!(
+                "attempt to nest a bounding sphere outside the limits of the buffer, GpuCulling Overflow"
+            );
+            return;
+        }
+        let mut state = self.state.lock().expect("Cannot lock culling");
+        if state.instances.len() < end as usize {
+            state.instances.resize(
+                end as usize,
+                CullInstance {
+                    range: NO_RANGE,
+                    ..Default::default()
+                },
+            );
+        }
+        for (instance, sphere) in state.instances[first as usize..end as usize]
+            .iter_mut()
+            .zip(bounds)
+        {
+            instance.sphere = *sphere;
+        }
+        if let Some(pass) = &self.pass {
+            self.renderer.queue().write_buffer(
+                &pass.cull_instances,
+                first * std::mem::size_of::<CullInstance>() as u64,
+                bytemuck::cast_slice(&state.instances[first as usize..end as usize]),
+            );
+        }
+    }
+    /// Sets the commands that are culled, Ex: the commands built by `DrawQueueBuilder`
+    /// The commands that share an instance range share its visible instances
+    pub fn set_commands(&self, commands: &[DrawIndexedIndirectArgs]) {
+        if commands.len() as u64 > self.command_limit {
+            error!(
+                "attempt to nest a command outside the limits of the buffer, GpuCulling Overflow"
+            );
+            return;
+        }
+        let mut state = self.state.lock().expect("Cannot lock culling");
+        let templates = state.set_commands(commands, self.limit as usize);
+
+        if let Some(pass) = &self.pass {
+            let queue = self.renderer.queue();
+            queue.write_buffer(&pass.templates, 0, bytemuck::cast_slice(&templates));
+            queue.write_buffer(
+                &pass.cull_instances,
+                0,
+                bytemuck::cast_slice(&state.instances),
+            );
+        }
+    }
+    /// Records the culling pass, `view_projection` is the matrix of the camera, Ex: `Camera::matrix`
+    pub fn cull(&self, encoder: &mut CommandEncoder, view_projection: Mat4) {
+        let Some(pass) = &self.pass else {
+            return;
+        };
+        let state = self.state.lock().expect("Cannot lock culling");
+        let params = CullParams {
+            planes: frustum_planes(view_projection),
+            instance_count: state.instances.len() as u32,
+            range_count: state.ranges,
+            command_count: state.commands,
+            stride: self.stride,
+            compact: self.compacts() as u32,
+            _pad: [0; 3],
+        };
+        self.renderer
+            .queue()
+            .write_buffer(&pass.params, 0, bytemuck::bytes_of(&params));
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Culling pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&pass.reset);
+        compute_pass.set_bind_group(0, &pass.reset_bind_group, &[]);
+        compute_pass.dispatch_workgroups((state.ranges + 1).div_ceil(WORKGROUP_SIZE), 1, 1);
+        compute_pass.set_pipeline(&pass.cull);
+        compute_pass.set_bind_group(0, &pass.cull_bind_group, &[]);
+        compute_pass.dispatch_workgroups(
+            (state.instances.len() as u32).div_ceil(WORKGROUP_SIZE),
+            1,
+            1,
+        );
+        compute_pass.set_pipeline(&pass.compact);
+        compute_pass.set_bind_group(0, &pass.compact_bind_group, &[]);
+        compute_pass.dispatch_workgroups(state.commands.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+    /// Draws the visible instances, the compacted instances are set in the vertex buffer `slot`
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, slot: u32, commands: &DrawQueueBuffer) {
+        let count = self.state.lock().expect("Cannot lock culling").commands;
+        let Some(pass) = &self.pass else {
+            render_pass.set_vertex_buffer(slot, self.source.slice(..));
+            render_pass.draw_queue(commands, count);
+            return;
+        };
+        render_pass.set_vertex_buffer(slot, pass.culled.slice(..));
+        if self.compacts() {
+            let ranges = self.state.lock().expect("Cannot lock culling").ranges;
+            render_pass.multi_draw_indexed_indirect_count(
+                commands.buffer(),
+                0,
+                &pass.counts,
+                ranges as u64 * 4,
+                count,
+            );
+        } else {
+            render_pass.draw_queue(commands, count);
+        }
+    }
+    /// Gets the buffer with the compacted instances
+    pub fn instances(&self) -> Option<&Buffer> {
+        self.pass.as_ref().map(|pass| &pass.culled)
+    }
+    /// Gets the buffer with the indices of the visible instances, as `array<u32>`
+    /// The visible instances of a command start in its `first_instance`
+    pub fn visible(&self) -> Option<&Buffer> {
+        self.pass.as_ref().map(|pass| &pass.visible)
+    }
+
+    /// returns true if the empty commands are removed
+    fn compacts(&self) -> bool {
+        self.renderer
+            .features()
+            .contains(Features::MULTI_DRAW_INDIRECT_COUNT)
+    }
+    fn create_pass(
+        renderer: &Renderer,
+        source: &Buffer,
+        commands: &Buffer,
+        limit: u64,
+        command_limit: u64,
+        size: u64,
+    ) -> CullingPass {
+        let device = renderer.device();
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Culling shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("culling.wgsl").into()),
+        });
+        let pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(format!("Culling pipeline {}", entry_point).as_str()),
+                layout: None,
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let reset = pipeline("reset");
+        let cull = pipeline("cull");
+        let compact = pipeline("compact");
+
+        let params = renderer.create_buffer(
+            "Culling params",
+            BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            std::mem::size_of::<CullParams>() as u64,
+        );
+        let cull_instances = renderer.create_buffer(
+            "Culling instances",
+            BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            limit.max(1) * std::mem::size_of::<CullInstance>() as u64,
+        );
+        let templates = renderer.create_buffer(
+            "Culling commands",
+            BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            command_limit.max(1) * std::mem::size_of::<CommandTemplate>() as u64,
+        );
+        // one count per instance range and the number of commands
+        let counts = renderer.create_buffer(
+            "Culling counts",
+            BufferUsages::STORAGE | BufferUsages::INDIRECT,
+            (command_limit + 1) * 4,
+        );
+        let culled = renderer.create_buffer(
+            "Culled instances",
+            BufferUsages::STORAGE | BufferUsages::VERTEX,
+            limit.max(1) * size,
+        );
+        let visible =
+            renderer.create_buffer("Visible instances", BufferUsages::STORAGE, limit.max(1) * 4);
+
+        let bind_group = |label: &str, pipeline: &ComputePipeline, buffers: &[(u32, &Buffer)]| {
+            let entries: Vec<wgpu::BindGroupEntry> = buffers
+                .iter()
+                .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                    binding: *binding,
+                    resource: buffer.as_entire_binding(),
+                })
+                .collect();
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &entries,
+            })
+        };
+        let reset_bind_group = bind_group(
+            "Culling reset bind group",
+            &reset,
+            &[(0, &params), (1, &counts)],
+        );
+        let cull_bind_group = bind_group(
+            "Culling bind group",
+            &cull,
+            &[
+                (0, &params),
+                (1, &counts),
+                (2, &cull_instances),
+                (3, source),
+                (4, &culled),
+                (5, &visible),
+            ],
+        );
+        let compact_bind_group = bind_group(
+            "Culling compact bind group",
+            &compact,
+            &[(0, &params), (1, &counts), (6, &templates), (7, commands)],
+        );
+
+        CullingPass {
+            reset,
+            cull,
+            compact,
+            reset_bind_group,
+            cull_bind_group,
+            compact_bind_group,
+            params,
+            cull_instances,
+            templates,
+            counts,
+            culled,
+            visible,
+        }
+    }
+}
+
+/// Extracts the planes of the frustum of a view projection matrix with depth from 0 to 1
+/// The normals of the planes point inside the frustum
+pub fn frustum_planes(matrix: Mat4) -> [[f32; 4]; 6] {
+    let rows = [matrix.row(0), matrix.row(1), matrix.row(2), matrix.row(3)];
+    let planes = [
+        rows[3] + rows[0],
+        rows[3] - rows[0],
+        rows[3] + rows[1],
+        rows[3] - rows[1],
+        rows[2],
+        rows[3] - rows[2],
+    ];
+    planes.map(|plane| (plane / plane.truncate().length()).to_array())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{Vec3, Vec4};
+
+    /// signed distances of a point to the planes, negative outside the frustum
+    fn distances(planes: &[[f32; 4]; 6], point: Vec3) -> [f32; 6] {
+        planes.map(|plane| Vec4::from_array(plane).dot(point.extend(1.0)))
+    }
+
+    fn inside(planes: &[[f32; 4]; 6], point: Vec3) -> bool {
+        distances(planes, point)
+            .iter()
+            .all(|distance| *distance >= 0.0)
+    }
+
+    fn state() -> CullingState {
+        CullingState {
+            instances: Vec::new(),
+            ranges: 0,
+            commands: 0,
+        }
+    }
+
+    fn command(
+        first_index: u32,
+        first_instance: u32,
+        instance_count: u32,
+    ) -> DrawIndexedIndirectArgs {
+        DrawIndexedIndirectArgs {
+            index_count: 3,
+            first_index,
+            first_instance,
+            instance_count,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn perspective_frustum_contains_the_points_in_front_of_the_camera() {
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 0.0, 5.0), Vec3::ZERO, Vec3::Y);
+        let projection = Mat4::perspective_rh(90f32.to_radians(), 1.0, 0.1, 100.0);
+        let planes = frustum_planes(projection * view);
+
+        assert!(inside(&planes, Vec3::ZERO));
+        assert!(inside(&planes, Vec3::new(4.0, -4.0, 0.0)));
+        // behind the camera, past the far plane and outside of the sides
+        assert!(!inside(&planes, Vec3::new(0.0, 0.0, 6.0)));
+        assert!(!inside(&planes, Vec3::new(0.0, 0.0, -100.0)));
+        assert!(!inside(&planes, Vec3::new(6.0, 0.0, 0.0)));
+        assert!(!inside(&planes, Vec3::new(0.0, -6.0, 0.0)));
+    }
+
+    #[test]
+    fn orthographic_frustum_contains_the_points_of_the_box() {
+        let planes = frustum_planes(Mat4::orthographic_rh(-2.0, 2.0, -1.0, 1.0, 0.0, 10.0));
+
+        assert!(inside(&planes, Vec3::new(0.0, 0.0, -5.0)));
+        assert!(inside(&planes, Vec3::new(-2.0, 1.0, -10.0)));
+        assert!(!inside(&planes, Vec3::new(2.5, 0.0, -5.0)));
+        assert!(!inside(&planes, Vec3::new(0.0, -1.5, -5.0)));
+        assert!(!inside(&planes, Vec3::new(0.0, 0.0, 1.0)));
+        assert!(!inside(&planes, Vec3::new(0.0, 0.0, -11.0)));
+    }
+
+    #[test]
+    fn planes_are_normalized() {
+        let view = Mat4::look_at_rh(Vec3::new(3.0, 2.0, 5.0), Vec3::ZERO, Vec3::Y);
+        let projection = Mat4::perspective_rh(60f32.to_radians(), 16.0 / 9.0, 0.5, 50.0);
+        for plane in frustum_planes(projection * view) {
+            let length = Vec4::from_array(plane).truncate().length();
+            assert!((length - 1.0).abs() < 1e-5, "{plane:?}");
+        }
+
+        // the distances are in world units, the spheres are tested against their radius
+        let planes = frustum_planes(Mat4::orthographic_rh(-2.0, 2.0, -1.0, 1.0, 0.0, 10.0));
+        let expected = [2.5, 1.5, 1.0, 1.0, 4.0, 6.0];
+        for (distance, expected) in distances(&planes, Vec3::new(0.5, 0.0, -4.0))
+            .iter()
+            .zip(expected)
+        {
+            assert!(
+                (distance - expected).abs() < 1e-5,
+                "{distance} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn commands_with_the_same_instances_share_the_range() {
+        let mut state = state();
+        // two meshes of a model with the instances 0..3, a model with the instances 5..7
+        let templates = state.set_commands(
+            &[command(0, 0, 3), command(12, 0, 3), command(24, 5, 2)],
+            16,
+        );
+        assert_eq!(
+            templates
+                .iter()
+                .map(|template| (template.first_index, template.range))
+                .collect::<Vec<_>>(),
+            vec![(0, 0), (12, 0), (24, 1)]
+        );
+        assert_eq!((state.ranges, state.commands), (2, 3));
+        assert_eq!(
+            state
+                .instances
+                .iter()
+                .map(|instance| (instance.range, instance.first))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, 0),
+                (0, 0),
+                (0, 0),
+                (NO_RANGE, 0),
+                (NO_RANGE, 0),
+                (1, 5),
+                (1, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn ranges_are_reset_and_clipped_to_the_limit() {
+        let mut state = state();
+        state.set_commands(&[command(0, 0, 4)], 16);
+        state.set_commands(&[command(0, 2, 2), command(3, 6, 4), command(6, 20, 1)], 8);
+        assert_eq!((state.ranges, state.commands), (3, 3));
+        assert_eq!(
+            state
+                .instances
+                .iter()
+                .map(|instance| instance.range)
+                .collect::<Vec<_>>(),
+            vec![NO_RANGE, NO_RANGE, 0, 0, NO_RANGE, NO_RANGE, 1, 1]
+        );
+        // the bounds are kept
+        state.instances[0].sphere.radius = 2.0;
+        state.set_commands(&[], 8);
+        assert_eq!(state.instances.len(), 8);
+        assert_eq!(state.instances[0].sphere.radius, 2.0);
+        assert!(
+            state
+                .instances
+                .iter()
+                .all(|instance| instance.range == NO_RANGE)
+        );
+    }
+
+    #[test]
+    fn culling_shader_is_valid() {
+        let module = naga::front::wgsl::parse_str(include_str!("culling.wgsl"))
+            .expect("Cannot parse the culling shader");
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::empty(),
+        )
+        .validate(&module)
+        .expect("Cannot validate the culling shader");
+
+        let entry_points: Vec<&str> = module
+            .entry_points
+            .iter()
+            .map(|entry_point| entry_point.name.as_str())
+            .collect();
+        assert_eq!(entry_points, ["reset", "cull", "compact"]);
+
+        // the structs of the shader match the structs that are uploaded
+        let span = |name: &str| {
+            module
+                .types
+                .iter()
+                .find_map(|(_, ty)| match &ty.inner {
+                    naga::TypeInner::Struct { span, .. } if ty.name.as_deref() == Some(name) => {
+                        Some(*span as usize)
+                    }
+                    _ => None,
+                })
+                .unwrap_or_else(|| panic!("Missing struct {name}"))
+        };
+        assert_eq!(span("Params"), std::mem::size_of::<CullParams>());
+        assert_eq!(span("CullInstance"), std::mem::size_of::<CullInstance>());
+        assert_eq!(span("Template"), std::mem::size_of::<CommandTemplate>());
+        assert_eq!(span("Args"), std::mem::size_of::<DrawIndexedIndirectArgs>());
+    }
+}
@@ -0,0 +1,112 @@
+// Frustum culling of instances
+// reset: clears the visible count of every instance range and the draw count
+// cull: tests the bounding sphere of every instance and copies the visible ones
+// compact: writes the indirect arguments of every command
+
+struct Params {
+    // left, right, bottom, top, near, far
+    planes: array<vec4<f32>, 6>,
+    instance_count: u32,
+    range_count: u32,
+    command_count: u32,
+    // size of an instance in words
+    stride: u32,
+    // 1 if the empty commands are removed and counted in counts[range_count]
+    compact: u32,
+}
+
+struct CullInstance {
+    // xyz center, w radius
+    sphere: vec4<f32>,
+    // index of the instance range, 0xffffffff if no command draws the instance
+    range: u32,
+    // first instance of the range
+    first: u32,
+    _pad0: u32,
+    _pad1: u32,
+}
+
+struct Args {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+struct Template {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+    range: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read_write> counts: array<atomic<u32>>;
+
+@group(0) @binding(2) var<storage, read> cull_instances: array<CullInstance>;
+@group(0) @binding(3) var<storage, read> source: array<u32>;
+@group(0) @binding(4) var<storage, read_write> instances: array<u32>;
+@group(0) @binding(5) var<storage, read_write> visible: array<u32>;
+
+@group(0) @binding(6) var<storage, read> templates: array<Template>;
+@group(0) @binding(7) var<storage, read_write> commands: array<Args>;
+
+@compute @workgroup_size(64)
+fn reset(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x <= params.range_count {
+        atomicStore(&counts[id.x], 0u);
+    }
+}
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= params.instance_count {
+        return;
+    }
+    let instance = cull_instances[index];
+    if instance.range == 0xffffffffu {
+        return;
+    }
+    let center = instance.sphere.xyz;
+    let radius = instance.sphere.w;
+    for (var i = 0u; i < 6u; i++) {
+        let plane = params.planes[i];
+        if dot(plane.xyz, center) + plane.w < -radius {
+            return;
+        }
+    }
+
+    let slot = instance.first + atomicAdd(&counts[instance.range], 1u);
+    visible[slot] = index;
+    for (var word = 0u; word < params.stride; word++) {
+        instances[slot * params.stride + word] = source[index * params.stride + word];
+    }
+}
+
+@compute @workgroup_size(64)
+fn compact(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= params.command_count {
+        return;
+    }
+    let command = templates[index];
+    let count = atomicLoad(&counts[command.range]);
+    let args = Args(
+        command.index_count,
+        count,
+        command.first_index,
+        command.base_vertex,
+        command.first_instance,
+    );
+    if params.compact == 0u {
+        commands[index] = args;
+        return;
+    }
+    if count > 0u {
+        commands[atomicAdd(&counts[params.range_count], 1u)] = args;
+    }
+}
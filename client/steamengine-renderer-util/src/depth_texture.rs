@@ -1,4 +1,5 @@
 use steamengine_renderer::Renderer;
+use steamengine_renderer::sampler::{SamplerFilter, SamplerPreset};
 use steamengine_renderer::texture::Texture;
 use steamengine_renderer::texture::TextureBuilder;
 use steamengine_renderer::texture::TextureDimensions;
@@ -26,6 +27,10 @@ pub trait DepthTexture {
     fn bias() -> DepthBiasState {
         Default::default()
     }
+    /// Return the sampler preset of the texture, default a shadow sampler with the compare function
+    fn sampler() -> SamplerPreset {
+        SamplerPreset::new(SamplerFilter::Shadow(Self::compare()))
+    }
     /// Return the pipeline config, default, with configurations
     fn pipeline_stencil() -> DepthStencilState {
         DepthStencilState {
@@ -54,29 +59,14 @@ pub trait DepthTexture {
                 ),
         );
         texture.texture_view(wgpu::TextureViewDescriptor::default());
-        texture.texture_sampler(
-            wgpu::wgt::SamplerDescriptor {
-                label: Some("Depth sampler descriptor"),
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Linear,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                lod_min_clamp: 0.0,
-                lod_max_clamp: 100.0,
-                compare: Some(Self::compare()),
-                ..Default::default()
-            },
-            renderer,
-        );
+        texture.sampler_preset(Self::sampler(), renderer);
 
         texture
     }
     /// Create a new depth texture
     fn create(renderer: &Renderer) -> Self;
     /// Return the render pass config
-    fn stencil_attachment(&self) -> wgpu::RenderPassDepthStencilAttachment;
+    fn stencil_attachment(&self) -> wgpu::RenderPassDepthStencilAttachment<'_>;
 }
 /// Function to create a new depth_texture from a renderer
 pub trait RenderPassCreateDepthTexture {
@@ -97,7 +87,7 @@ impl DepthTexture for DefaultDepthTexture {
         let texture = Self::create_texture(renderer);
         Self { texture }
     }
-    fn stencil_attachment(&self) -> wgpu::RenderPassDepthStencilAttachment {
+    fn stencil_attachment(&self) -> wgpu::RenderPassDepthStencilAttachment<'_> {
         let texture = &self.texture;
         wgpu::RenderPassDepthStencilAttachment {
             view: texture
@@ -0,0 +1,277 @@
+use hashbrown::HashMap;
+use steamengine_renderer::instances::{Instance, RawInstance};
+use tracing::*;
+use wgpu::util::DrawIndexedIndirectArgs;
+
+use crate::instance_buffer::InstanceBuffer;
+use crate::resources::Identifier;
+use crate::simple_buffer::SimpleBuffer;
+
+/// Builder of the draw commands of a frame
+/// Submit the instances of every model, `build` sorts them by model, uploads the instances
+/// and one `DrawIndexedIndirectArgs` per mesh, and returns the number of commands
+/// The queue owns the instances from the `first` index of `build` to the end of the instance
+/// buffer and the whole command buffer, they are replaced in every build
+/// ## Example
+/// ```rust,ignore
+/// let (models, commands) = ModelResourceLoader::new().load_to_buffers("resources", renderer.clone())?;
+/// let mut queue = DrawQueueBuilder::new(commands);
+///
+/// queue.draw(&Identifier::parse_from_str("resources/models/tree.obj"), &trees);
+/// queue.draw(&Identifier::parse_from_str("resources/models/rock.obj"), &rocks);
+/// // the instances before 128 are static instances that the queue doesn't change
+/// let count = queue.build(&instances, 128, &draw_queue_buffer);
+///
+/// render_pass.multi_draw_indexed_indirect(draw_queue_buffer.buffer(), 0, count);
+/// ```
+pub struct DrawQueueBuilder<T: RawInstance> {
+    /// commands of every mesh of the models
+    models: HashMap<Identifier, Vec<DrawIndexedIndirectArgs>>,
+    draws: Vec<(Identifier, Vec<T>)>,
+    count: u32,
+    /// models that didn't fit in the buffers in the last build
+    dropped: Vec<Identifier>,
+}
+impl<T: RawInstance> DrawQueueBuilder<T> {
+    /// create a new builder with the commands of the models, Ex: from `ModelResourceLoader::load_to_buffers`
+    pub fn new(models: HashMap<Identifier, Vec<DrawIndexedIndirectArgs>>) -> Self {
+        Self {
+            models,
+            draws: Vec::new(),
+            count: 0,
+            dropped: Vec::new(),
+        }
+    }
+    /// Draws a model with the instances
+    pub fn draw<I: Instance<T>>(&mut self, model: &Identifier, instances: &[I]) -> &mut Self {
+        let raw = instances.iter().map(Instance::to_raw).collect();
+        self.draw_raw(model, raw)
+    }
+    /// Draws a model with the raw instances
+    pub fn draw_raw(&mut self, model: &Identifier, instances: Vec<T>) -> &mut Self {
+        if !self.models.contains_key(model) {
+            warn!("Drawing an unknown model -- {:?}", model);
+            return self;
+        }
+        if !instances.is_empty() {
+            self.draws.push((model.clone(), instances));
+        }
+        self
+    }
+    /// Removes the submitted draws
+    pub fn clear(&mut self) {
+        self.draws.clear();
+    }
+    /// Uploads the instances from the index `first` and the commands of the submitted draws,
+    /// returns the number of commands
+    /// The models that don't fit in the buffers are skipped with all its meshes, see `dropped`
+    /// The submitted draws are removed
+    pub fn build<'a, B: SimpleBuffer<'a, DrawIndexedIndirectArgs>>(
+        &mut self,
+        instances: &InstanceBuffer<'_, T>,
+        first: u64,
+        commands: &B,
+    ) -> u32 {
+        let (raw, args) = self.collect(first, instances.limit(), commands.limit());
+        instances.set_raw_range(first, &raw);
+        instances.truncate(first + raw.len() as u64);
+        instances.flush();
+        commands.set_all(&args);
+        self.count = args.len() as u32;
+        trace!(
+            "Draw queue built with {} commands and {} instances",
+            self.count,
+            raw.len()
+        );
+        self.count
+    }
+    /// Takes the submitted draws and returns the instances from `first` and the commands
+    /// The draws are sorted and merged by model, the models that don't fit in `instance_limit`
+    /// or `command_limit` are added to `dropped`
+    fn collect(
+        &mut self,
+        first: u64,
+        instance_limit: u64,
+        command_limit: u64,
+    ) -> (Vec<T>, Vec<DrawIndexedIndirectArgs>) {
+        self.dropped.clear();
+        let mut draws = std::mem::take(&mut self.draws);
+        draws.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut raw: Vec<T> = Vec::new();
+        let mut args: Vec<DrawIndexedIndirectArgs> = Vec::new();
+        let mut draws = draws.into_iter().peekable();
+        while let Some((model, mut model_instances)) = draws.next() {
+            // merge the draws of the same model
+            while let Some((_, next)) = draws.next_if(|(next, _)| *next == model) {
+                model_instances.extend(next);
+            }
+            let meshes = &self.models[&model];
+            if first + (raw.len() + model_instances.len()) as u64 > instance_limit
+                || (args.len() + meshes.len()) as u64 > command_limit
+            {
+                error!(
+                    "attempt to nest a model outside the limits of the buffers, DrawQueueBuilder Overflow -- {:?}",
+                    model
+                );
+                self.dropped.push(model);
+                continue;
+            }
+            let first_instance = (first + raw.len() as u64) as u32;
+            let instance_count = model_instances.len() as u32;
+            raw.extend(model_instances);
+            args.extend(meshes.iter().map(|mesh| DrawIndexedIndirectArgs {
+                first_instance,
+                instance_count,
+                ..*mesh
+            }));
+        }
+        (raw, args)
+    }
+    /// Number of commands of the last build
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+    /// Models that were skipped in the last build because they didn't fit in the buffers
+    pub fn dropped(&self) -> &[Identifier] {
+        &self.dropped
+    }
+    /// Gets the commands of the meshes of a model
+    pub fn model(&self, model: &Identifier) -> Option<&[DrawIndexedIndirectArgs]> {
+        self.models.get(model).map(Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+    struct Raw(u32);
+    impl RawInstance for Raw {
+        fn desc() -> wgpu::VertexBufferLayout<'static> {
+            wgpu::VertexBufferLayout {
+                array_stride: 4,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &[],
+            }
+        }
+    }
+
+    fn id(path: &str) -> Identifier {
+        Identifier::parse_from_str(path)
+    }
+
+    fn mesh(first_index: u32, index_count: u32) -> DrawIndexedIndirectArgs {
+        DrawIndexedIndirectArgs {
+            index_count,
+            first_index,
+            ..Default::default()
+        }
+    }
+
+    /// `rock` has one mesh and `tree` has two, the trunk and the leaves
+    fn queue() -> DrawQueueBuilder<Raw> {
+        DrawQueueBuilder::new(HashMap::from([
+            (id("models/rock.obj"), vec![mesh(0, 36)]),
+            (id("models/tree.obj"), vec![mesh(36, 24), mesh(60, 90)]),
+        ]))
+    }
+
+    fn raw(values: &[u32]) -> Vec<Raw> {
+        values.iter().copied().map(Raw).collect()
+    }
+
+    /// (first_index, index_count, first_instance, instance_count) of the commands
+    fn commands(args: &[DrawIndexedIndirectArgs]) -> Vec<(u32, u32, u32, u32)> {
+        args.iter()
+            .map(|args| {
+                (
+                    args.first_index,
+                    args.index_count,
+                    args.first_instance,
+                    args.instance_count,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn draws_are_sorted_and_merged_by_model() {
+        let mut queue = queue();
+        queue
+            .draw_raw(&id("models/tree.obj"), raw(&[1, 2]))
+            .draw_raw(&id("models/rock.obj"), raw(&[3]))
+            .draw_raw(&id("models/tree.obj"), raw(&[4]))
+            // unknown models and empty draws are ignored
+            .draw_raw(&id("models/house.obj"), raw(&[5]))
+            .draw_raw(&id("models/rock.obj"), vec![]);
+
+        let (instances, args) = queue.collect(0, 64, 64);
+        assert_eq!(instances, raw(&[3, 1, 2, 4]));
+        assert_eq!(
+            commands(&args),
+            vec![(0, 36, 0, 1), (36, 24, 1, 3), (60, 90, 1, 3)]
+        );
+        assert!(queue.dropped().is_empty());
+        // the draws are taken by the build
+        assert!(queue.collect(0, 64, 64).0.is_empty());
+    }
+
+    #[test]
+    fn first_instance_starts_at_the_base_instance() {
+        let mut queue = queue();
+        queue
+            .draw_raw(&id("models/rock.obj"), raw(&[1, 2]))
+            .draw_raw(&id("models/tree.obj"), raw(&[3]));
+        let (instances, args) = queue.collect(100, 128, 64);
+        assert_eq!(instances.len(), 3);
+        assert_eq!(
+            commands(&args),
+            vec![(0, 36, 100, 2), (36, 24, 102, 1), (60, 90, 102, 1)]
+        );
+    }
+
+    #[test]
+    fn models_that_dont_fit_are_skipped_and_later_ones_kept() {
+        let mut queue = queue();
+        queue
+            .draw_raw(&id("models/a.obj"), raw(&[0]))
+            .draw_raw(&id("models/rock.obj"), raw(&[1, 2, 3]))
+            .draw_raw(&id("models/tree.obj"), raw(&[4, 5, 6, 7, 8]))
+            .draw_raw(&id("models/rock.obj"), raw(&[9]));
+        // the 4 rocks fit, the 5 trees don't fit in the 6 instances after the base
+        let (instances, args) = queue.collect(10, 16, 64);
+        assert_eq!(instances, raw(&[1, 2, 3, 9]));
+        assert_eq!(commands(&args), vec![(0, 36, 10, 4)]);
+        assert_eq!(queue.dropped(), [id("models/tree.obj")]);
+
+        // the tree doesn't fit in the commands, the rock after it is kept
+        let mut queue = DrawQueueBuilder::new(HashMap::from([
+            (id("models/a.obj"), vec![mesh(0, 3)]),
+            (id("models/b.obj"), vec![mesh(3, 3), mesh(6, 3)]),
+            (id("models/c.obj"), vec![mesh(9, 3)]),
+        ]));
+        queue
+            .draw_raw(&id("models/c.obj"), raw(&[3]))
+            .draw_raw(&id("models/b.obj"), raw(&[2]))
+            .draw_raw(&id("models/a.obj"), raw(&[1]));
+        let (instances, args) = queue.collect(0, 64, 2);
+        assert_eq!(instances, raw(&[1, 3]));
+        assert_eq!(commands(&args), vec![(0, 3, 0, 1), (9, 3, 1, 1)]);
+        assert_eq!(queue.dropped(), [id("models/b.obj")]);
+    }
+
+    #[test]
+    fn dropped_models_are_reset_in_every_build() {
+        let mut queue = queue();
+        queue.draw_raw(&id("models/tree.obj"), raw(&[1, 2]));
+        queue.collect(0, 1, 64);
+        assert_eq!(queue.dropped().len(), 1);
+        queue.draw_raw(&id("models/tree.obj"), raw(&[1]));
+        let (_, args) = queue.collect(0, 1, 64);
+        assert_eq!(args.len(), 2);
+        assert!(queue.dropped().is_empty());
+    }
+}
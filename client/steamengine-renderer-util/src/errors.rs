@@ -12,7 +12,34 @@ pub enum Error {
     ImageError(#[from] image::error::ImageError),
     #[error("from UTF8 error")]
     Utf8Error(#[from] std::string::FromUtf8Error),
+    #[cfg(feature = "texture-resource-manager")]
+    #[error("texture error, {0}")]
+    TextureError(#[from] steamengine_renderer::errors::TextureError),
+    #[cfg(feature = "texture-resource-manager")]
+    #[error("the faces of a cubemap must be squares of the same size")]
+    CubemapFaces,
+    #[cfg(feature = "texture-resource-manager")]
+    #[error("an animation needs at least one frame and the frames of an array the same size")]
+    FrameSize,
+    #[cfg(feature = "texture-resource-manager")]
+    #[error("animation without frames, {0}")]
+    NoFrames(String),
+    #[cfg(feature = "compressed-textures")]
+    #[error("invalid texture container, {0}")]
+    Container(&'static str),
+    #[cfg(feature = "compressed-textures")]
+    #[error("texture format {0:?} isn't supported by the adapter")]
+    UnsupportedTextureFormat(wgpu::TextureFormat),
     #[cfg(feature = "model-resource-manager")]
     #[error("model load error")]
     ModelLoadError(#[from] tobj::LoadError),
+    #[cfg(feature = "bindless-textures")]
+    #[error("bindless textures aren't supported, {0}")]
+    BindlessUnsupported(&'static str),
+    #[cfg(feature = "bindless-textures")]
+    #[error("the texture can't be added to the table, {0}")]
+    TableMismatch(&'static str),
+    #[cfg(feature = "bindless-textures")]
+    #[error("the texture table is full, it has {0} slots")]
+    TableFull(u32),
 }
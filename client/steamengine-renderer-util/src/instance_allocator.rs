@@ -0,0 +1,694 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use steamengine_renderer::Renderer;
+use steamengine_renderer::instances::{Instance, RawInstance};
+use tracing::*;
+use wgpu::util::DrawIndexedIndirectArgs;
+
+use super::instance_buffer::InstanceBuffer;
+
+/// Initial capacity of the region of a model
+const INITIAL_CAPACITY: u64 = 8;
+
+/// Stable handle of an instance inside an `InstanceAllocator`
+/// The handle keeps pointing to the instance when it is moved in the buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct InstanceHandle {
+    index: u32,
+    generation: u32,
+}
+
+/// Entry of the handle table
+struct HandleEntry {
+    generation: u32,
+    group: usize,
+    slot: u64,
+    alive: bool,
+}
+
+/// Contiguous region of the buffer with the instances of a model
+struct Group<K> {
+    key: K,
+    start: u64,
+    capacity: u64,
+    /// handle of every instance in the region, the instances are always packed from `start`
+    handles: Vec<u32>,
+}
+
+/// Copy of instances inside the buffer, the source and the destination can overlap
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Move {
+    from: u64,
+    to: u64,
+    len: u64,
+}
+
+/// Regions of the buffer, the instances are copied by the allocator with the moves
+struct AllocatorState<K> {
+    groups: Vec<Group<K>>,
+    keys: HashMap<K, usize>,
+    handles: Vec<HandleEntry>,
+    free_handles: Vec<u32>,
+    /// regions of the buffer that are not used by any group, sorted and merged
+    free_regions: Vec<Range<u64>>,
+    /// end of the used part of the buffer
+    end: u64,
+    limit: u64,
+    /// the compaction continues in the next flushes until the buffer is packed
+    compacting: bool,
+    /// copies of instances that aren't applied to the buffer yet
+    moves: Vec<Move>,
+    /// models whose range moved or was freed since the last `take_moved`
+    moved: Vec<K>,
+}
+impl<K: Hash + Eq + Clone> AllocatorState<K> {
+    fn new(limit: u64) -> Self {
+        Self {
+            groups: Vec::new(),
+            keys: HashMap::new(),
+            handles: Vec::new(),
+            free_handles: Vec::new(),
+            free_regions: Vec::new(),
+            end: 0,
+            limit,
+            compacting: false,
+            moves: Vec::new(),
+            moved: Vec::new(),
+        }
+    }
+    /// Adds an instance of a model, returns the handle and the index of the instance
+    fn insert(&mut self, key: K) -> Option<(InstanceHandle, u64)> {
+        let group = match self.keys.get(&key) {
+            Some(group) => *group,
+            None => {
+                let start = self.allocate(INITIAL_CAPACITY)?;
+                self.groups.push(Group {
+                    key: key.clone(),
+                    start,
+                    capacity: INITIAL_CAPACITY,
+                    handles: Vec::new(),
+                });
+                let group = self.groups.len() - 1;
+                self.keys.insert(key, group);
+                group
+            }
+        };
+        if self.groups[group].handles.len() as u64 == self.groups[group].capacity {
+            self.grow(group)?;
+        }
+
+        let slot = self.groups[group].handles.len() as u64;
+        let index = match self.free_handles.pop() {
+            Some(index) => {
+                let entry = &mut self.handles[index as usize];
+                entry.group = group;
+                entry.slot = slot;
+                entry.alive = true;
+                index
+            }
+            None => {
+                self.handles.push(HandleEntry {
+                    generation: 0,
+                    group,
+                    slot,
+                    alive: true,
+                });
+                self.handles.len() as u32 - 1
+            }
+        };
+        self.groups[group].handles.push(index);
+        let handle = InstanceHandle {
+            index,
+            generation: self.handles[index as usize].generation,
+        };
+        Some((handle, self.groups[group].start + slot))
+    }
+    /// Removes an instance, the last instance of the model takes its slot and the region of the
+    /// model is freed with its last instance
+    fn remove(&mut self, handle: InstanceHandle) -> bool {
+        let Some(entry) = self.entry(handle) else {
+            return false;
+        };
+        let (group, slot) = (entry.group, entry.slot);
+        let start = self.groups[group].start;
+        let last = self.groups[group].handles.len() as u64 - 1;
+        if slot != last {
+            let moved = self.groups[group].handles[last as usize];
+            self.moves.push(Move {
+                from: start + last,
+                to: start + slot,
+                len: 1,
+            });
+            self.groups[group].handles[slot as usize] = moved;
+            self.handles[moved as usize].slot = slot;
+        }
+        self.groups[group].handles.pop();
+
+        let entry = &mut self.handles[handle.index as usize];
+        entry.alive = false;
+        entry.generation = entry.generation.wrapping_add(1);
+        self.free_handles.push(handle.index);
+        if self.groups[group].handles.is_empty() {
+            self.free_group(group);
+        }
+        true
+    }
+    fn entry(&self, handle: InstanceHandle) -> Option<&HandleEntry> {
+        self.handles
+            .get(handle.index as usize)
+            .filter(|entry| entry.alive && entry.generation == handle.generation)
+    }
+    fn index(&self, handle: InstanceHandle) -> Option<u64> {
+        self.entry(handle)
+            .map(|entry| self.groups[entry.group].start + entry.slot)
+    }
+    fn range(&self, key: &K) -> Option<Range<u32>> {
+        let group = &self.groups[*self.keys.get(key)?];
+        let start = group.start as u32;
+        Some(start..start + group.handles.len() as u32)
+    }
+    /// Fraction of the used part of the buffer that can be reclaimed, the holes between the
+    /// groups and the capacity of the groups that a compaction would shrink
+    fn fragmentation(&self) -> f32 {
+        if self.end == 0 {
+            return 0.0;
+        }
+        let holes: u64 = self
+            .free_regions
+            .iter()
+            .map(|region| region.end - region.start)
+            .sum();
+        let slack: u64 = self.groups.iter().map(Group::slack).sum();
+        (holes + slack) as f32 / self.end as f32
+    }
+    /// Finds a free region of the buffer, compacts the buffer until the region fits
+    fn allocate(&mut self, capacity: u64) -> Option<u64> {
+        loop {
+            if let Some(start) = self.take_region(capacity) {
+                return Some(start);
+            }
+            if !self.compact_step() {
+                error!(
+                    "attempt to nest an instance outside the limits of the buffer, InstanceAllocator Overflow"
+                );
+                return None;
+            }
+        }
+    }
+    fn take_region(&mut self, capacity: u64) -> Option<u64> {
+        if let Some(index) = self
+            .free_regions
+            .iter()
+            .position(|region| region.end - region.start >= capacity)
+        {
+            let region = &mut self.free_regions[index];
+            let start = region.start;
+            region.start += capacity;
+            if region.start == region.end {
+                self.free_regions.remove(index);
+            }
+            return Some(start);
+        }
+        if self.end + capacity <= self.limit {
+            let start = self.end;
+            self.end += capacity;
+            return Some(start);
+        }
+        None
+    }
+    /// Doubles the capacity of a full group, in place if the group is followed by the end of the
+    /// buffer or by a hole that fits, otherwise the group is moved to a new region
+    fn grow(&mut self, group: usize) -> Option<()> {
+        let (start, capacity) = (self.groups[group].start, self.groups[group].capacity);
+        let next = start + capacity;
+        if next == self.end && self.end + capacity <= self.limit {
+            self.end += capacity;
+            self.groups[group].capacity *= 2;
+            return Some(());
+        }
+        if let Some(index) = self
+            .free_regions
+            .iter()
+            .position(|region| region.start == next && region.end - region.start >= capacity)
+        {
+            let region = &mut self.free_regions[index];
+            region.start += capacity;
+            if region.start == region.end {
+                self.free_regions.remove(index);
+            }
+            self.groups[group].capacity *= 2;
+            return Some(());
+        }
+        let new_start = self.allocate(capacity * 2)?;
+        // the compaction may have moved the group
+        let (start, capacity, len) = (
+            self.groups[group].start,
+            self.groups[group].capacity,
+            self.groups[group].handles.len() as u64,
+        );
+        trace!(
+            "Moving instance group from {} to {} with capacity {}",
+            start,
+            new_start,
+            capacity * 2
+        );
+        self.moves.push(Move {
+            from: start,
+            to: new_start,
+            len,
+        });
+        self.groups[group].start = new_start;
+        self.groups[group].capacity = capacity * 2;
+        self.release(start..start + capacity);
+        self.mark_moved(group);
+        Some(())
+    }
+    /// Removes a group without instances and releases its region
+    fn free_group(&mut self, group: usize) {
+        let removed = self.groups.swap_remove(group);
+        trace!(
+            "Freeing instance group at {} with capacity {}",
+            removed.start, removed.capacity
+        );
+        self.keys.remove(&removed.key);
+        self.release(removed.start..removed.start + removed.capacity);
+        // the last group takes the index of the removed one
+        if let Some(swapped) = self.groups.get(group) {
+            self.keys.insert(swapped.key.clone(), group);
+            for handle in &swapped.handles {
+                self.handles[*handle as usize].group = group;
+            }
+        }
+        if !self.moved.contains(&removed.key) {
+            self.moved.push(removed.key);
+        }
+    }
+    /// Marks a region as free, the region is merged with the holes next to it
+    fn release(&mut self, region: Range<u64>) {
+        if region.is_empty() {
+            return;
+        }
+        let mut index = self
+            .free_regions
+            .partition_point(|free| free.start < region.start);
+        self.free_regions.insert(index, region);
+        if index + 1 < self.free_regions.len()
+            && self.free_regions[index].end == self.free_regions[index + 1].start
+        {
+            self.free_regions[index].end = self.free_regions.remove(index + 1).end;
+        }
+        if index > 0 && self.free_regions[index - 1].end == self.free_regions[index].start {
+            self.free_regions[index - 1].end = self.free_regions.remove(index).end;
+            index -= 1;
+        }
+        if self.free_regions[index].end == self.end {
+            self.end = self.free_regions.remove(index).start;
+        }
+    }
+    /// Moves the group after the first hole to the start of the hole, or shrinks a group with
+    /// slack if there aren't holes, returns false if the buffer is packed
+    fn compact_step(&mut self) -> bool {
+        if let Some(hole) = self.free_regions.first().cloned() {
+            // the holes are merged and never touch the end, so a group starts after every hole
+            let group = self
+                .groups
+                .iter()
+                .position(|group| group.start == hole.end)
+                .expect("A group after every hole");
+            let (start, capacity) = (self.groups[group].start, self.groups[group].capacity);
+            let len = self.groups[group].handles.len() as u64;
+            let tight = tight_capacity(len);
+            self.free_regions.remove(0);
+            self.moves.push(Move {
+                from: start,
+                to: hole.start,
+                len,
+            });
+            self.groups[group].start = hole.start;
+            self.groups[group].capacity = tight;
+            self.release(hole.start + tight..start + capacity);
+            self.mark_moved(group);
+            return true;
+        }
+        if let Some(group) = self.groups.iter().position(|group| group.slack() > 0) {
+            let group = &mut self.groups[group];
+            let (start, capacity) = (group.start, group.capacity);
+            group.capacity = tight_capacity(group.handles.len() as u64);
+            let end = start + group.capacity;
+            self.release(end..start + capacity);
+            return true;
+        }
+        false
+    }
+    /// Compacts at most `budget` groups, returns true if the buffer is packed
+    fn compact_groups(&mut self, budget: usize) -> bool {
+        for _ in 0..budget {
+            if !self.compact_step() {
+                return true;
+            }
+        }
+        false
+    }
+    fn mark_moved(&mut self, group: usize) {
+        let key = &self.groups[group].key;
+        if !self.moved.contains(key) {
+            self.moved.push(key.clone());
+        }
+    }
+}
+impl<K> Group<K> {
+    /// capacity that a compaction would reclaim
+    fn slack(&self) -> u64 {
+        self.capacity - tight_capacity(self.handles.len() as u64)
+    }
+}
+
+/// capacity of a group after a compaction
+fn tight_capacity(len: u64) -> u64 {
+    len.next_power_of_two().max(INITIAL_CAPACITY)
+}
+
+/// Allocator of instances grouped by model
+/// Every model owns a contiguous region of the instance buffer, so its instances can be drawn
+/// with a single `DrawIndexedIndirectArgs`. Removed instances are replaced by the last instance
+/// of the group, the handles are recycled and the region of a model is freed with its last
+/// instance. When a group grows its old region becomes a hole, the buffer is compacted in the
+/// next flushes when the holes and the unused capacity exceed the compaction threshold, moving a
+/// few groups per flush. The models that moved are returned by `take_moved`
+/// ## Example
+/// ```rust,ignore
+/// let instances = InstanceAllocator::<Identifier, RawInstance>::new(renderer.clone(), 1024);
+/// let tree = instances.insert(Identifier::parse_from_str("models/tree.obj"), &entity);
+/// instances.update(tree, &moved_entity);
+/// instances.flush();
+///
+/// for model in instances.take_moved() {
+///     commands.set(command_of[&model], instances.draw_args(&model, model_args[&model]));
+/// }
+/// ```
+pub struct InstanceAllocator<'a, K: Hash + Eq + Clone, T: RawInstance> {
+    buffer: InstanceBuffer<'a, T>,
+    state: Mutex<AllocatorState<K>>,
+    compaction_threshold: f32,
+    compaction_budget: usize,
+}
+impl<'a, K: Hash + Eq + Clone, T: RawInstance> InstanceAllocator<'a, K, T> {
+    /// create a new allocator with limit of instances inside the buffer
+    pub fn new(renderer: Arc<Renderer<'a>>, limit: u64) -> Self {
+        Self {
+            buffer: InstanceBuffer::new(renderer, limit),
+            state: Mutex::new(AllocatorState::new(limit)),
+            compaction_threshold: 0.25,
+            compaction_budget: 4,
+        }
+    }
+    /// sets the fraction of holes and unused capacity in the buffer that starts a compaction,
+    /// default 0.25
+    pub fn with_compaction_threshold(mut self, threshold: f32) -> Self {
+        self.compaction_threshold = threshold;
+        self
+    }
+    /// sets the number of groups that a flush compacts, default 4
+    pub fn with_compaction_budget(mut self, groups: usize) -> Self {
+        self.compaction_budget = groups.max(1);
+        self
+    }
+    /// Adds an instance of a model, returns None if the buffer is full
+    /// compacts the buffer only until the new instance fits
+    pub fn insert<I: Instance<T>>(&self, key: K, instance: &I) -> Option<InstanceHandle> {
+        let mut state = self.state.lock().expect("Cannot lock instance allocator");
+        let inserted = state.insert(key);
+        self.apply(&mut state);
+        let (handle, index) = inserted?;
+        self.buffer.set(index, instance);
+        Some(handle)
+    }
+    /// Updates the instance of a handle, returns false if the handle was removed
+    pub fn update<I: Instance<T>>(&self, handle: InstanceHandle, instance: &I) -> bool {
+        let state = self.state.lock().expect("Cannot lock instance allocator");
+        let Some(index) = state.index(handle) else {
+            return false;
+        };
+        self.buffer.set(index, instance);
+        true
+    }
+    /// Removes the instance of a handle, the last instance of the model takes its slot
+    pub fn remove(&self, handle: InstanceHandle) -> bool {
+        let mut state = self.state.lock().expect("Cannot lock instance allocator");
+        let removed = state.remove(handle);
+        self.apply(&mut state);
+        removed
+    }
+    /// returns true if the handle points to an instance
+    pub fn contains(&self, handle: InstanceHandle) -> bool {
+        let state = self.state.lock().expect("Cannot lock instance allocator");
+        state.entry(handle).is_some()
+    }
+    /// Gets the index of the instance in the buffer, it changes when the buffer is compacted
+    pub fn index(&self, handle: InstanceHandle) -> Option<u32> {
+        let state = self.state.lock().expect("Cannot lock instance allocator");
+        state.index(handle).map(|index| index as u32)
+    }
+    /// Gets the range of instances of a model
+    pub fn range(&self, key: &K) -> Option<Range<u32>> {
+        let state = self.state.lock().expect("Cannot lock instance allocator");
+        state.range(key)
+    }
+    /// Gets the ranges of instances of all the models
+    pub fn ranges(&self) -> Vec<(K, Range<u32>)> {
+        let state = self.state.lock().expect("Cannot lock instance allocator");
+        state
+            .groups
+            .iter()
+            .map(|group| {
+                let start = group.start as u32;
+                (group.key.clone(), start..start + group.handles.len() as u32)
+            })
+            .collect()
+    }
+    /// Gets the models whose range moved in the buffer or that were freed since the last call
+    /// Ex: to update the `first_instance` of the draw commands
+    pub fn take_moved(&self) -> Vec<K> {
+        let mut state = self.state.lock().expect("Cannot lock instance allocator");
+        std::mem::take(&mut state.moved)
+    }
+    /// Sets `first_instance` and `instance_count` of the draw of a model
+    pub fn draw_args(&self, key: &K, args: DrawIndexedIndirectArgs) -> DrawIndexedIndirectArgs {
+        let range = self.range(key).unwrap_or(0..0);
+        DrawIndexedIndirectArgs {
+            first_instance: range.start,
+            instance_count: range.end - range.start,
+            ..args
+        }
+    }
+    /// Fraction of the used part of the buffer that is a hole between groups or capacity that a
+    /// compaction would reclaim
+    pub fn fragmentation(&self) -> f32 {
+        let state = self.state.lock().expect("Cannot lock instance allocator");
+        state.fragmentation()
+    }
+    /// Uploads the instances that changed, if the buffer is too fragmented it compacts a few
+    /// groups, the compaction continues in the next flushes until the buffer is packed
+    pub fn flush(&self) {
+        {
+            let mut state = self.state.lock().expect("Cannot lock instance allocator");
+            if !state.compacting && state.fragmentation() > self.compaction_threshold {
+                trace!("Starting instance buffer compaction");
+                state.compacting = true;
+            }
+            if state.compacting && state.compact_groups(self.compaction_budget) {
+                trace!("Instance buffer compacted");
+                state.compacting = false;
+            }
+            self.apply(&mut state);
+        }
+        self.buffer.flush();
+    }
+    /// Moves all the groups to the start of the buffer, removing the holes between them
+    /// The handles are still valid but the ranges of the models change
+    pub fn compact(&self) {
+        let mut state = self.state.lock().expect("Cannot lock instance allocator");
+        trace!("Compacting instance buffer");
+        while state.compact_step() {}
+        state.compacting = false;
+        self.apply(&mut state);
+    }
+    /// Gets the instance buffer
+    pub fn buffer(&self) -> &InstanceBuffer<'a, T> {
+        &self.buffer
+    }
+
+    /// Copies the instances that were moved in the state
+    fn apply(&self, state: &mut AllocatorState<K>) {
+        for Move { from, to, len } in state.moves.drain(..) {
+            let raw: Vec<T> = (from..from + len)
+                .filter_map(|index| self.buffer.get(index))
+                .collect();
+            self.buffer.set_raw_range(to, &raw);
+        }
+        if self.buffer.len() > state.end {
+            self.buffer.truncate(state.end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(state: &mut AllocatorState<&'static str>, key: &'static str, count: usize) {
+        for _ in 0..count {
+            state.insert(key).unwrap();
+        }
+    }
+
+    #[test]
+    fn removed_handles_are_reused_with_a_new_generation() {
+        let mut state = AllocatorState::new(64);
+        let (first, index) = state.insert("tree").unwrap();
+        let (second, _) = state.insert("tree").unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(state.range(&"tree"), Some(0..2));
+
+        assert!(state.remove(first));
+        assert!(!state.remove(first));
+        assert!(state.entry(first).is_none());
+        // the last instance takes the slot of the removed one
+        assert_eq!(state.index(second), Some(0));
+        assert_eq!(
+            state.moves,
+            vec![Move {
+                from: 1,
+                to: 0,
+                len: 1
+            }]
+        );
+
+        let (third, _) = state.insert("tree").unwrap();
+        assert_eq!(third.index, first.index);
+        assert_ne!(third.generation, first.generation);
+        assert!(state.entry(third).is_some());
+        assert!(state.entry(first).is_none());
+    }
+
+    #[test]
+    fn groups_grow_in_place_at_the_end() {
+        let mut state = AllocatorState::new(64);
+        insert(&mut state, "tree", 9);
+        assert_eq!(state.groups[0].capacity, 16);
+        assert_eq!(state.end, 16);
+        assert!(state.moves.is_empty());
+        assert!(state.moved.is_empty());
+    }
+
+    #[test]
+    fn groups_move_when_they_cant_grow_in_place() {
+        let mut state = AllocatorState::new(64);
+        insert(&mut state, "tree", 8);
+        insert(&mut state, "rock", 1);
+        insert(&mut state, "tree", 1);
+        assert_eq!(state.range(&"tree"), Some(16..25));
+        assert_eq!(state.range(&"rock"), Some(8..9));
+        assert_eq!(
+            state.moves,
+            vec![Move {
+                from: 0,
+                to: 16,
+                len: 8
+            }]
+        );
+        assert_eq!(state.free_regions, vec![0..8]);
+        assert_eq!(state.moved, vec!["tree"]);
+    }
+
+    #[test]
+    fn empty_groups_are_freed() {
+        let mut state = AllocatorState::new(64);
+        let (tree, _) = state.insert("tree").unwrap();
+        let (rock, _) = state.insert("rock").unwrap();
+        assert!(state.remove(tree));
+        assert_eq!(state.range(&"tree"), None);
+        assert_eq!(state.free_regions, vec![0..8]);
+        // the rock group took the index of the tree group
+        assert_eq!(state.index(rock), Some(8));
+
+        assert!(state.remove(rock));
+        assert!(state.groups.is_empty());
+        assert!(state.free_regions.is_empty());
+        assert_eq!(state.end, 0);
+        assert_eq!(state.moved, vec!["tree", "rock"]);
+    }
+
+    #[test]
+    fn fragmentation_counts_holes_and_slack() {
+        let mut state = AllocatorState::new(64);
+        insert(&mut state, "tree", 16);
+        let handles: Vec<_> = (0..12).map(|_| state.insert("rock").unwrap().0).collect();
+        assert_eq!(state.fragmentation(), 0.0);
+        for handle in &handles[1..] {
+            state.remove(*handle);
+        }
+        // the rock group keeps 16 slots for 1 instance
+        assert_eq!(state.fragmentation(), 8.0 / 32.0);
+    }
+
+    #[test]
+    fn compaction_moves_a_bounded_number_of_groups() {
+        let mut state = AllocatorState::new(128);
+        insert(&mut state, "a", 8);
+        insert(&mut state, "b", 1);
+        insert(&mut state, "c", 1);
+        insert(&mut state, "d", 1);
+        // a moves after d and leaves a hole at the start
+        insert(&mut state, "a", 1);
+        state.moves.clear();
+        state.moved.clear();
+        assert_eq!(state.free_regions, vec![0..8]);
+
+        assert!(!state.compact_groups(2));
+        assert_eq!(state.range(&"b"), Some(0..1));
+        assert_eq!(state.range(&"c"), Some(8..9));
+        assert_eq!(state.range(&"d"), Some(24..25));
+        assert_eq!(state.moved, vec!["b", "c"]);
+
+        assert!(!state.compact_groups(2));
+        assert!(state.compact_groups(2));
+        assert_eq!(state.range(&"d"), Some(16..17));
+        assert_eq!(state.range(&"a"), Some(24..33));
+        assert!(state.free_regions.is_empty());
+        assert_eq!(state.end, 40);
+        assert_eq!(state.fragmentation(), 0.0);
+        assert_eq!(
+            state.moves.first(),
+            Some(&Move {
+                from: 8,
+                to: 0,
+                len: 1
+            })
+        );
+    }
+
+    #[test]
+    fn allocation_compacts_until_the_group_fits() {
+        let mut state = AllocatorState::new(40);
+        let (a, _) = state.insert("a").unwrap();
+        insert(&mut state, "b", 8);
+        insert(&mut state, "c", 1);
+        insert(&mut state, "d", 1);
+        state.remove(a);
+        assert_eq!(state.free_regions, vec![0..8]);
+        // b doesn't fit in the hole or after the end until c and d are moved
+        insert(&mut state, "b", 1);
+        assert_eq!(state.range(&"c"), Some(8..9));
+        assert_eq!(state.range(&"d"), Some(16..17));
+        assert_eq!(state.range(&"b"), Some(24..33));
+        assert_eq!(state.free_regions, vec![0..8]);
+        assert_eq!(state.end, 40);
+
+        insert(&mut state, "e", 1);
+        assert_eq!(state.range(&"e"), Some(0..1));
+        assert!(state.insert("f").is_none());
+    }
+}
@@ -0,0 +1,274 @@
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use steamengine_renderer::Renderer;
+use steamengine_renderer::instances::{Instance, RawInstance};
+use tracing::*;
+use wgpu::{Buffer, BufferUsages, VertexBufferLayout};
+
+/// Instances stored in the CPU and the ranges that are not uploaded yet
+struct InstanceState<T: RawInstance> {
+    raw: Vec<T>,
+    dirty: Vec<Range<u64>>,
+}
+impl<T: RawInstance> InstanceState<T> {
+    fn new() -> Self {
+        Self {
+            raw: Vec::new(),
+            dirty: Vec::new(),
+        }
+    }
+    /// copies the instances from `first` and marks them as dirty, the gap before `first` is
+    /// filled with zeroed instances
+    fn set(&mut self, first: u64, raw: &[T]) {
+        let end = first + raw.len() as u64;
+        if self.raw.len() < end as usize {
+            self.raw.resize(end as usize, T::zeroed());
+        }
+        self.raw[first as usize..end as usize].copy_from_slice(raw);
+        self.dirty.push(first..end);
+    }
+    /// removes the instances after `len` and the dirty ranges that pointed to them
+    fn truncate(&mut self, len: u64) {
+        self.raw.truncate(len as usize);
+        self.dirty.retain_mut(|range| {
+            range.end = range.end.min(len);
+            range.start < range.end
+        });
+    }
+    /// takes the dirty ranges, sorted and with the overlapping and adjacent ranges merged
+    fn take_dirty(&mut self) -> Vec<Range<u64>> {
+        let mut dirty = std::mem::take(&mut self.dirty);
+        dirty.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(dirty.len());
+        for range in dirty {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+}
+
+/// Vertex buffer of instances
+/// The instances are converted with `Instance::to_raw` and kept in the CPU,
+/// `flush` only uploads the ranges that changed
+/// ## Example
+/// ```rust
+/// use std::sync::Arc;
+///
+/// use steamengine_renderer::Renderer;
+/// use steamengine_renderer::instances::{Instance, RawInstance};
+/// use steamengine_renderer_util::instance_buffer::InstanceBuffer;
+///
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, RawInstance)]
+/// struct RawEntity {
+///     position: [f32; 3],
+/// }
+/// struct Entity {
+///     position: [f32; 3],
+/// }
+/// impl Instance<RawEntity> for Entity {
+///     fn to_raw(&self) -> RawEntity {
+///         RawEntity { position: self.position }
+///     }
+/// }
+///
+/// fn draw(renderer: Arc<Renderer<'static>>, render_pass: &mut wgpu::RenderPass, entity: &Entity) {
+///     let instances = InstanceBuffer::<RawEntity>::new(renderer, 1024);
+///     instances.set(0, entity);
+///     instances.flush();
+///
+///     render_pass.set_vertex_buffer(1, instances.slice());
+/// }
+/// ```
+pub struct InstanceBuffer<'a, T: RawInstance> {
+    /// Wgpu Buffer
+    buffer: Buffer,
+    /// Renderer
+    renderer: Arc<Renderer<'a>>,
+    /// Limit of the buffer
+    limit: u64,
+    state: Mutex<InstanceState<T>>,
+}
+impl<'a, T: RawInstance> InstanceBuffer<'a, T> {
+    /// create a new buffer with limit of instances inside the buffer
+    pub fn new(renderer: Arc<Renderer<'a>>, limit: u64) -> Self {
+        let mut usage = BufferUsages::VERTEX | BufferUsages::COPY_DST;
+        // the instances can be read by compute passes, Ex: GPU culling
+        if renderer
+            .downlevel_flags()
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            usage |= BufferUsages::STORAGE;
+        }
+        let buffer = renderer.create_buffer(
+            "Instance Buffer",
+            usage,
+            limit * std::mem::size_of::<T>() as u64,
+        );
+        Self {
+            buffer,
+            renderer,
+            limit,
+            state: Mutex::new(InstanceState::new()),
+        }
+    }
+    /// Sets an instance, it is uploaded in the next flush
+    pub fn set<I: Instance<T>>(&self, index: u64, instance: &I) {
+        self.set_raw(index, instance.to_raw());
+    }
+    /// Sets the instances from an index, they are uploaded in the next flush
+    pub fn set_range<I: Instance<T>>(&self, first: u64, instances: &[I]) {
+        let raw: Vec<T> = instances.iter().map(Instance::to_raw).collect();
+        self.set_raw_range(first, &raw);
+    }
+    /// Sets a raw instance, it is uploaded in the next flush
+    pub fn set_raw(&self, index: u64, raw: T) {
+        self.set_raw_range(index, &[raw]);
+    }
+    /// Sets raw instances from an index, they are uploaded in the next flush
+    pub fn set_raw_range(&self, first: u64, raw: &[T]) {
+        let end = first + raw.len() as u64;
+        if end > self.limit {
+            error!(
+                "attempt to nest an instance outside the limits of the buffer, InstanceBuffer Overflow"
+            );
+            return;
+        }
+        self.state
+            .lock()
+            .expect("Cannot lock instances")
+            .set(first, raw);
+    }
+    /// Gets the raw instance of an index
+    pub fn get(&self, index: u64) -> Option<T> {
+        self.state
+            .lock()
+            .expect("Cannot lock instances")
+            .raw
+            .get(index as usize)
+            .copied()
+    }
+    /// Number of instances, from 0 to the last one set
+    pub fn len(&self) -> u64 {
+        self.state.lock().expect("Cannot lock instances").raw.len() as u64
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Removes the instances after `len`
+    pub fn truncate(&self, len: u64) {
+        self.state
+            .lock()
+            .expect("Cannot lock instances")
+            .truncate(len);
+    }
+    /// returns true if there are instances that are not uploaded
+    pub fn is_dirty(&self) -> bool {
+        !self
+            .state
+            .lock()
+            .expect("Cannot lock instances")
+            .dirty
+            .is_empty()
+    }
+    /// Uploads the ranges that changed since the last flush
+    /// The overlapping and adjacent ranges are merged in a single write
+    pub fn flush(&self) {
+        let mut state = self.state.lock().expect("Cannot lock instances");
+        let size = std::mem::size_of::<T>() as u64;
+        for range in state.take_dirty() {
+            trace!("Uploading instances {:?}", range);
+            self.renderer.queue().write_buffer(
+                &self.buffer,
+                range.start * size,
+                bytemuck::cast_slice(&state.raw[range.start as usize..range.end as usize]),
+            );
+        }
+    }
+    /// Gets the layout of the instances for the pipelines
+    pub fn desc() -> VertexBufferLayout<'static> {
+        T::desc()
+    }
+    /// Gets the slice of the instances for `set_vertex_buffer`
+    pub fn slice(&self) -> wgpu::BufferSlice<'_> {
+        self.buffer.slice(..)
+    }
+    /// Gets the buffer
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+    pub fn renderer(&self) -> Arc<Renderer<'a>> {
+        self.renderer.clone()
+    }
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+    struct Raw(u32);
+    impl RawInstance for Raw {
+        fn desc() -> VertexBufferLayout<'static> {
+            VertexBufferLayout {
+                array_stride: 4,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &[],
+            }
+        }
+    }
+
+    fn raw(values: &[u32]) -> Vec<Raw> {
+        values.iter().copied().map(Raw).collect()
+    }
+
+    #[test]
+    fn gaps_before_the_first_instance_are_zeroed() {
+        let mut state = InstanceState::new();
+        state.set(3, &raw(&[7, 8]));
+        assert_eq!(state.raw, raw(&[0, 0, 0, 7, 8]));
+        state.set(1, &raw(&[5]));
+        assert_eq!(state.raw, raw(&[0, 5, 0, 7, 8]));
+        assert_eq!(state.take_dirty(), vec![1..2, 3..5]);
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_ranges_are_merged() {
+        let mut state = InstanceState::new();
+        state.set(10, &raw(&[1, 2, 3]));
+        state.set(0, &raw(&[1, 2]));
+        // adjacent to 0..2
+        state.set(2, &raw(&[3]));
+        // inside 10..13
+        state.set(11, &raw(&[4]));
+        // overlaps the end of 10..13
+        state.set(12, &raw(&[5, 6]));
+        state.set(20, &raw(&[1]));
+        assert_eq!(state.take_dirty(), vec![0..3, 10..14, 20..21]);
+        assert!(state.take_dirty().is_empty());
+        assert_eq!(state.raw[10..14], raw(&[1, 4, 5, 6]));
+    }
+
+    #[test]
+    fn truncate_clips_the_dirty_ranges() {
+        let mut state = InstanceState::new();
+        state.set(0, &raw(&[1, 2, 3, 4, 5, 6]));
+        state.set(8, &raw(&[9]));
+        state.truncate(4);
+        assert_eq!(state.raw, raw(&[1, 2, 3, 4]));
+        assert_eq!(state.take_dirty(), vec![0..4]);
+
+        state.set(2, &raw(&[7, 8]));
+        state.truncate(2);
+        assert!(state.take_dirty().is_empty());
+        state.truncate(0);
+        assert!(state.raw.is_empty());
+    }
+}
@@ -16,6 +16,42 @@ pub mod resources;
 #[cfg(feature = "simple-buffers")]
 pub mod simple_buffer;
 
+/// Module with instance buffers
+/// Upload the instances that changed
+/// Enable it with feature "instance-buffers"
+#[cfg(feature = "instance-buffers")]
+pub mod instance_buffer;
+
+/// Module with an allocator of instances
+/// Stable handles and contiguous instances per model
+/// Enable it with feature "instance-buffers"
+#[cfg(feature = "instance-buffers")]
+pub mod instance_allocator;
+
+/// Module with a draw queue builder
+/// Generate the draw commands from the models and its instances
+/// Enable it with feature "draw-queue"
+#[cfg(feature = "draw-queue")]
+pub mod draw_queue;
+
+/// Module with GPU frustum culling
+/// Cull the instances in a compute pass and write the draw commands
+/// Enable it with feature "gpu-culling"
+#[cfg(feature = "gpu-culling")]
+pub mod culling;
+
+/// Module with a ring of uniform buffers
+/// Share a bind group between objects with dynamic offsets
+/// Enable it with feature "uniform-ring"
+#[cfg(feature = "uniform-ring")]
+pub mod uniform_ring;
+
+/// Module with bindless textures
+/// Bind all the textures in a single binding array
+/// Enable it with feature "bindless-textures"
+#[cfg(feature = "bindless-textures")]
+pub mod bindless;
+
 /// Module with bindings
 /// Group a bind_group and layout into a single structure
 /// Enable it with feature "simple-bindings"
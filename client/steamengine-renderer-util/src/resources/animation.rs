@@ -0,0 +1,357 @@
+use super::ResourceLoader;
+use super::texture::{TextureBounds, TextureResourceLoader};
+use crate::errors::Error;
+use image::{AnimationDecoder, DynamicImage, ImageFormat, RgbaImage};
+use std::io::Cursor;
+use std::path::Path;
+use std::time::Duration;
+use steamengine_renderer::Renderer;
+use steamengine_renderer::import::ImportOptions;
+use steamengine_renderer::sampler::SamplerPreset;
+use steamengine_renderer::texture::{Texture, TextureBuilder, TextureDimensions};
+use tracing::*;
+
+/// Time of the frames without a delay, Ex: frame folders or GIFs with a delay of 0
+pub const DEFAULT_FRAME_TIME: Duration = Duration::from_millis(100);
+/// Pixels between the frames of the atlas, so the filter doesn't sample the next frame
+const PADDING: u32 = 2;
+
+/// Time that every frame is shown
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameTiming {
+    pub delays: Vec<Duration>,
+    /// the animation starts again after the last frame, otherwise it stays in the last frame
+    pub looping: bool,
+}
+impl FrameTiming {
+    /// create a looping timing with a delay per frame
+    pub fn new(delays: Vec<Duration>) -> Self {
+        Self {
+            delays,
+            looping: true,
+        }
+    }
+    /// create a looping timing where every frame has the same delay
+    pub fn uniform(frames: usize, frame_time: Duration) -> Self {
+        Self::new(vec![frame_time; frames])
+    }
+    /// the animation stays in the last frame
+    pub fn once(mut self) -> Self {
+        self.looping = false;
+        self
+    }
+    /// Time of a pass through all the frames
+    pub fn duration(&self) -> Duration {
+        self.delays.iter().sum()
+    }
+    /// Gets the frame shown at a time since the start of the animation
+    /// ## Example
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use steamengine_renderer_util::resources::animation::FrameTiming;
+    /// let timing = FrameTiming::uniform(4, Duration::from_millis(100));
+    /// assert_eq!(timing.frame_at(Duration::from_millis(250)), 2);
+    /// assert_eq!(timing.frame_at(Duration::from_millis(450)), 0);
+    /// assert_eq!(timing.once().frame_at(Duration::from_millis(450)), 3);
+    /// ```
+    pub fn frame_at(&self, time: Duration) -> usize {
+        let duration = self.duration();
+        if duration.is_zero() {
+            return 0;
+        }
+        let time = match self.looping {
+            true => Duration::from_nanos((time.as_nanos() % duration.as_nanos()) as u64),
+            false if time >= duration => return self.delays.len() - 1,
+            false => time,
+        };
+        let mut end = Duration::ZERO;
+        for (frame, delay) in self.delays.iter().enumerate() {
+            end += *delay;
+            if time < end {
+                return frame;
+            }
+        }
+        self.delays.len() - 1
+    }
+}
+
+/// Decoded frames of an animation, Ex: an animated GIF or a numbered frame folder
+pub struct AnimationFrames {
+    pub frames: Vec<RgbaImage>,
+    pub timing: FrameTiming,
+    /// format of the textures, `ImportOptions::format` of the animation
+    pub format: wgpu::TextureFormat,
+}
+impl AnimationFrames {
+    /// Packs the frames in a grid inside an atlas, returns the atlas and an animator of the bounds
+    /// of the frames, returns an error if there aren't frames
+    pub fn into_atlas(
+        self,
+        renderer: &Renderer,
+        preset: SamplerPreset,
+    ) -> Result<(crate::bindings::Bindings, SpriteAnimator), Error> {
+        if self.frames.is_empty() {
+            return Err(Error::FrameSize);
+        }
+        let columns = (self.frames.len() as f32).sqrt().ceil().max(1.0) as u32;
+        let cell_width = self.frames.iter().map(|frame| frame.width()).max();
+        let cell_height = self.frames.iter().map(|frame| frame.height()).max();
+        let (cell_width, cell_height) = (
+            cell_width.unwrap_or(1) + PADDING,
+            cell_height.unwrap_or(1) + PADDING,
+        );
+        let rows = (self.frames.len() as u32).div_ceil(columns).max(1);
+        let (width, height) = (columns * cell_width, rows * cell_height);
+        trace!(
+            "Packing {} frames in an atlas {}x{}",
+            self.frames.len(),
+            width,
+            height
+        );
+
+        let mut atlas = vec![0u8; width as usize * height as usize * 4];
+        let mut bounds = Vec::with_capacity(self.frames.len());
+        for (index, frame) in self.frames.iter().enumerate() {
+            let x = index as u32 % columns * cell_width;
+            let y = index as u32 / columns * cell_height;
+            let row = (frame.width() * 4) as usize;
+            for (line, pixels) in frame.as_raw().chunks(row).enumerate() {
+                let start = ((y as usize + line) * width as usize + x as usize) * 4;
+                atlas[start..start + row].copy_from_slice(pixels);
+            }
+            bounds.push(TextureBounds {
+                uv_offset: [x as f32 / width as f32, y as f32 / height as f32],
+                uv_scale: [
+                    frame.width() as f32 / width as f32,
+                    frame.height() as f32 / height as f32,
+                ],
+            });
+        }
+
+        let mut builder = TextureBuilder::new();
+        if preset.uses_mipmaps() {
+            builder = builder.generate_mipmaps();
+        }
+        let mut texture = renderer.init_texture(
+            "Animation Atlas",
+            None,
+            builder
+                .data(atlas)
+                .format(self.format)
+                .dimension(TextureDimensions::new_2d(width, height)),
+        );
+        texture.texture_view(wgpu::TextureViewDescriptor::default());
+        texture.sampler_preset(preset, renderer);
+        let (layout, bind) = texture.default_bind_group("Animation Atlas Bind Group", renderer);
+
+        let animator = SpriteAnimator {
+            frames: bounds,
+            timing: self.timing,
+        };
+        Ok((crate::bindings::Bindings::new(bind, layout), animator))
+    }
+    /// Uploads the frames as the layers of a texture array, the shader samples the layer of
+    /// `FrameTiming::frame_at`, all the frames must have the same size
+    pub fn into_array(
+        self,
+        renderer: &Renderer,
+        preset: SamplerPreset,
+    ) -> Result<(Texture, FrameTiming), Error> {
+        let first = self.frames.first().ok_or(Error::FrameSize)?;
+        let (width, height) = first.dimensions();
+        if self
+            .frames
+            .iter()
+            .any(|frame| frame.dimensions() != (width, height))
+        {
+            return Err(Error::FrameSize);
+        }
+        let layers = self.frames.len() as u32;
+        let mut builder = TextureBuilder::new();
+        if preset.uses_mipmaps() {
+            builder = builder.generate_mipmaps();
+        }
+        let mut texture = renderer.try_init_texture(
+            "Animation Array",
+            None,
+            builder
+                .layers(self.frames.into_iter().map(RgbaImage::into_raw).collect())
+                .format(self.format)
+                .dimension(TextureDimensions::new_2d_array(width, height, layers)),
+        )?;
+        texture.texture_view(wgpu::TextureViewDescriptor::default());
+        texture.sampler_preset(preset, renderer);
+        Ok((texture, self.timing))
+    }
+}
+
+/// Gets the bounds of the current frame inside an atlas, Ex: the uv of instanced sprites
+/// ## Example
+/// ```rust,ignore
+/// let (atlas, animator) = loader.load_animation("assets/sprites/walk.gif")?.into_atlas(&renderer, SamplerPreset::PIXEL_ART)?;
+///
+/// for sprite in &mut sprites {
+///     let bounds = animator.bounds_at(now - sprite.started);
+///     sprite.instance.uv_offset = bounds.uv_offset;
+///     sprite.instance.uv_scale = bounds.uv_scale;
+/// }
+/// ```
+pub struct SpriteAnimator {
+    pub frames: Vec<TextureBounds>,
+    pub timing: FrameTiming,
+}
+impl SpriteAnimator {
+    /// Gets the bounds of the frame shown at a time since the start of the animation
+    /// an animator without frames shows the whole texture
+    pub fn bounds_at(&self, time: Duration) -> TextureBounds {
+        self.frames
+            .get(self.timing.frame_at(time))
+            .copied()
+            .unwrap_or(TextureBounds {
+                uv_offset: [0.0, 0.0],
+                uv_scale: [1.0, 1.0],
+            })
+    }
+    pub fn frame_at(&self, time: Duration) -> usize {
+        self.timing.frame_at(time)
+    }
+    pub fn duration(&self) -> Duration {
+        self.timing.duration()
+    }
+}
+
+impl TextureResourceLoader {
+    /// load the frames of an animated GIF or PNG, or the numbered images of a folder
+    /// Ex: `walk/walk_0.png`, `walk/walk_1.png`, the frames of a folder are shown
+    /// `DEFAULT_FRAME_TIME`, the import options of the path are applied to every frame
+    /// returns an error if the animation doesn't have frames
+    pub fn load_animation(&self, path: &str) -> Result<AnimationFrames, Error> {
+        let options = self.import_options(path)?;
+        let mut animation = match Path::new(path).is_dir() {
+            true => self.load_frame_folder(path)?,
+            false => decode_animation(&std::fs::read(path)?)?,
+        };
+        if animation.frames.is_empty() {
+            return Err(Error::NoFrames(path.to_owned()));
+        }
+        animation.format = options.format();
+        animation.frames = animation
+            .frames
+            .into_iter()
+            .map(|frame| options.apply(DynamicImage::ImageRgba8(frame)))
+            .collect();
+        debug!(
+            "Loaded animation with {} frames -- {}",
+            animation.frames.len(),
+            path
+        );
+        Ok(animation)
+    }
+    fn load_frame_folder(&self, path: &str) -> Result<AnimationFrames, Error> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?.path();
+            let entry = entry.to_string_lossy();
+            if !self.accepts(&entry) {
+                continue;
+            }
+            match frame_number(&entry) {
+                Some(number) => entries.push((number, entry.into_owned())),
+                None => warn!("Skipping a file without a frame number -- {}", entry),
+            }
+        }
+        if entries.is_empty() {
+            return Err(Error::NoFrames(path.to_owned()));
+        }
+        entries.sort();
+        let frames = entries
+            .iter()
+            .map(|(_, entry)| Ok(self.load_from_path(entry)?.to_rgba8()))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let timing = FrameTiming::uniform(frames.len(), DEFAULT_FRAME_TIME);
+        Ok(AnimationFrames {
+            frames,
+            timing,
+            format: ImportOptions::new().format(),
+        })
+    }
+}
+
+/// Number at the end of the name of a frame, Ex: 12 in `walk_12.png`
+fn frame_number(path: &str) -> Option<u32> {
+    let name = Path::new(path).file_stem()?.to_str()?;
+    let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    name[name.len() - digits..].parse().ok()
+}
+
+/// Decodes the frames of an animated image, the images that aren't animated have one frame
+fn decode_animation(bytes: &[u8]) -> Result<AnimationFrames, Error> {
+    match image::guess_format(bytes)? {
+        #[cfg(feature = "gif")]
+        ImageFormat::Gif => frames_of(image::codecs::gif::GifDecoder::new(Cursor::new(bytes))?),
+        ImageFormat::Png => {
+            let decoder = image::codecs::png::PngDecoder::new(Cursor::new(bytes))?;
+            match decoder.is_apng()? {
+                true => frames_of(decoder.apng()?),
+                false => single_frame(bytes),
+            }
+        }
+        _ => single_frame(bytes),
+    }
+}
+
+fn frames_of<'a>(decoder: impl AnimationDecoder<'a>) -> Result<AnimationFrames, Error> {
+    let mut frames = Vec::new();
+    let mut delays = Vec::new();
+    for frame in decoder.into_frames() {
+        let frame = frame?;
+        let delay = Duration::from(frame.delay());
+        // browsers show the frames with a very small delay at the default speed
+        delays.push(match delay < Duration::from_millis(20) {
+            true => DEFAULT_FRAME_TIME,
+            false => delay,
+        });
+        frames.push(frame.into_buffer());
+    }
+    Ok(AnimationFrames {
+        frames,
+        timing: FrameTiming::new(delays),
+        format: ImportOptions::new().format(),
+    })
+}
+
+fn single_frame(bytes: &[u8]) -> Result<AnimationFrames, Error> {
+    let frame = image::load_from_memory(bytes)?.to_rgba8();
+    Ok(AnimationFrames {
+        frames: vec![frame],
+        timing: FrameTiming::uniform(1, DEFAULT_FRAME_TIME),
+        format: ImportOptions::new().format(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_without_frames_is_an_error() {
+        let folder = std::env::temp_dir().join("steamengine-empty-animation");
+        std::fs::create_dir_all(&folder).unwrap();
+        let result = TextureResourceLoader::new().load_animation(&folder.to_string_lossy());
+        std::fs::remove_dir_all(&folder).unwrap();
+        assert!(matches!(result, Err(Error::NoFrames(_))));
+    }
+
+    #[test]
+    fn animator_without_frames_shows_the_whole_texture() {
+        let animator = SpriteAnimator {
+            frames: Vec::new(),
+            timing: FrameTiming::new(Vec::new()),
+        };
+        let bounds = animator.bounds_at(Duration::from_millis(250));
+        assert_eq!(
+            (bounds.uv_offset, bounds.uv_scale),
+            ([0.0, 0.0], [1.0, 1.0])
+        );
+    }
+}
@@ -0,0 +1,1247 @@
+use crate::errors::Error;
+use wgpu::TextureFormat;
+
+/// bits of the number of symbols of a Huffman table
+const MAX_SYMBOLS_BITS: u32 = 14;
+const MAX_CODE_SIZE: usize = 16;
+/// code length codes in the order of the table headers
+const CODE_LENGTH_ORDER: [usize; 21] = [
+    17, 18, 19, 20, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15, 16,
+];
+/// symbol of the endpoint predictions that repeats the last one
+const REPEAT_LAST_PREDICTION: u32 = 256;
+const MIN_PREDICTION_REPEAT: u32 = 3;
+/// minimum run of the selector history, the last symbol of the run model reads a longer run
+const MIN_SELECTOR_RUN: usize = 3;
+const SELECTOR_RUN_SYMBOLS: u32 = 64;
+/// the 5 bit colors of the endpoints use a delta model depending on the previous color
+const COLOR_MODEL_0_MAX: u8 = 9;
+const COLOR_MODEL_1_MAX: u8 = 21;
+/// image flag of the P frames of the videos
+const P_FRAME: u32 = 2;
+
+/// modifiers of the ETC1 intensity tables, from the lowest to the highest
+const INTENSITIES: [[i32; 4]; 8] = [
+    [-8, -2, 2, 8],
+    [-17, -5, 5, 17],
+    [-29, -9, 9, 29],
+    [-42, -13, 13, 42],
+    [-60, -18, 18, 60],
+    [-80, -24, 24, 80],
+    [-106, -33, 33, 106],
+    [-183, -47, 47, 183],
+];
+/// ETC1 pixel index of the selectors, the selectors go from the lowest modifier to the highest
+const ETC1_INDICES: [u8; 4] = [3, 2, 0, 1];
+/// modifiers of the EAC alpha blocks of ETC2
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+/// interpolation weights of the BC7 indices
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn invalid() -> Error {
+    Error::Container("invalid BasisLZ data")
+}
+
+/// Little endian reader of the bits of the BasisLZ streams, the bits after the end are zeros
+struct Bits<'a> {
+    data: &'a [u8],
+    offset: usize,
+    buffer: u64,
+    len: u32,
+}
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            offset: 0,
+            buffer: 0,
+            len: 0,
+        }
+    }
+    /// reads up to 32 bits
+    fn read(&mut self, count: u32) -> u32 {
+        while self.len < count {
+            let byte = self.data.get(self.offset).copied().unwrap_or(0);
+            self.offset += 1;
+            self.buffer |= (byte as u64) << self.len;
+            self.len += 8;
+        }
+        let value = (self.buffer & ((1u64 << count) - 1)) as u32;
+        self.buffer >>= count;
+        self.len -= count;
+        value
+    }
+    /// reads a number in chunks of `bits` bits, every chunk is followed by a bit that is set if
+    /// there is another chunk
+    fn vlc(&mut self, bits: u32) -> Result<u32, Error> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let chunk = self.read(bits + 1);
+            value |= (chunk & ((1 << bits) - 1)) << shift;
+            if chunk & (1 << bits) == 0 {
+                return Ok(value);
+            }
+            shift += bits;
+            if shift >= 32 {
+                return Err(invalid());
+            }
+        }
+    }
+    /// reads a Huffman table, the code sizes are compressed with a table of code length codes
+    fn table(&mut self) -> Result<Huffman, Error> {
+        let total = self.read(MAX_SYMBOLS_BITS) as usize;
+        if total == 0 {
+            return Huffman::new(&[]);
+        }
+        let mut code_length_sizes = [0u8; 21];
+        let count = self.read(5) as usize;
+        if count == 0 || count > CODE_LENGTH_ORDER.len() {
+            return Err(invalid());
+        }
+        for code in &CODE_LENGTH_ORDER[..count] {
+            code_length_sizes[*code] = self.read(3) as u8;
+        }
+        let code_lengths = Huffman::new(&code_length_sizes)?;
+
+        let mut sizes = vec![0u8; total];
+        let mut symbol = 0;
+        while symbol < total {
+            let code = self.decode(&code_lengths)?;
+            let (value, run) = match code {
+                0..=16 => (code as u8, 1),
+                // runs of zeros
+                17 => (0, self.read(3) as usize + 3),
+                18 => (0, self.read(7) as usize + 11),
+                // repeats of the last size
+                _ => {
+                    let run = match code {
+                        19 => self.read(2) as usize + 3,
+                        _ => self.read(7) as usize + 7,
+                    };
+                    match symbol.checked_sub(1).map(|last| sizes[last]) {
+                        Some(last) if last != 0 => (last, run),
+                        _ => return Err(invalid()),
+                    }
+                }
+            };
+            if symbol + run > total {
+                return Err(invalid());
+            }
+            sizes[symbol..symbol + run].fill(value);
+            symbol += run;
+        }
+        Huffman::new(&sizes)
+    }
+    fn decode(&mut self, table: &Huffman) -> Result<u32, Error> {
+        table.decode(self)
+    }
+}
+
+/// Canonical Huffman table, the codes are sorted by size and symbol
+struct Huffman {
+    /// number of codes of every size
+    counts: [u16; MAX_CODE_SIZE + 1],
+    /// symbols sorted by the size of the code
+    symbols: Vec<u16>,
+}
+impl Huffman {
+    fn new(sizes: &[u8]) -> Result<Self, Error> {
+        let mut counts = [0u16; MAX_CODE_SIZE + 1];
+        for size in sizes {
+            *counts.get_mut(*size as usize).ok_or_else(invalid)? += 1;
+        }
+        counts[0] = 0;
+        // the codes can't use more than the space of the sizes
+        let mut left = 1i32;
+        for count in &counts[1..] {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(invalid());
+            }
+        }
+        let mut offsets = [0usize; MAX_CODE_SIZE + 2];
+        for size in 1..=MAX_CODE_SIZE {
+            offsets[size + 1] = offsets[size] + counts[size] as usize;
+        }
+        let mut symbols = vec![0; offsets[MAX_CODE_SIZE + 1]];
+        for (symbol, size) in sizes.iter().enumerate() {
+            if *size != 0 {
+                symbols[offsets[*size as usize]] = symbol as u16;
+                offsets[*size as usize] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+    fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+    /// reads a code, the first bit is the highest bit of the code
+    fn decode(&self, bits: &mut Bits) -> Result<u32, Error> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for count in &self.counts[1..] {
+            code |= bits.read(1) as i32;
+            let count = *count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as u32);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid())
+    }
+}
+
+/// Approximate move to front of the last selectors, the used selectors move halfway to the front
+struct SelectorHistory {
+    values: Vec<usize>,
+    next: usize,
+}
+impl SelectorHistory {
+    fn new(len: usize) -> Self {
+        Self {
+            values: vec![0; len],
+            next: len / 2,
+        }
+    }
+    fn add(&mut self, selector: usize) {
+        self.values[self.next] = selector;
+        self.next += 1;
+        if self.next == self.values.len() {
+            self.next = self.values.len() / 2;
+        }
+    }
+    fn take(&mut self, index: usize) -> Option<usize> {
+        let selector = *self.values.get(index)?;
+        self.values.swap(index / 2, index);
+        Some(selector)
+    }
+}
+
+/// Color and intensity table of an ETC1S block, the color has 5 bits per channel
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Endpoint {
+    color: [u8; 3],
+    intensity: u8,
+}
+impl Endpoint {
+    /// the 4 colors of the selectors
+    fn palette(&self) -> [[u8; 3]; 4] {
+        let base = self.color.map(|channel| (channel << 3) | (channel >> 2));
+        INTENSITIES[self.intensity as usize]
+            .map(|modifier| base.map(|channel| (channel as i32 + modifier).clamp(0, 255) as u8))
+    }
+}
+
+/// Selectors of the pixels of a block, row by row
+type Selector = [u8; 16];
+
+/// Block of a slice as the endpoint and the selector of the codebooks
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Block {
+    endpoint: usize,
+    selector: usize,
+}
+
+/// Offsets of the slices of an image inside the data of its level
+struct ImageDesc {
+    flags: u32,
+    rgb: (usize, usize),
+    alpha: (usize, usize),
+}
+
+/// Global data of the BasisLZ supercompression of KTX2, the codebooks and Huffman tables of the
+/// ETC1S slices and the slices of every image
+pub(crate) struct BasisLz {
+    endpoints: Vec<Endpoint>,
+    selectors: Vec<Selector>,
+    endpoint_predictions: Huffman,
+    endpoint_deltas: Huffman,
+    selector_symbols: Huffman,
+    selector_runs: Huffman,
+    history_size: usize,
+    images: Vec<ImageDesc>,
+}
+impl BasisLz {
+    /// reads the global data with the descriptions of `image_count` images
+    pub(crate) fn new(global: &[u8], image_count: usize) -> Result<Self, Error> {
+        let u16_at = |offset: usize| -> Result<usize, Error> {
+            let bytes = global.get(offset..offset + 2).ok_or_else(invalid)?;
+            Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+        };
+        let u32_at = |offset: usize| -> Result<usize, Error> {
+            let bytes = global.get(offset..offset + 4).ok_or_else(invalid)?;
+            Ok(u32::from_le_bytes(bytes.try_into().expect("4 bytes read")) as usize)
+        };
+        let endpoint_count = u16_at(0)?;
+        let selector_count = u16_at(2)?;
+        let lengths = [u32_at(4)?, u32_at(8)?, u32_at(12)?];
+        let images = (0..image_count)
+            .map(|image| {
+                let offset = 20 + image * 20;
+                Ok(ImageDesc {
+                    flags: u32_at(offset)? as u32,
+                    rgb: (u32_at(offset + 4)?, u32_at(offset + 8)?),
+                    alpha: (u32_at(offset + 12)?, u32_at(offset + 16)?),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut offset = 20 + image_count * 20;
+        let [endpoints, selectors, tables] = lengths.map(|len| {
+            let data = offset
+                .checked_add(len)
+                .and_then(|end| global.get(offset..end));
+            offset = offset.saturating_add(len);
+            data
+        });
+        let (Some(endpoints), Some(selectors), Some(tables)) = (endpoints, selectors, tables)
+        else {
+            return Err(Error::Container("BasisLZ global data is truncated"));
+        };
+        if endpoint_count == 0 || selector_count == 0 {
+            return Err(invalid());
+        }
+
+        let mut bits = Bits::new(tables);
+        let endpoint_predictions = bits.table()?;
+        let endpoint_deltas = bits.table()?;
+        let selector_symbols = bits.table()?;
+        let selector_runs = bits.table()?;
+        let history_size = bits.read(13) as usize;
+        if endpoint_predictions.is_empty()
+            || endpoint_deltas.is_empty()
+            || selector_symbols.is_empty()
+            || selector_runs.is_empty()
+            || history_size == 0
+        {
+            return Err(invalid());
+        }
+        Ok(Self {
+            endpoints: decode_endpoints(endpoints, endpoint_count)?,
+            selectors: decode_selectors(selectors, selector_count)?,
+            endpoint_predictions,
+            endpoint_deltas,
+            selector_symbols,
+            selector_runs,
+            history_size,
+            images,
+        })
+    }
+    /// transcodes an image of `width` x `height` pixels from the data of its level
+    /// the block formats have a block per 4x4 pixels, the alpha is 255 without an alpha slice
+    pub(crate) fn transcode(
+        &self,
+        image: usize,
+        level: &[u8],
+        (width, height): (u32, u32),
+        format: TextureFormat,
+    ) -> Result<Vec<u8>, Error> {
+        let desc = self.images.get(image).ok_or_else(invalid)?;
+        if desc.flags & P_FRAME != 0 {
+            return Err(Error::Container("BasisLZ videos aren't supported"));
+        }
+        let slice = |(offset, len): (usize, usize)| {
+            offset
+                .checked_add(len)
+                .and_then(|end| level.get(offset..end))
+                .ok_or(Error::Container("BasisLZ slice outside of the level"))
+        };
+        let (blocks_x, blocks_y) = (width.div_ceil(4) as usize, height.div_ceil(4) as usize);
+        let rgb = self.decode_slice(slice(desc.rgb)?, blocks_x, blocks_y)?;
+        let alpha = match desc.alpha.1 {
+            0 => None,
+            _ => Some(self.decode_slice(slice(desc.alpha)?, blocks_x, blocks_y)?),
+        };
+
+        let mut data = Vec::new();
+        let stride = width as usize * 4;
+        if format.block_dimensions() == (1, 1) {
+            data.resize(stride * height as usize, 0);
+        }
+        for (index, block) in rgb.iter().enumerate() {
+            let pixels = self.pixels(*block, alpha.as_ref().map(|alpha| alpha[index]));
+            match format.remove_srgb_suffix() {
+                TextureFormat::Etc2Rgb8Unorm => data.extend(self.etc1(*block)),
+                TextureFormat::Etc2Rgba8Unorm => {
+                    data.extend(eac(&pixels.map(|pixel| pixel[3])));
+                    data.extend(self.etc1(*block));
+                }
+                TextureFormat::Bc7RgbaUnorm => data.extend(match alpha {
+                    Some(_) => bc7_mode5(&pixels),
+                    None => bc7_mode6(&pixels),
+                }),
+                _ => {
+                    // the blocks at the edges are cropped
+                    let (x, y) = (index % blocks_x * 4, index / blocks_x * 4);
+                    for row in 0..4.min(height as usize - y) {
+                        for column in 0..4.min(width as usize - x) {
+                            let offset = (y + row) * stride + (x + column) * 4;
+                            data[offset..offset + 4].copy_from_slice(&pixels[row * 4 + column]);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(data)
+    }
+    /// decodes the endpoint and selector of every block of a slice
+    fn decode_slice(
+        &self,
+        data: &[u8],
+        blocks_x: usize,
+        blocks_y: usize,
+    ) -> Result<Vec<Block>, Error> {
+        let mut bits = Bits::new(data);
+        let (endpoint_count, selector_count) = (self.endpoints.len(), self.selectors.len());
+        let run_symbol = (selector_count + self.history_size) as u32;
+        let mut history = SelectorHistory::new(self.history_size);
+        let mut run = 0;
+        let mut prediction_repeat = 0;
+        let mut last_prediction = 0;
+        let mut prediction = 0;
+        let mut last_endpoint = 0;
+        // endpoints of the last row and predictions of the odd rows
+        let mut rows = [
+            vec![(0usize, 0u32); blocks_x],
+            vec![(0usize, 0u32); blocks_x],
+        ];
+
+        let mut blocks = Vec::with_capacity(blocks_x * blocks_y);
+        for y in 0..blocks_y {
+            let row = y & 1;
+            for x in 0..blocks_x {
+                // a symbol has the predictions of 2x2 blocks
+                if x & 1 == 0 {
+                    if row == 0 {
+                        if prediction_repeat > 0 {
+                            prediction_repeat -= 1;
+                            prediction = last_prediction;
+                        } else {
+                            prediction = bits.decode(&self.endpoint_predictions)?;
+                            if prediction == REPEAT_LAST_PREDICTION {
+                                prediction_repeat = bits.vlc(4)? + MIN_PREDICTION_REPEAT - 1;
+                                prediction = last_prediction;
+                            } else {
+                                last_prediction = prediction;
+                            }
+                        }
+                        rows[1][x].1 = prediction >> 4;
+                    } else {
+                        prediction = rows[1][x].1;
+                    }
+                }
+                let endpoint = match prediction & 3 {
+                    // left
+                    0 if x > 0 => last_endpoint,
+                    // upper
+                    1 if y > 0 => rows[row ^ 1][x].0,
+                    // upper left
+                    2 if x > 0 && y > 0 => rows[row ^ 1][x - 1].0,
+                    3 => {
+                        let endpoint = bits.decode(&self.endpoint_deltas)? as usize + last_endpoint;
+                        match endpoint >= endpoint_count {
+                            true => endpoint - endpoint_count,
+                            false => endpoint,
+                        }
+                    }
+                    _ => return Err(invalid()),
+                };
+                prediction >>= 2;
+                if endpoint >= endpoint_count {
+                    return Err(invalid());
+                }
+                rows[row][x].0 = endpoint;
+                last_endpoint = endpoint;
+
+                let symbol = match run {
+                    0 => {
+                        let symbol = bits.decode(&self.selector_symbols)?;
+                        if symbol == run_symbol {
+                            let symbol = bits.decode(&self.selector_runs)?;
+                            run = match symbol == SELECTOR_RUN_SYMBOLS - 1 {
+                                true => bits.vlc(7)? as usize + MIN_SELECTOR_RUN,
+                                false => symbol as usize + MIN_SELECTOR_RUN,
+                            };
+                            if run > blocks_x * blocks_y {
+                                return Err(invalid());
+                            }
+                            run -= 1;
+                            selector_count
+                        } else {
+                            symbol as usize
+                        }
+                    }
+                    _ => {
+                        run -= 1;
+                        selector_count
+                    }
+                };
+                let selector = match symbol.checked_sub(selector_count) {
+                    Some(index) => history.take(index).ok_or_else(invalid)?,
+                    None => {
+                        history.add(symbol);
+                        symbol
+                    }
+                };
+                if selector >= selector_count {
+                    return Err(invalid());
+                }
+                blocks.push(Block { endpoint, selector });
+            }
+        }
+        Ok(blocks)
+    }
+    /// RGBA of the pixels of a block row by row, the alpha is the green of the alpha block
+    fn pixels(&self, block: Block, alpha: Option<Block>) -> [[u8; 4]; 16] {
+        let palette = self.endpoints[block.endpoint].palette();
+        let selectors = &self.selectors[block.selector];
+        let alpha = alpha.map(|alpha| {
+            let palette = self.endpoints[alpha.endpoint].palette();
+            self.selectors[alpha.selector].map(|selector| palette[selector as usize][1])
+        });
+        std::array::from_fn(|pixel| {
+            let [r, g, b] = palette[selectors[pixel] as usize];
+            [r, g, b, alpha.map_or(255, |alpha| alpha[pixel])]
+        })
+    }
+    /// ETC1 block in differential mode with the same color and table in both halves
+    fn etc1(&self, block: Block) -> [u8; 8] {
+        let endpoint = self.endpoints[block.endpoint];
+        let selectors = &self.selectors[block.selector];
+        let [r, g, b] = endpoint.color;
+        let intensity = endpoint.intensity;
+        // the pixels are sorted by column, the high bits of the indices before the low bits
+        let (mut high, mut low) = (0u16, 0u16);
+        for (pixel, selector) in selectors.iter().enumerate() {
+            let index = ETC1_INDICES[*selector as usize] as u16;
+            let bit = pixel % 4 * 4 + pixel / 4;
+            high |= (index >> 1) << bit;
+            low |= (index & 1) << bit;
+        }
+        let [high_0, high_1] = high.to_be_bytes();
+        let [low_0, low_1] = low.to_be_bytes();
+        [
+            r << 3,
+            g << 3,
+            b << 3,
+            (intensity << 5) | (intensity << 2) | 0b10,
+            high_0,
+            high_1,
+            low_0,
+            low_1,
+        ]
+    }
+}
+
+/// decodes the codebook of endpoints, the colors and intensities are deltas of the last one
+fn decode_endpoints(data: &[u8], count: usize) -> Result<Vec<Endpoint>, Error> {
+    let mut bits = Bits::new(data);
+    let color_models = [bits.table()?, bits.table()?, bits.table()?];
+    let intensity_model = bits.table()?;
+    let grayscale = bits.read(1) == 1;
+    let mut last_color = [16u8; 3];
+    let mut last_intensity = 0;
+    (0..count)
+        .map(|_| {
+            let intensity = (bits.decode(&intensity_model)? + last_intensity) & 7;
+            last_intensity = intensity;
+            let channels = if grayscale { 1 } else { 3 };
+            for channel in &mut last_color[..channels] {
+                let model = if *channel <= COLOR_MODEL_0_MAX {
+                    &color_models[0]
+                } else if *channel <= COLOR_MODEL_1_MAX {
+                    &color_models[1]
+                } else {
+                    &color_models[2]
+                };
+                *channel = ((bits.decode(model)? + *channel as u32) & 31) as u8;
+            }
+            if grayscale {
+                last_color = [last_color[0]; 3];
+            }
+            Ok(Endpoint {
+                color: last_color,
+                intensity: intensity as u8,
+            })
+        })
+        .collect()
+}
+
+/// decodes the codebook of selectors, raw or as the xor of the rows of the last one
+fn decode_selectors(data: &[u8], count: usize) -> Result<Vec<Selector>, Error> {
+    let mut bits = Bits::new(data);
+    if bits.read(1) == 1 {
+        return Err(Error::Container(
+            "BasisLZ global selector codebooks aren't supported",
+        ));
+    }
+    if bits.read(1) == 1 {
+        return Err(Error::Container(
+            "BasisLZ hybrid selector codebooks aren't supported",
+        ));
+    }
+    let raw = bits.read(1) == 1;
+    let deltas = match raw {
+        true => None,
+        false => Some(bits.table()?),
+    };
+    let mut last = [0u8; 4];
+    (0..count)
+        .map(|index| {
+            let mut selector = [0u8; 16];
+            for (row, last) in last.iter_mut().enumerate() {
+                let byte = match &deltas {
+                    Some(deltas) if index > 0 => bits.decode(deltas)? as u8 ^ *last,
+                    _ => bits.read(8) as u8,
+                };
+                *last = byte;
+                for column in 0..4 {
+                    selector[row * 4 + column] = (byte >> (column * 2)) & 3;
+                }
+            }
+            Ok(selector)
+        })
+        .collect()
+}
+
+/// EAC alpha block of ETC2, the table, multiplier and base with the smallest error
+fn eac(alpha: &[u8; 16]) -> [u8; 8] {
+    let min = *alpha.iter().min().expect("16 pixels") as i32;
+    let max = *alpha.iter().max().expect("16 pixels") as i32;
+    let mut best = (u32::MAX, [0u8; 8]);
+    for (table, modifiers) in EAC_MODIFIERS.iter().enumerate() {
+        for multiplier in 1..16 {
+            // the base centers the range of the table in the range of the block
+            let base = ((min + max) - (modifiers[3] + modifiers[7]) * multiplier).div_euclid(2);
+            let base = base.clamp(0, 255);
+            let mut error = 0;
+            let mut indices = 0u64;
+            for (pixel, value) in alpha.iter().enumerate() {
+                let (index, distance) = modifiers
+                    .iter()
+                    .map(|modifier| (base + modifier * multiplier).clamp(0, 255))
+                    .map(|decoded| (decoded - *value as i32).unsigned_abs())
+                    .enumerate()
+                    .min_by_key(|(_, distance)| *distance)
+                    .expect("8 modifiers");
+                error += distance * distance;
+                // the pixels are sorted by column, the first one in the highest bits
+                let position = pixel % 4 * 4 + pixel / 4;
+                indices |= (index as u64) << (45 - position * 3);
+            }
+            if error < best.0 {
+                let indices = indices.to_be_bytes();
+                best = (
+                    error,
+                    [
+                        base as u8,
+                        ((multiplier as u8) << 4) | table as u8,
+                        indices[2],
+                        indices[3],
+                        indices[4],
+                        indices[5],
+                        indices[6],
+                        indices[7],
+                    ],
+                );
+            }
+        }
+    }
+    best.1
+}
+
+/// the darkest and the brightest pixels, the ETC1S colors are on a line
+fn color_range(pixels: &[[u8; 4]; 16]) -> ([u8; 3], [u8; 3]) {
+    let luma = |pixel: &&[u8; 4]| pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32;
+    let low = pixels.iter().min_by_key(luma).expect("16 pixels");
+    let high = pixels.iter().max_by_key(luma).expect("16 pixels");
+    ([low[0], low[1], low[2]], [high[0], high[1], high[2]])
+}
+
+/// index of the nearest interpolated value of the endpoints
+fn nearest<const N: usize>(weights: &[u32; N], low: &[u8], high: &[u8], value: &[u8]) -> u32 {
+    (0..N as u32)
+        .min_by_key(|index| {
+            let weight = weights[*index as usize];
+            low.iter()
+                .zip(high)
+                .zip(value)
+                .map(|((low, high), value)| {
+                    let decoded = ((64 - weight) * *low as u32 + weight * *high as u32 + 32) >> 6;
+                    decoded.abs_diff(*value as u32).pow(2)
+                })
+                .sum::<u32>()
+        })
+        .expect("at least one weight")
+}
+
+/// Writer of the bits of a BC7 block, the first field in the lowest bits
+struct Bc7Block {
+    bits: u128,
+    len: u32,
+}
+impl Bc7Block {
+    fn new(mode: u32) -> Self {
+        let mut block = Self { bits: 0, len: 0 };
+        block.write(1 << mode, mode + 1);
+        block
+    }
+    fn write(&mut self, value: u32, bits: u32) {
+        self.bits |= (value as u128) << self.len;
+        self.len += bits;
+    }
+    /// writes the indices, the first one is an anchor without its highest bit
+    fn indices(&mut self, indices: &[u32; 16], bits: u32) {
+        for (pixel, index) in indices.iter().enumerate() {
+            self.write(*index, if pixel == 0 { bits - 1 } else { bits });
+        }
+    }
+    fn finish(self) -> [u8; 16] {
+        debug_assert_eq!(self.len, 128);
+        self.bits.to_le_bytes()
+    }
+}
+
+/// indices of the pixels, the endpoints are swapped if the first index has the highest bit set
+fn anchored_indices<const N: usize>(
+    weights: &[u32; N],
+    low: &mut [u8],
+    high: &mut [u8],
+    values: &[&[u8]; 16],
+) -> [u32; 16] {
+    let indices = values.map(|value| nearest(weights, low, high, value));
+    match indices[0] >= N as u32 / 2 {
+        true => {
+            low.swap_with_slice(high);
+            indices.map(|index| N as u32 - 1 - index)
+        }
+        false => indices,
+    }
+}
+
+/// BC7 block of mode 6, opaque with 16 levels between the endpoints
+fn bc7_mode6(pixels: &[[u8; 4]; 16]) -> [u8; 16] {
+    // the endpoints have 7 bits and a p bit, the p bit is set so the alpha is 255
+    let (low, high) = color_range(pixels);
+    let (mut low, mut high) = (
+        low.map(|channel| channel | 1),
+        high.map(|channel| channel | 1),
+    );
+    let colors = pixels.each_ref().map(|pixel| &pixel[..3]);
+    let indices = anchored_indices(&BC7_WEIGHTS_4, &mut low, &mut high, &colors);
+
+    let mut block = Bc7Block::new(6);
+    for channel in 0..3 {
+        block.write(low[channel] as u32 >> 1, 7);
+        block.write(high[channel] as u32 >> 1, 7);
+    }
+    block.write(127, 7);
+    block.write(127, 7);
+    block.write(1, 1);
+    block.write(1, 1);
+    block.indices(&indices, 4);
+    block.finish()
+}
+
+/// BC7 block of mode 5, the color and the alpha have their own indices
+fn bc7_mode5(pixels: &[[u8; 4]; 16]) -> [u8; 16] {
+    // the colors have 7 bits, the highest bit is repeated in the lowest one
+    let quantize = |channel: u8| {
+        let value = channel >> 1;
+        (value << 1) | (value >> 6)
+    };
+    let (low, high) = color_range(pixels);
+    let (mut low, mut high) = (low.map(quantize), high.map(quantize));
+    let colors = pixels.each_ref().map(|pixel| &pixel[..3]);
+    let color_indices = anchored_indices(&BC7_WEIGHTS_2, &mut low, &mut high, &colors);
+
+    let alpha = pixels.each_ref().map(|pixel| &pixel[3..]);
+    let mut alpha_low = [pixels
+        .iter()
+        .map(|pixel| pixel[3])
+        .min()
+        .expect("16 pixels")];
+    let mut alpha_high = [pixels
+        .iter()
+        .map(|pixel| pixel[3])
+        .max()
+        .expect("16 pixels")];
+    let alpha_indices = anchored_indices(&BC7_WEIGHTS_2, &mut alpha_low, &mut alpha_high, &alpha);
+
+    let mut block = Bc7Block::new(5);
+    // no rotation
+    block.write(0, 2);
+    for channel in 0..3 {
+        block.write(low[channel] as u32 >> 1, 7);
+        block.write(high[channel] as u32 >> 1, 7);
+    }
+    block.write(alpha_low[0] as u32, 8);
+    block.write(alpha_high[0] as u32, 8);
+    block.indices(&color_indices, 2);
+    block.indices(&alpha_indices, 2);
+    block.finish()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Writer of the BasisLZ streams, the tables of the tests have codes of the same size so the
+    /// code of a symbol is the symbol
+    #[derive(Default)]
+    pub(crate) struct Writer {
+        bits: Vec<bool>,
+    }
+    impl Writer {
+        /// writes the lowest bit first
+        pub(crate) fn write(&mut self, value: u32, count: u32) -> &mut Self {
+            self.bits
+                .extend((0..count).map(|bit| (value >> bit) & 1 == 1));
+            self
+        }
+        /// writes the highest bit of a code first
+        pub(crate) fn code(&mut self, code: u32, size: u32) -> &mut Self {
+            self.bits
+                .extend((0..size).rev().map(|bit| (code >> bit) & 1 == 1));
+            self
+        }
+        /// writes a table of `count` symbols with codes of `size` bits
+        pub(crate) fn flat_table(&mut self, count: u32, size: u32) -> &mut Self {
+            self.write(count, MAX_SYMBOLS_BITS);
+            // a single code length code of one bit
+            self.write(CODE_LENGTH_ORDER.len() as u32, 5);
+            for code in CODE_LENGTH_ORDER {
+                self.write((code == size as usize) as u32, 3);
+            }
+            for _ in 0..count {
+                self.code(0, 1);
+            }
+            self
+        }
+        pub(crate) fn finish(&self) -> Vec<u8> {
+            self.bits
+                .chunks(8)
+                .map(|byte| {
+                    byte.iter()
+                        .enumerate()
+                        .fold(0, |value, (bit, set)| value | ((*set as u8) << bit))
+                })
+                .collect()
+        }
+    }
+
+    /// bits of the codes of a table of `count` symbols
+    pub(crate) fn size_of(count: u32) -> u32 {
+        count.next_power_of_two().trailing_zeros().max(1)
+    }
+
+    /// global data with the codebooks and the slices of the images
+    /// the selectors are delta coded and the history has `history_size` selectors
+    pub(crate) fn global_data(
+        endpoints: &[([u8; 3], u8)],
+        selectors: &[Selector],
+        history_size: u32,
+        images: &[[u32; 4]],
+    ) -> Vec<u8> {
+        let mut writer = Writer::default();
+        for _ in 0..3 {
+            writer.flat_table(32, 5);
+        }
+        writer.flat_table(8, 3).write(0, 1);
+        let (mut last_color, mut last_intensity) = ([16u8; 3], 0u8);
+        for (color, intensity) in endpoints {
+            writer.code(intensity.wrapping_sub(last_intensity) as u32 & 7, 3);
+            for channel in 0..3 {
+                writer.code(
+                    color[channel].wrapping_sub(last_color[channel]) as u32 & 31,
+                    5,
+                );
+            }
+            (last_color, last_intensity) = (*color, *intensity);
+        }
+        let endpoint_data = writer.finish();
+
+        let mut writer = Writer::default();
+        writer.write(0, 3).flat_table(256, 8);
+        let mut last = [0u8; 4];
+        for (index, selector) in selectors.iter().enumerate() {
+            for (row, last) in last.iter_mut().enumerate() {
+                let byte = (0..4).fold(0, |byte, column| {
+                    byte | (selector[row * 4 + column] << (column * 2))
+                });
+                match index {
+                    0 => writer.write(byte as u32, 8),
+                    _ => writer.code((byte ^ *last) as u32, 8),
+                };
+                *last = byte;
+            }
+        }
+        let selector_data = writer.finish();
+
+        let mut writer = Writer::default();
+        let selector_symbols = selectors.len() as u32 + history_size + 1;
+        writer
+            .flat_table(257, 9)
+            .flat_table(endpoints.len() as u32, size_of(endpoints.len() as u32))
+            .flat_table(selector_symbols, size_of(selector_symbols))
+            .flat_table(SELECTOR_RUN_SYMBOLS, 6)
+            .write(history_size, 13);
+        let table_data = writer.finish();
+
+        let mut global = Vec::new();
+        global.extend((endpoints.len() as u16).to_le_bytes());
+        global.extend((selectors.len() as u16).to_le_bytes());
+        for len in [
+            endpoint_data.len(),
+            selector_data.len(),
+            table_data.len(),
+            0,
+        ] {
+            global.extend((len as u32).to_le_bytes());
+        }
+        for image in images {
+            global.extend(0u32.to_le_bytes());
+            global.extend(image.iter().flat_map(|value| value.to_le_bytes()));
+        }
+        global.extend(endpoint_data);
+        global.extend(selector_data);
+        global.extend(table_data);
+        global
+    }
+
+    /// selectors of a single value, the same modifier in every pixel
+    pub(crate) fn uniform(selector: u8) -> Selector {
+        [selector; 16]
+    }
+
+    fn gradient() -> Selector {
+        std::array::from_fn(|pixel| (pixel % 4) as u8)
+    }
+
+    #[test]
+    fn tables_with_runs_of_code_sizes() {
+        let mut writer = Writer::default();
+        // the code length codes 3, 17 and 19 with codes of 2 bits
+        writer.write(8, MAX_SYMBOLS_BITS).write(21, 5);
+        for code in CODE_LENGTH_ORDER {
+            writer.write(if [3, 17, 19].contains(&code) { 2 } else { 0 }, 3);
+        }
+        // 4 zeros, a size of 3 bits and 3 repeats
+        writer
+            .code(1, 2)
+            .write(1, 3)
+            .code(0, 2)
+            .code(2, 2)
+            .write(0, 2);
+        // the symbols 4 to 7 have the codes 0 to 3
+        writer.code(2, 3).code(3, 3);
+        let data = writer.finish();
+        let mut bits = Bits::new(&data);
+        let table = bits.table().unwrap();
+        assert_eq!(table.symbols, [4, 5, 6, 7]);
+        assert_eq!(bits.decode(&table).unwrap(), 6);
+        assert_eq!(bits.decode(&table).unwrap(), 7);
+
+        // a repeat without a previous size
+        let mut writer = Writer::default();
+        writer.write(8, MAX_SYMBOLS_BITS).write(21, 5);
+        for code in CODE_LENGTH_ORDER {
+            writer.write(if [3, 17, 19].contains(&code) { 2 } else { 0 }, 3);
+        }
+        writer.code(2, 2).write(0, 2);
+        assert!(Bits::new(&writer.finish()).table().is_err());
+
+        // codes that use more than the space of the sizes
+        assert!(Huffman::new(&[1, 1, 1]).is_err());
+        assert!(Huffman::new(&[17]).is_err());
+    }
+
+    #[test]
+    fn codebooks_are_deltas_of_the_last_value() {
+        let endpoints = [
+            ([16, 16, 16], 0),
+            ([3, 30, 9], 5),
+            ([12, 0, 31], 7),
+            ([31, 22, 10], 1),
+        ];
+        let selectors = [gradient(), uniform(3), uniform(1)];
+        let global = global_data(&endpoints, &selectors, 4, &[]);
+        let basis = BasisLz::new(&global, 0).unwrap();
+        let decoded: Vec<_> = endpoints
+            .iter()
+            .map(|(color, intensity)| Endpoint {
+                color: *color,
+                intensity: *intensity,
+            })
+            .collect();
+        assert_eq!(basis.endpoints, decoded);
+        assert_eq!(basis.selectors, selectors);
+        assert_eq!(basis.history_size, 4);
+
+        // the global data must have the descriptions of all the images
+        assert!(BasisLz::new(&global, 1).is_err());
+        assert!(BasisLz::new(&global[..global.len() - 1], 0).is_err());
+    }
+
+    #[test]
+    fn slices_predict_endpoints_and_reuse_selectors() {
+        let endpoints = [([0; 3], 0), ([8; 3], 1), ([16; 3], 2), ([24; 3], 3)];
+        let selectors = [uniform(0), uniform(1), uniform(2)];
+        let global = global_data(&endpoints, &selectors, 4, &[]);
+        let basis = BasisLz::new(&global, 0).unwrap();
+
+        let mut writer = Writer::default();
+        // (0, 0) and (1, 0) are deltas, (0, 1) is upper and (1, 1) is upper left
+        writer
+            .code(3 | 3 << 2 | (1 | 2 << 2) << 4, 9)
+            .code(2, 2)
+            .code(2, 3);
+        writer.code(1, 2).code(1, 3);
+        // repeats the last predictions, the delta wraps to the first endpoints
+        writer
+            .code(REPEAT_LAST_PREDICTION, 9)
+            .write(0, 5)
+            .code(2, 2)
+            .code(3 + 3, 3);
+        // the selectors of the history and a run of the first one
+        writer.code(3 + 1, 3).code(3 + 4, 3).code(0, 6);
+        let blocks = basis.decode_slice(&writer.finish(), 3, 2).unwrap();
+        let block = |endpoint, selector| Block { endpoint, selector };
+        assert_eq!(
+            blocks,
+            [
+                block(2, 2),
+                block(3, 1),
+                block(1, 1),
+                block(2, 1),
+                block(2, 1),
+                block(1, 1),
+            ]
+        );
+
+        // the first block can't predict from the left
+        let mut writer = Writer::default();
+        writer.code(0, 9);
+        assert!(basis.decode_slice(&writer.finish(), 1, 1).is_err());
+    }
+
+    /// ETC1 decoder of the blocks in differential mode
+    fn decode_etc1(block: &[u8]) -> [[u8; 3]; 16] {
+        assert_eq!(block[3] & 0b10, 0b10);
+        let modifiers = [
+            [2, 8],
+            [5, 17],
+            [9, 29],
+            [13, 42],
+            [18, 60],
+            [24, 80],
+            [33, 106],
+            [47, 183],
+        ];
+        let [a, b] = modifiers[(block[3] >> 5) as usize];
+        let base = [0, 1, 2].map(|channel| {
+            let color = block[channel] >> 3;
+            (color << 3) | (color >> 2)
+        });
+        let high = u16::from_be_bytes([block[4], block[5]]);
+        let low = u16::from_be_bytes([block[6], block[7]]);
+        std::array::from_fn(|pixel| {
+            let bit = pixel % 4 * 4 + pixel / 4;
+            let index = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+            let modifier = [a, b, -a, -b][index as usize];
+            base.map(|channel| (channel as i32 + modifier).clamp(0, 255) as u8)
+        })
+    }
+
+    fn decode_eac(block: &[u8]) -> [u8; 16] {
+        let base = block[0] as i32;
+        let (multiplier, table) = ((block[1] >> 4) as i32, (block[1] & 15) as usize);
+        let indices = u64::from_be_bytes([
+            0, 0, block[2], block[3], block[4], block[5], block[6], block[7],
+        ]);
+        std::array::from_fn(|pixel| {
+            let position = pixel % 4 * 4 + pixel / 4;
+            let index = (indices >> (45 - position * 3)) & 7;
+            (base + EAC_MODIFIERS[table][index as usize] * multiplier).clamp(0, 255) as u8
+        })
+    }
+
+    /// BC7 decoder of the modes 5 and 6
+    fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+        let bits = u128::from_le_bytes(block.try_into().unwrap());
+        let mut offset = 0;
+        let mut read = |count: u32| {
+            let value = (bits >> offset) as u32 & ((1 << count) - 1);
+            offset += count;
+            value
+        };
+        let interpolate = |weights: &[u32], low: u32, high: u32, index: u32| {
+            (((64 - weights[index as usize]) * low + weights[index as usize] * high + 32) >> 6)
+                as u8
+        };
+        if bits & 0x3f == 1 << 5 {
+            read(6);
+            assert_eq!(read(2), 0);
+            let colors: Vec<[u32; 2]> = (0..3)
+                .map(|_| [read(7), read(7)].map(|value| (value << 1) | (value >> 6)))
+                .collect();
+            let alpha = [read(8), read(8)];
+            let color_indices: Vec<u32> = (0..16)
+                .map(|pixel| read(if pixel == 0 { 1 } else { 2 }))
+                .collect();
+            let alpha_indices: Vec<u32> = (0..16)
+                .map(|pixel| read(if pixel == 0 { 1 } else { 2 }))
+                .collect();
+            std::array::from_fn(|pixel| {
+                let [r, g, b] = [0, 1, 2].map(|channel| {
+                    let [low, high] = colors[channel];
+                    interpolate(&BC7_WEIGHTS_2, low, high, color_indices[pixel])
+                });
+                [
+                    r,
+                    g,
+                    b,
+                    interpolate(&BC7_WEIGHTS_2, alpha[0], alpha[1], alpha_indices[pixel]),
+                ]
+            })
+        } else {
+            assert_eq!(read(7), 1 << 6);
+            let endpoints: Vec<[u32; 2]> = (0..4).map(|_| [read(7), read(7)]).collect();
+            let p = [read(1), read(1)];
+            let indices: Vec<u32> = (0..16)
+                .map(|pixel| read(if pixel == 0 { 3 } else { 4 }))
+                .collect();
+            std::array::from_fn(|pixel| {
+                [0, 1, 2, 3].map(|channel| {
+                    let [low, high] = endpoints[channel];
+                    interpolate(
+                        &BC7_WEIGHTS_4,
+                        low << 1 | p[0],
+                        high << 1 | p[1],
+                        indices[pixel],
+                    )
+                })
+            })
+        }
+    }
+
+    fn max_error(decoded: &[[u8; 4]; 16], pixels: &[[u8; 4]; 16]) -> u8 {
+        decoded
+            .iter()
+            .flatten()
+            .zip(pixels.iter().flatten())
+            .map(|(decoded, pixel)| decoded.abs_diff(*pixel))
+            .max()
+            .unwrap()
+    }
+
+    /// 4x4 image, the color uses the gradient and the alpha the rows
+    fn block_image(alpha: bool) -> (BasisLz, Vec<u8>) {
+        let endpoints = [([20, 12, 6], 2), ([16, 16, 16], 0)];
+        let selectors = [gradient(), std::array::from_fn(|pixel| (pixel / 4) as u8)];
+        let mut writer = Writer::default();
+        writer.code(3, 9).code(0, 1).code(0, 2);
+        let mut level = writer.finish();
+        let mut images = [[0, level.len() as u32, 0, 0]];
+        if alpha {
+            let mut writer = Writer::default();
+            writer.code(3, 9).code(1, 1).code(1, 2);
+            let alpha = writer.finish();
+            images[0][2..].copy_from_slice(&[level.len() as u32, alpha.len() as u32]);
+            level.extend(alpha);
+        }
+        let global = global_data(&endpoints, &selectors, 1, &images);
+        (BasisLz::new(&global, 1).unwrap(), level)
+    }
+
+    #[test]
+    fn blocks_are_transcoded_to_every_target() {
+        let (basis, level) = block_image(true);
+        let transcode = |format| basis.transcode(0, &level, (4, 4), format).unwrap();
+        let block = |endpoint, selector| Block { endpoint, selector };
+        let pixels = basis.pixels(block(0, 0), Some(block(1, 1)));
+        let palette = basis.endpoints[0].palette();
+        for (pixel, value) in pixels.iter().enumerate() {
+            let [r, g, b] = palette[pixel % 4];
+            // the alpha is the green of the endpoint with the color 16
+            assert_eq!(*value, [r, g, b, [124, 130, 134, 140][pixel / 4]]);
+        }
+        assert_eq!(transcode(TextureFormat::Rgba8Unorm), pixels.concat());
+
+        // the EAC alpha is followed by the ETC1 color
+        let etc2 = transcode(TextureFormat::Etc2Rgba8UnormSrgb);
+        assert_eq!(etc2.len(), 16);
+        assert_eq!(
+            decode_etc1(&etc2[8..]),
+            pixels.map(|[r, g, b, _]| [r, g, b])
+        );
+        let alpha = decode_eac(&etc2[..8]);
+        assert!(
+            alpha
+                .iter()
+                .zip(&pixels)
+                .all(|(alpha, pixel)| alpha.abs_diff(pixel[3]) <= 2)
+        );
+        assert_eq!(transcode(TextureFormat::Etc2Rgb8Unorm), etc2[8..]);
+
+        let bc7 = transcode(TextureFormat::Bc7RgbaUnorm);
+        assert_eq!(bc7.len(), 16);
+        assert!(max_error(&decode_bc7(&bc7), &pixels) <= 3);
+
+        // without alpha the BC7 blocks are opaque
+        let (basis, level) = block_image(false);
+        let pixels = basis.pixels(block(0, 0), None);
+        let bc7 = basis
+            .transcode(0, &level, (4, 4), TextureFormat::Bc7RgbaUnormSrgb)
+            .unwrap();
+        let decoded = decode_bc7(&bc7);
+        assert!(decoded.iter().all(|pixel| pixel[3] == 255));
+        assert!(max_error(&decoded, &pixels) <= 3);
+    }
+
+    #[test]
+    fn rgba_images_are_cropped() {
+        let (basis, level) = block_image(false);
+        let pixels = basis.pixels(
+            Block {
+                endpoint: 0,
+                selector: 0,
+            },
+            None,
+        );
+        let image = basis
+            .transcode(0, &level, (3, 2), TextureFormat::Rgba8Unorm)
+            .unwrap();
+        assert_eq!(image, [&pixels[0..3], &pixels[4..7]].concat().concat());
+        // a slice outside of the level
+        assert!(
+            basis
+                .transcode(0, &level[..1], (4, 4), TextureFormat::Rgba8Unorm)
+                .is_err()
+        );
+        assert!(
+            basis
+                .transcode(1, &level, (4, 4), TextureFormat::Rgba8Unorm)
+                .is_err()
+        );
+    }
+}
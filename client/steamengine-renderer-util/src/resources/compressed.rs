@@ -0,0 +1,931 @@
+use super::ResourceLoader;
+use super::basis::BasisLz;
+use crate::errors::Error;
+use steamengine_renderer::Renderer;
+use steamengine_renderer::texture::{Texture, TextureBuilder, TextureDimensions};
+use tracing::*;
+use wgpu::{AstcBlock, AstcChannel, Features, TextureFormat};
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const DDS_MAGIC: [u8; 4] = *b"DDS ";
+/// supercompression schemes of the KTX2 levels
+const BASIS_LZ_SUPERCOMPRESSION: u32 = 1;
+const ZSTD_SUPERCOMPRESSION: u32 = 2;
+const ZLIB_SUPERCOMPRESSION: u32 = 3;
+/// color models of the data format descriptor
+const ETC1S_COLOR_MODEL: u8 = 163;
+const UASTC_COLOR_MODEL: u8 = 166;
+/// transfer function of the data format descriptor with sRGB colors
+const SRGB_TRANSFER: u8 = 2;
+/// channel of the alpha samples of the data format descriptor
+const ALPHA_CHANNEL: u8 = 15;
+
+/// Texture read from a KTX2 or DDS container, the data is uploaded without decoding
+pub struct CompressedImage {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    /// number of layers, 6 per cube in the cubemaps
+    pub layers: u32,
+    pub cube: bool,
+    /// data of every mip level, each level contains all the layers
+    pub levels: Vec<Vec<u8>>,
+}
+impl CompressedImage {
+    /// returns true if the adapter can sample the format of the image
+    pub fn is_supported(&self, features: Features) -> bool {
+        features.contains(self.format.required_features())
+    }
+    /// Uploads the image with all its mip levels
+    /// returns an error if the adapter doesn't support the format
+    pub fn upload(&self, label: &'static str, renderer: &Renderer) -> Result<Texture, Error> {
+        if !self.is_supported(renderer.features()) {
+            return Err(Error::UnsupportedTextureFormat(self.format));
+        }
+        let dimension = match (self.cube, self.layers) {
+            (true, 6) => TextureDimensions::new_cube(self.width),
+            (true, layers) => TextureDimensions::new_cube_array(self.width, layers / 6),
+            (false, 1) => TextureDimensions::new_2d(self.width, self.height),
+            (false, layers) => TextureDimensions::new_2d_array(self.width, self.height, layers),
+        };
+        let mut texture = renderer.init_texture(
+            label,
+            None,
+            TextureBuilder::new()
+                .dimension(dimension)
+                .format(self.format)
+                .mip_level_count(self.levels.len() as u32),
+        );
+        let (block_width, block_height) = self.format.block_dimensions();
+        let block_size = self.format.block_copy_size(None).unwrap_or(0);
+        for (level, data) in self.levels.iter().enumerate() {
+            let size = texture
+                .texture
+                .size()
+                .mip_level_size(level as u32, wgpu::TextureDimension::D2)
+                .physical_size(self.format);
+            renderer.queue().write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &texture.texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                data,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(size.width / block_width * block_size),
+                    rows_per_image: Some(size.height / block_height),
+                },
+                size,
+            );
+        }
+        texture.texture_view(wgpu::TextureViewDescriptor::default());
+        Ok(texture)
+    }
+    /// bytes of a layer in a mip level
+    fn layer_size(format: TextureFormat, width: u32, height: u32, level: u32) -> Option<usize> {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        }
+        .mip_level_size(level, wgpu::TextureDimension::D2)
+        .physical_size(format);
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_copy_size(None)?;
+        // the sizes of the headers can overflow the size of a layer
+        ((size.width / block_width) as usize)
+            .checked_mul((size.height / block_height) as usize)?
+            .checked_mul(block_size as usize)
+    }
+}
+
+/// returns the best format for textures that can be encoded in several formats
+/// Ex: to choose between the `.bc7.ktx2`, `.astc.ktx2` or `.etc2.ktx2` files of a texture
+/// BC7 is used in desktop, ASTC and ETC2 in mobile, and RGBA8 if there isn't compression
+pub fn preferred_format(features: Features, srgb: bool) -> TextureFormat {
+    let format = if features.contains(Features::TEXTURE_COMPRESSION_BC) {
+        TextureFormat::Bc7RgbaUnorm
+    } else if features.contains(Features::TEXTURE_COMPRESSION_ASTC) {
+        TextureFormat::Astc {
+            block: AstcBlock::B4x4,
+            channel: AstcChannel::Unorm,
+        }
+    } else if features.contains(Features::TEXTURE_COMPRESSION_ETC2) {
+        TextureFormat::Etc2Rgba8Unorm
+    } else {
+        TextureFormat::Rgba8Unorm
+    };
+    match srgb {
+        true => format.add_srgb_suffix(),
+        false => format,
+    }
+}
+
+/// returns the format the Basis Universal ETC1S textures are transcoded to
+/// BC7 in desktop, ETC2 in mobile and RGBA8 if there isn't compression, the ASTC devices support
+/// ETC2 so ASTC isn't a target
+pub fn transcode_format(features: Features, alpha: bool, srgb: bool) -> TextureFormat {
+    let format = if features.contains(Features::TEXTURE_COMPRESSION_BC) {
+        TextureFormat::Bc7RgbaUnorm
+    } else if features.contains(Features::TEXTURE_COMPRESSION_ETC2) {
+        match alpha {
+            true => TextureFormat::Etc2Rgba8Unorm,
+            false => TextureFormat::Etc2Rgb8Unorm,
+        }
+    } else {
+        TextureFormat::Rgba8Unorm
+    };
+    match srgb {
+        true => format.add_srgb_suffix(),
+        false => format,
+    }
+}
+
+/// Implementation of Resource loader for KTX2 and DDS textures
+/// The BC, ETC2 and ASTC data is kept compressed, the KTX2 levels with zlib or zstd
+/// supercompression are inflated when they are read
+/// The Basis Universal ETC1S textures are transcoded to the format of `transcode_format` with the
+/// features of the loader, RGBA8 by default. UASTC isn't transcoded, encode those textures to BC,
+/// ETC2 or ASTC before packing them, Ex: one `.bc7.ktx2` and one `.astc.ktx2` per texture picked
+/// with `preferred_format`. The 3D textures aren't supported
+/// ## Example
+/// ```rust,ignore
+/// let loader = CompressedTextureLoader::new().features(renderer.features());
+/// let textures = loader.load_all("assets/textures")?;
+/// ```
+pub struct CompressedTextureLoader {
+    /// features of the adapter, used to pick the format of the transcoded textures
+    features: Features,
+}
+impl Default for CompressedTextureLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl CompressedTextureLoader {
+    pub fn new() -> Self {
+        Self {
+            features: Features::empty(),
+        }
+    }
+    /// sets the features of the adapter, the Basis Universal textures are transcoded to BC7 or
+    /// ETC2 if the adapter supports them
+    pub fn features(mut self, features: Features) -> Self {
+        self.features = features;
+        self
+    }
+    /// reads a KTX2 container
+    pub fn load_ktx2(&self, bytes: &[u8]) -> Result<CompressedImage, Error> {
+        let mut reader = Reader::new(bytes);
+        if reader.bytes(12)? != KTX2_IDENTIFIER {
+            return Err(Error::Container("missing KTX2 identifier"));
+        }
+        let vk_format = reader.u32()?;
+        let _type_size = reader.u32()?;
+        let width = reader.u32()?;
+        let height = reader.u32()?.max(1);
+        let depth = reader.u32()?;
+        let layers = reader.u32()?.max(1);
+        let faces = reader.u32()?;
+        let level_count = reader.u32()?.max(1);
+        let supercompression = reader.u32()?;
+        if !matches!(
+            supercompression,
+            0 | BASIS_LZ_SUPERCOMPRESSION | ZSTD_SUPERCOMPRESSION | ZLIB_SUPERCOMPRESSION
+        ) {
+            return Err(Error::Container("unknown supercompression"));
+        }
+        if depth > 1 {
+            return Err(Error::Container("3D textures aren't supported"));
+        }
+        let faces = faces.max(1);
+        let dfd = (reader.u32()? as u64, reader.u32()? as u64);
+        let _key_values = (reader.u32()?, reader.u32()?);
+        let global = (reader.u64()?, reader.u64()?);
+
+        let mut index = Vec::with_capacity(level_count as usize);
+        for _ in 0..level_count {
+            let offset = reader.u64()?;
+            let length = reader.u64()?;
+            let uncompressed = reader.u64()?;
+            index.push((range(bytes, offset, length)?, uncompressed));
+        }
+
+        let (format, basis) = match (vk_format, supercompression) {
+            (0, BASIS_LZ_SUPERCOMPRESSION) => {
+                let (color_model, alpha, srgb) = dfd_of(range(bytes, dfd.0, dfd.1)?)?;
+                if color_model != ETC1S_COLOR_MODEL {
+                    return Err(Error::Container("BasisLZ needs ETC1S data"));
+                }
+                let images = (level_count as usize)
+                    .checked_mul(layers as usize)
+                    .and_then(|images| images.checked_mul(faces as usize))
+                    .ok_or(Error::Container("too many KTX2 layers"))?;
+                let basis = BasisLz::new(range(bytes, global.0, global.1)?, images)?;
+                let mut format = transcode_format(self.features, alpha, srgb);
+                // the block formats need a size multiple of the blocks
+                if format.block_dimensions() != (1, 1) && (width % 4 != 0 || height % 4 != 0) {
+                    format = transcode_format(Features::empty(), alpha, srgb);
+                }
+                (format, Some(basis))
+            }
+            (_, BASIS_LZ_SUPERCOMPRESSION) => {
+                return Err(Error::Container("BasisLZ needs ETC1S data"));
+            }
+            (0, _) => {
+                let (color_model, ..) = dfd_of(range(bytes, dfd.0, dfd.1)?)?;
+                return Err(Error::Container(match color_model {
+                    UASTC_COLOR_MODEL => {
+                        "UASTC textures aren't transcoded, encode them to BC, ETC2 or ASTC"
+                    }
+                    _ => "unsupported KTX2 color model",
+                }));
+            }
+            _ => (
+                ktx2_format(vk_format).ok_or(Error::Container("unsupported KTX2 vkFormat"))?,
+                None,
+            ),
+        };
+
+        let images = layers
+            .checked_mul(faces)
+            .ok_or(Error::Container("too many KTX2 layers"))?;
+        let mut levels = Vec::with_capacity(level_count as usize);
+        for (level, (data, uncompressed)) in index.into_iter().enumerate() {
+            levels.push(match (&basis, supercompression) {
+                (Some(basis), _) => {
+                    let size = ((width >> level).max(1), (height >> level).max(1));
+                    let mut transcoded = Vec::new();
+                    for image in 0..images as usize {
+                        let image = level * images as usize + image;
+                        transcoded.extend(basis.transcode(image, data, size, format)?);
+                    }
+                    transcoded
+                }
+                (None, ZLIB_SUPERCOMPRESSION) => inflate(data, uncompressed)?,
+                (None, ZSTD_SUPERCOMPRESSION) => unzstd(data, uncompressed)?,
+                _ => data.to_vec(),
+            });
+        }
+        let image = CompressedImage {
+            format,
+            width,
+            height,
+            layers: images,
+            cube: faces == 6,
+            levels,
+        };
+        check_levels(&image)?;
+        trace!(
+            "Loaded KTX2 {:?} {}x{} with {} levels",
+            format,
+            width,
+            height,
+            image.levels.len()
+        );
+        Ok(image)
+    }
+    /// reads a DDS container, with or without the DX10 header
+    pub fn load_dds(&self, bytes: &[u8]) -> Result<CompressedImage, Error> {
+        let mut reader = Reader::new(bytes);
+        if reader.bytes(4)? != DDS_MAGIC {
+            return Err(Error::Container("missing DDS magic"));
+        }
+        if reader.u32()? != 124 {
+            return Err(Error::Container("invalid DDS header size"));
+        }
+        let _flags = reader.u32()?;
+        let height = reader.u32()?;
+        let width = reader.u32()?;
+        let _pitch = reader.u32()?;
+        let depth = reader.u32()?;
+        let level_count = reader.u32()?.max(1);
+        reader.skip(44)?;
+        // pixel format
+        let _size = reader.u32()?;
+        let pixel_flags = reader.u32()?;
+        let four_cc = reader.bytes(4)?;
+        let bit_count = reader.u32()?;
+        let masks = [reader.u32()?, reader.u32()?, reader.u32()?, reader.u32()?];
+        let _caps = reader.u32()?;
+        let caps2 = reader.u32()?;
+        reader.skip(12)?;
+
+        // DDSCAPS2_CUBEMAP and DDSCAPS2_VOLUME
+        let mut cube = caps2 & 0x200 != 0;
+        if caps2 & 0x200000 != 0 && depth > 1 {
+            return Err(Error::Container("3D textures aren't supported"));
+        }
+        let mut layers = 1;
+        let format = if four_cc == b"DX10" {
+            let dxgi_format = reader.u32()?;
+            let _dimension = reader.u32()?;
+            let misc_flag = reader.u32()?;
+            layers = reader.u32()?.max(1);
+            let _misc_flags2 = reader.u32()?;
+            // DDS_RESOURCE_MISC_TEXTURECUBE
+            cube |= misc_flag & 0x4 != 0;
+            dxgi_format_of(dxgi_format).ok_or(Error::Container("unsupported DXGI format"))?
+        } else if pixel_flags & 0x4 != 0 {
+            four_cc_format(four_cc).ok_or(Error::Container("unsupported DDS FourCC"))?
+        } else {
+            match (bit_count, masks) {
+                (32, [0xff, 0xff00, 0xff0000, _]) => TextureFormat::Rgba8Unorm,
+                (32, [0xff0000, 0xff00, 0xff, _]) => TextureFormat::Bgra8Unorm,
+                (8, [0xff, 0, 0, 0]) => TextureFormat::R8Unorm,
+                _ => return Err(Error::Container("unsupported DDS pixel format")),
+            }
+        };
+        if cube {
+            layers = layers
+                .checked_mul(6)
+                .ok_or(Error::Container("too many DDS layers"))?;
+        }
+
+        // DDS stores every mip level of a layer before the next layer
+        let data = reader.rest();
+        let mut levels = vec![Vec::new(); level_count as usize];
+        let mut offset: usize = 0;
+        for _ in 0..layers {
+            for (level, result) in levels.iter_mut().enumerate() {
+                let size = CompressedImage::layer_size(format, width, height, level as u32)
+                    .ok_or(Error::Container("unsupported DDS format"))?;
+                let layer = offset
+                    .checked_add(size)
+                    .and_then(|end| data.get(offset..end))
+                    .ok_or(Error::Container("DDS data is truncated"))?;
+                result.extend_from_slice(layer);
+                offset += size;
+            }
+        }
+        trace!(
+            "Loaded DDS {:?} {}x{} with {} levels",
+            format, width, height, level_count
+        );
+        Ok(CompressedImage {
+            format,
+            width,
+            height,
+            layers,
+            cube,
+            levels,
+        })
+    }
+}
+
+impl ResourceLoader for CompressedTextureLoader {
+    type Resource = CompressedImage;
+    type Error = Error;
+    fn label(&self) -> &'static str {
+        "Compressed Texture Resource Loader"
+    }
+    fn load_from_bytes(&self, bytes: Vec<u8>) -> Result<Self::Resource, Self::Error> {
+        match bytes.get(..4) {
+            Some(magic) if magic == DDS_MAGIC => self.load_dds(&bytes),
+            _ => self.load_ktx2(&bytes),
+        }
+    }
+}
+
+/// inflates a level with zlib supercompression, the level can't be bigger than its declared size
+fn inflate(data: &[u8], uncompressed: u64) -> Result<Vec<u8>, Error> {
+    let limit =
+        usize::try_from(uncompressed).map_err(|_| Error::Container("KTX2 level is too big"))?;
+    let level = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(data, limit)
+        .map_err(|_| Error::Container("invalid zlib level"))?;
+    if level.len() != limit {
+        return Err(Error::Container("zlib level size doesn't match the header"));
+    }
+    Ok(level)
+}
+
+/// decompresses a level with zstd supercompression, the level can't be bigger than its declared
+/// size
+fn unzstd(data: &[u8], uncompressed: u64) -> Result<Vec<u8>, Error> {
+    use std::io::Read;
+    let invalid = || Error::Container("invalid zstd level");
+    let mut level = Vec::new();
+    ruzstd::decoding::StreamingDecoder::new(data)
+        .map_err(|_| invalid())?
+        .take(uncompressed.saturating_add(1))
+        .read_to_end(&mut level)
+        .map_err(|_| invalid())?;
+    if level.len() as u64 != uncompressed {
+        return Err(Error::Container("zstd level size doesn't match the header"));
+    }
+    Ok(level)
+}
+
+/// gets the bytes of a region of the file
+fn range(bytes: &[u8], offset: u64, length: u64) -> Result<&[u8], Error> {
+    offset
+        .checked_add(length)
+        .and_then(|end| bytes.get(usize::try_from(offset).ok()?..usize::try_from(end).ok()?))
+        .ok_or(Error::Container("KTX2 region outside of the file"))
+}
+
+/// reads the color model of the data format descriptor, if it has alpha and if it's sRGB
+fn dfd_of(dfd: &[u8]) -> Result<(u8, bool, bool), Error> {
+    // total size, vendor and type, version and size of the block
+    let mut reader = Reader::new(dfd);
+    reader.skip(10)?;
+    let block_size = u16::from_le_bytes(reader.bytes(2)?.try_into().expect("2 bytes read"));
+    let [color_model, _primaries, transfer, _flags] =
+        reader.bytes(4)?.try_into().expect("4 bytes read");
+    reader.skip(12)?;
+    let samples = (block_size as usize).saturating_sub(24) / 16;
+    let mut alpha = false;
+    for _ in 0..samples {
+        let sample = reader.bytes(16)?;
+        alpha |= sample[3] & 0x0f == ALPHA_CHANNEL;
+    }
+    Ok((color_model, alpha, transfer == SRGB_TRANSFER))
+}
+
+/// checks that every level has the data of all the layers
+fn check_levels(image: &CompressedImage) -> Result<(), Error> {
+    for (level, data) in image.levels.iter().enumerate() {
+        let size =
+            CompressedImage::layer_size(image.format, image.width, image.height, level as u32)
+                .ok_or(Error::Container("unsupported format"))?;
+        if size.checked_mul(image.layers as usize) != Some(data.len()) {
+            return Err(Error::Container("level size doesn't match the format"));
+        }
+    }
+    Ok(())
+}
+
+/// Little endian reader of the headers
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .offset
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.offset..end))
+            .ok_or(Error::Container("header is truncated"))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+    fn skip(&mut self, len: usize) -> Result<(), Error> {
+        self.bytes(len).map(|_| ())
+    }
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("4 bytes read")))
+    }
+    fn u64(&mut self) -> Result<u64, Error> {
+        let bytes = self.bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().expect("8 bytes read")))
+    }
+    fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.offset..]
+    }
+}
+
+/// Converts a VkFormat of a KTX2 container
+fn ktx2_format(vk_format: u32) -> Option<TextureFormat> {
+    use TextureFormat::*;
+    let astc = |block, srgb| Astc {
+        block,
+        channel: match srgb {
+            true => AstcChannel::UnormSrgb,
+            false => AstcChannel::Unorm,
+        },
+    };
+    Some(match vk_format {
+        9 => R8Unorm,
+        16 => Rg8Unorm,
+        37 => Rgba8Unorm,
+        43 => Rgba8UnormSrgb,
+        44 => Bgra8Unorm,
+        50 => Bgra8UnormSrgb,
+        76 => R16Float,
+        83 => Rg16Float,
+        97 => Rgba16Float,
+        100 => R32Float,
+        103 => Rg32Float,
+        109 => Rgba32Float,
+        131 | 133 => Bc1RgbaUnorm,
+        132 | 134 => Bc1RgbaUnormSrgb,
+        135 => Bc2RgbaUnorm,
+        136 => Bc2RgbaUnormSrgb,
+        137 => Bc3RgbaUnorm,
+        138 => Bc3RgbaUnormSrgb,
+        139 => Bc4RUnorm,
+        140 => Bc4RSnorm,
+        141 => Bc5RgUnorm,
+        142 => Bc5RgSnorm,
+        143 => Bc6hRgbUfloat,
+        144 => Bc6hRgbFloat,
+        145 => Bc7RgbaUnorm,
+        146 => Bc7RgbaUnormSrgb,
+        147 => Etc2Rgb8Unorm,
+        148 => Etc2Rgb8UnormSrgb,
+        149 => Etc2Rgb8A1Unorm,
+        150 => Etc2Rgb8A1UnormSrgb,
+        151 => Etc2Rgba8Unorm,
+        152 => Etc2Rgba8UnormSrgb,
+        153 => EacR11Unorm,
+        154 => EacR11Snorm,
+        155 => EacRg11Unorm,
+        156 => EacRg11Snorm,
+        157..=184 => {
+            let block = [
+                AstcBlock::B4x4,
+                AstcBlock::B5x4,
+                AstcBlock::B5x5,
+                AstcBlock::B6x5,
+                AstcBlock::B6x6,
+                AstcBlock::B8x5,
+                AstcBlock::B8x6,
+                AstcBlock::B8x8,
+                AstcBlock::B10x5,
+                AstcBlock::B10x6,
+                AstcBlock::B10x8,
+                AstcBlock::B10x10,
+                AstcBlock::B12x10,
+                AstcBlock::B12x12,
+            ][(vk_format - 157) as usize / 2];
+            astc(block, (vk_format - 157) % 2 == 1)
+        }
+        _ => return None,
+    })
+}
+
+/// Converts a DXGI_FORMAT of the DX10 header
+fn dxgi_format_of(dxgi_format: u32) -> Option<TextureFormat> {
+    use TextureFormat::*;
+    Some(match dxgi_format {
+        2 => Rgba32Float,
+        10 => Rgba16Float,
+        28 => Rgba8Unorm,
+        29 => Rgba8UnormSrgb,
+        49 => Rg8Unorm,
+        61 => R8Unorm,
+        71 => Bc1RgbaUnorm,
+        72 => Bc1RgbaUnormSrgb,
+        74 => Bc2RgbaUnorm,
+        75 => Bc2RgbaUnormSrgb,
+        77 => Bc3RgbaUnorm,
+        78 => Bc3RgbaUnormSrgb,
+        80 => Bc4RUnorm,
+        81 => Bc4RSnorm,
+        83 => Bc5RgUnorm,
+        84 => Bc5RgSnorm,
+        87 => Bgra8Unorm,
+        91 => Bgra8UnormSrgb,
+        95 => Bc6hRgbUfloat,
+        96 => Bc6hRgbFloat,
+        98 => Bc7RgbaUnorm,
+        99 => Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+/// Converts the FourCC of the legacy DDS header
+fn four_cc_format(four_cc: &[u8]) -> Option<TextureFormat> {
+    use TextureFormat::*;
+    Some(match four_cc {
+        b"DXT1" => Bc1RgbaUnorm,
+        b"DXT2" | b"DXT3" => Bc2RgbaUnorm,
+        b"DXT4" | b"DXT5" => Bc3RgbaUnorm,
+        b"ATI1" | b"BC4U" => Bc4RUnorm,
+        b"BC4S" => Bc4RSnorm,
+        b"ATI2" | b"BC5U" => Bc5RgUnorm,
+        b"BC5S" => Bc5RgSnorm,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// DDS header with a legacy FourCC or the DX10 header
+    fn dds(width: u32, height: u32, levels: u32, four_cc: &[u8; 4], caps2: u32) -> Vec<u8> {
+        let mut bytes = DDS_MAGIC.to_vec();
+        for value in [124, 0, height, width, 0, 0, levels] {
+            bytes.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        bytes.extend_from_slice(&[0; 44]);
+        // pixel format with DDPF_FOURCC
+        bytes.extend_from_slice(&32u32.to_le_bytes());
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        bytes.extend_from_slice(four_cc);
+        bytes.extend_from_slice(&[0; 20]);
+        bytes.extend_from_slice(&0x1000u32.to_le_bytes());
+        bytes.extend_from_slice(&caps2.to_le_bytes());
+        bytes.extend_from_slice(&[0; 12]);
+        bytes
+    }
+
+    fn dx10(dxgi_format: u32, misc_flag: u32, layers: u32) -> Vec<u8> {
+        [dxgi_format, 3, misc_flag, layers, 0]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    /// KTX2 header with the data of the levels after the level index
+    fn ktx2(
+        vk_format: u32,
+        size: u32,
+        faces: u32,
+        supercompression: u32,
+        levels: &[(Vec<u8>, u64)],
+    ) -> Vec<u8> {
+        ktx2_with(
+            vk_format,
+            (size, size),
+            faces,
+            supercompression,
+            &[],
+            &[],
+            levels,
+        )
+    }
+
+    /// KTX2 header with the data format descriptor and the global data after the level index
+    fn ktx2_with(
+        vk_format: u32,
+        (width, height): (u32, u32),
+        faces: u32,
+        supercompression: u32,
+        dfd: &[u8],
+        global: &[u8],
+        levels: &[(Vec<u8>, u64)],
+    ) -> Vec<u8> {
+        let mut bytes = KTX2_IDENTIFIER.to_vec();
+        for value in [
+            vk_format,
+            1,
+            width,
+            height,
+            0,
+            0,
+            faces,
+            levels.len() as u32,
+            supercompression,
+        ] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        let dfd_offset = bytes.len() + 32 + levels.len() * 24;
+        let global_offset = dfd_offset + dfd.len();
+        for value in [dfd_offset as u32, dfd.len() as u32, 0, 0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [global_offset as u64, global.len() as u64] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        let mut offset = (global_offset + global.len()) as u64;
+        for (data, uncompressed) in levels {
+            for value in [offset, data.len() as u64, *uncompressed] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            offset += data.len() as u64;
+        }
+        bytes.extend_from_slice(dfd);
+        bytes.extend_from_slice(global);
+        for (data, _) in levels {
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    /// data format descriptor with a sample of the color and one of the alpha
+    fn dfd(color_model: u8, transfer: u8, alpha: bool) -> Vec<u8> {
+        let samples = if alpha { 2 } else { 1 };
+        let block_size = 24 + samples * 16;
+        let mut bytes = ((block_size + 4) as u32).to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&(block_size as u16).to_le_bytes());
+        bytes.extend_from_slice(&[color_model, 1, transfer, 0]);
+        bytes.extend_from_slice(&[0; 12]);
+        for channel in [0, ALPHA_CHANNEL].iter().take(samples) {
+            let mut sample = [0; 16];
+            sample[3] = *channel;
+            bytes.extend_from_slice(&sample);
+        }
+        bytes
+    }
+
+    /// ETC1S texture of 2x2 blocks, every block with the endpoint 0 and the selector 0
+    fn etc1s(
+        width: u32,
+        height: u32,
+        srgb: bool,
+        features: Features,
+    ) -> Result<CompressedImage, Error> {
+        use crate::resources::basis::tests::{Writer, global_data, uniform};
+        let mut writer = Writer::default();
+        // delta, left, upper and upper predictions of the 2x2 blocks
+        writer.code(3 | (1 | 1 << 2) << 4, 9).code(0, 1);
+        for _ in 0..4 {
+            writer.code(0, 2);
+        }
+        let slice = writer.finish();
+        let global = global_data(
+            &[([10, 20, 30], 1), ([0; 3], 0)],
+            &[uniform(2)],
+            2,
+            &[[0, slice.len() as u32, 0, 0]],
+        );
+        let transfer = if srgb { SRGB_TRANSFER } else { 1 };
+        let bytes = ktx2_with(
+            0,
+            (width, height),
+            1,
+            BASIS_LZ_SUPERCOMPRESSION,
+            &dfd(ETC1S_COLOR_MODEL, transfer, false),
+            &global,
+            &[(slice, 0)],
+        );
+        CompressedTextureLoader::new()
+            .features(features)
+            .load_ktx2(&bytes)
+    }
+
+    #[test]
+    fn dds_bc1_2d_with_mipmaps() {
+        let mut bytes = dds(8, 8, 3, b"DXT1", 0);
+        // 2x2, 1x1 and 1x1 blocks of 8 bytes
+        bytes.extend((0..48).map(|value| value as u8));
+        let image = CompressedTextureLoader::new().load_dds(&bytes).unwrap();
+        assert_eq!(image.format, TextureFormat::Bc1RgbaUnorm);
+        assert_eq!((image.width, image.height, image.layers), (8, 8, 1));
+        assert!(!image.cube);
+        let sizes: Vec<usize> = image.levels.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [32, 8, 8]);
+        assert_eq!(image.levels[1][0], 32);
+    }
+
+    #[test]
+    fn dds_cubemap_stores_the_levels_of_every_face() {
+        let mut bytes = dds(4, 4, 2, b"DXT1", 0x200);
+        for face in 0..6u8 {
+            bytes.extend([face; 16]);
+        }
+        let image = CompressedTextureLoader::new().load_dds(&bytes).unwrap();
+        assert!(image.cube);
+        assert_eq!(image.layers, 6);
+        assert_eq!(image.levels.len(), 2);
+        // the faces are sorted by level, the first level of the faces 0 and 1
+        assert_eq!(&image.levels[0][..16], &[[0; 8], [1; 8]].concat());
+        assert_eq!(image.levels[1].len(), 48);
+    }
+
+    #[test]
+    fn dds_dx10_array() {
+        let mut bytes = dds(4, 4, 1, b"DX10", 0);
+        bytes.extend(dx10(98, 0, 3));
+        bytes.extend([7; 48]);
+        let image = CompressedTextureLoader::new().load_dds(&bytes).unwrap();
+        assert_eq!(image.format, TextureFormat::Bc7RgbaUnorm);
+        assert_eq!(image.layers, 3);
+        assert!(!image.cube);
+        assert_eq!(image.levels, vec![vec![7; 48]]);
+
+        let mut bytes = dds(4, 4, 1, b"DX10", 0);
+        bytes.extend(dx10(99, 0x4, 2));
+        bytes.extend([0; 16 * 12]);
+        let image = CompressedTextureLoader::new().load_dds(&bytes).unwrap();
+        assert_eq!(image.format, TextureFormat::Bc7RgbaUnormSrgb);
+        assert!(image.cube);
+        assert_eq!(image.layers, 12);
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let loader = CompressedTextureLoader::new();
+        let bytes = dds(8, 8, 1, b"DXT1", 0);
+        assert!(loader.load_dds(&bytes[..60]).is_err());
+        let mut bytes = dds(8, 8, 2, b"DXT1", 0);
+        bytes.extend([0; 32]);
+        assert!(loader.load_dds(&bytes).is_err());
+
+        let bytes = ktx2(131, 4, 1, 0, &[(vec![0; 8], 8)]);
+        assert!(loader.load_ktx2(&bytes[..bytes.len() - 1]).is_err());
+        assert!(loader.load_ktx2(&bytes[..40]).is_err());
+    }
+
+    #[test]
+    fn ktx2_bc1_and_level_ranges() {
+        let loader = CompressedTextureLoader::new();
+        let bytes = ktx2(132, 4, 1, 0, &[(vec![3; 8], 8)]);
+        let image = loader.load_ktx2(&bytes).unwrap();
+        assert_eq!(image.format, TextureFormat::Bc1RgbaUnormSrgb);
+        assert_eq!(image.levels, vec![vec![3; 8]]);
+
+        // a level whose end overflows
+        let mut bytes = ktx2(131, 4, 1, 0, &[(vec![0; 8], 8)]);
+        let index = KTX2_IDENTIFIER.len() + 36 + 32;
+        bytes[index..index + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(loader.load_ktx2(&bytes).is_err());
+    }
+
+    #[test]
+    fn ktx2_zlib_levels_are_inflated() {
+        let level: Vec<u8> = (0..8 * 6).map(|value| value as u8).collect();
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&level, 6);
+        let bytes = ktx2(
+            131,
+            4,
+            6,
+            ZLIB_SUPERCOMPRESSION,
+            &[(compressed, level.len() as u64)],
+        );
+        let image = CompressedTextureLoader::new().load_ktx2(&bytes).unwrap();
+        assert!(image.cube);
+        assert_eq!(image.levels, vec![level]);
+
+        let bytes = ktx2(131, 4, 1, 1, &[(vec![0; 8], 8)]);
+        assert!(CompressedTextureLoader::new().load_ktx2(&bytes).is_err());
+    }
+
+    #[test]
+    fn ktx2_zstd_levels_are_decompressed() {
+        let level: Vec<u8> = [1, 2, 3, 4, 5, 6, 7, 8].repeat(6);
+        // compressed with the reference zstd encoder
+        let compressed = vec![
+            0x28, 0xb5, 0x2f, 0xfd, 0x24, 0x30, 0x7d, 0x00, 0x00, 0x48, 0x01, 0x02, 0x03, 0x04,
+            0x05, 0x06, 0x07, 0x08, 0x01, 0x01, 0x00, 0x96, 0xf7, 0x08, 0x5b, 0x65, 0x87, 0xdf,
+        ];
+        let bytes = ktx2(
+            131,
+            4,
+            6,
+            ZSTD_SUPERCOMPRESSION,
+            &[(compressed.clone(), level.len() as u64)],
+        );
+        let image = CompressedTextureLoader::new().load_ktx2(&bytes).unwrap();
+        assert_eq!(image.levels, vec![level.clone()]);
+
+        let compressed = ruzstd::encoding::compress_to_vec(
+            &level[..8],
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+        let bytes = ktx2(131, 4, 1, ZSTD_SUPERCOMPRESSION, &[(compressed, 8)]);
+        let image = CompressedTextureLoader::new().load_ktx2(&bytes).unwrap();
+        assert_eq!(image.levels, vec![level[..8].to_vec()]);
+
+        // a level bigger than the header
+        let compressed = ruzstd::encoding::compress_to_vec(
+            &[0u8; 16][..],
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+        let bytes = ktx2(131, 4, 1, ZSTD_SUPERCOMPRESSION, &[(compressed, 8)]);
+        assert!(CompressedTextureLoader::new().load_ktx2(&bytes).is_err());
+    }
+
+    #[test]
+    fn etc1s_is_transcoded_to_the_supported_format() {
+        let image = etc1s(8, 8, true, Features::empty()).unwrap();
+        assert_eq!(image.format, TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(image.levels[0].len(), 8 * 8 * 4);
+        // the selector 2 is the first positive modifier of the table 1
+        assert_eq!(image.levels[0][..4], [87, 170, 252, 255]);
+
+        let image = etc1s(8, 8, false, Features::TEXTURE_COMPRESSION_BC).unwrap();
+        assert_eq!(image.format, TextureFormat::Bc7RgbaUnorm);
+        assert_eq!(image.levels[0].len(), 4 * 16);
+        let image = etc1s(8, 8, false, Features::TEXTURE_COMPRESSION_ETC2).unwrap();
+        assert_eq!(image.format, TextureFormat::Etc2Rgb8Unorm);
+        assert_eq!(image.levels[0].len(), 4 * 8);
+        assert!(image.is_supported(Features::TEXTURE_COMPRESSION_ETC2));
+
+        // the block formats need a size multiple of the blocks
+        let image = etc1s(6, 6, false, Features::TEXTURE_COMPRESSION_BC).unwrap();
+        assert_eq!(image.format, TextureFormat::Rgba8Unorm);
+        assert_eq!(image.levels[0].len(), 6 * 6 * 4);
+    }
+
+    #[test]
+    fn uastc_is_rejected_explicitly() {
+        let bytes = ktx2_with(
+            0,
+            (4, 4),
+            1,
+            0,
+            &dfd(UASTC_COLOR_MODEL, 1, false),
+            &[],
+            &[(vec![0; 16], 16)],
+        );
+        let error = CompressedTextureLoader::new()
+            .load_ktx2(&bytes)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("UASTC"));
+    }
+}
@@ -12,8 +12,20 @@ pub mod model;
 #[cfg(feature = "texture-resource-manager")]
 pub mod texture;
 
+/// Animated textures from GIF, APNG and frame folders
+#[cfg(feature = "texture-resource-manager")]
+pub mod animation;
+
+/// Implementation of resource loader for KTX2 and DDS textures
+#[cfg(feature = "compressed-textures")]
+pub mod compressed;
+
+/// Transcoder of the Basis Universal ETC1S textures of KTX2
+#[cfg(feature = "compressed-textures")]
+mod basis;
+
 /// Identifier for the resources
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone)]
 pub struct Identifier {
     /// Root folder of the resource
     /// First folder to search
@@ -30,17 +42,19 @@ impl Identifier {
     /// Convert a path into a Indentifier
     /// Example
     /// ```rust
-    /// let identifier = Indentifier::parse_from_str("assets/textures/tree.png");
+    /// # use steamengine_renderer_util::resources::Identifier;
+    /// let identifier = Identifier::parse_from_str("assets/textures/tree.png");
     /// assert_eq!(identifier.root, "assets");
     /// assert_eq!(identifier.group, "textures");
     /// assert_eq!(identifier.id, "tree.png");
     /// ```
     /// If the path doesnt has a group
     /// ```rust
+    /// # use steamengine_renderer_util::resources::Identifier;
     /// let identifier = Identifier::parse_from_str("assets/cube.obj");
     /// assert_eq!(identifier.root, "assets");
     /// assert_eq!(identifier.group, "");
-    /// assert_eq!(identifier.id, "tree.png")
+    /// assert_eq!(identifier.id, "cube.obj")
     /// ```
     ///
     pub fn parse_from_str(id: &str) -> Self {
@@ -68,6 +82,18 @@ impl Identifier {
     pub fn id(&self) -> String {
         self.id.clone()
     }
+    /// Convert the identifier back into the path of the file
+    /// ```rust
+    /// # use steamengine_renderer_util::resources::Identifier;
+    /// let identifier = Identifier::parse_from_str("assets/textures/tree.png");
+    /// assert_eq!(identifier.path(), "assets/textures/tree.png");
+    /// ```
+    pub fn path(&self) -> String {
+        match self.group.is_empty() {
+            true => format!("{}/{}", self.root, self.id),
+            false => format!("{}/{}/{}", self.root, self.group, self.id),
+        }
+    }
     pub fn new(&self, root: &str, group: &str, id: &str) -> Self {
         Self {
             root: root.to_owned(),
@@ -102,6 +128,7 @@ pub trait ResourceLoader: Send + Sync {
         let result: HashMap<Identifier, Self::Resource> = entries
             .files
             .par_iter()
+            .filter(|entry| self.accepts(entry))
             .filter_map(|entry| {
                 debug!(
                     "READ ENTRY \"{}\" WITH RESOURCE LOADER \"{}\"",
@@ -121,6 +148,10 @@ pub trait ResourceLoader: Send + Sync {
             .collect();
         Ok(result)
     }
+    /// returns false if `load_all` must skip the file, default true
+    fn accepts(&self, _path: &str) -> bool {
+        true
+    }
     /// load a file from the bytes
     fn load_from_bytes(&self, bytes: Vec<u8>) -> Result<Self::Resource, Self::Error>;
     /// Name of the resource loader
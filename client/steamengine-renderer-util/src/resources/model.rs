@@ -12,38 +12,14 @@ use wgpu::util::DrawIndexedIndirectArgs;
 
 /// Vertex of a model
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(
+    Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, steamengine_renderer::vertex::Vertex,
+)]
 pub struct Vertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
     normal: [f32; 3],
 }
-impl steamengine_renderer::vertex::Vertex for Vertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
-                    shader_location: 2,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-            ],
-        }
-    }
-}
 
 /// Vertices and indices of a model
 pub struct RawModel {
@@ -69,6 +45,11 @@ impl RenderPassAttachModels for wgpu::RenderPass<'_> {
 /// Implementation of resource loader for model
 pub struct ModelResourceLoader;
 
+impl Default for ModelResourceLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl ModelResourceLoader {
     pub fn new() -> Self {
         Self
@@ -148,7 +129,7 @@ impl ResourceLoader for ModelResourceLoader {
                 ..Default::default()
             },
             move |p| {
-                let mat_text = fs::read_to_string(&p).expect("Cannot read to string file");
+                let mat_text = fs::read_to_string(p).expect("Cannot read to string file");
                 tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
             },
         )?;
@@ -1,13 +1,20 @@
 use super::Identifier;
 use super::ResourceLoader;
+use crate::errors::Error;
 use hashbrown::HashMap;
 use steamengine_renderer::Renderer;
+use steamengine_renderer::import::{ImportOptions, Precision, encode};
+use steamengine_renderer::sampler::SamplerPreset;
+use steamengine_renderer::texture::Texture;
 use steamengine_renderer::texture::TextureBuilder;
 use steamengine_renderer::texture::TextureDimensions;
 use tracing::*;
 use wgpu::TextureFormat;
 use wgpu::TextureUsages;
 
+/// Extension of the files with the import options of a texture, Ex: `brick.png.import`
+pub const SIDECAR_EXTENSION: &str = "import";
+
 /// Bound of a texture inside the atlas
 #[derive(Copy, Clone)]
 pub struct TextureBounds {
@@ -15,8 +22,28 @@ pub struct TextureBounds {
     pub uv_scale: [f32; 2],
 }
 
+/// Textures loaded in a bindless table or in an atlas when the table isn't supported
+#[cfg(feature = "bindless-textures")]
+pub enum LoadedTextures<'a> {
+    /// the textures are sampled with its index in the table
+    Bindless {
+        table: crate::bindless::TextureTable<'a>,
+        indices: HashMap<Identifier, u32>,
+    },
+    /// the textures are sampled with its bounds inside the atlas
+    Atlas {
+        atlas: crate::bindings::Bindings,
+        bounds: HashMap<Identifier, TextureBounds>,
+    },
+}
+
 /// Implementation of Resource loader for textures
 pub struct TextureResourceLoader;
+impl Default for TextureResourceLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl TextureResourceLoader {
     pub fn new() -> Self {
         Self
@@ -30,15 +57,46 @@ impl TextureResourceLoader {
         crate::bindings::Bindings,
         HashMap<Identifier, TextureBounds>,
     ) {
-        let images: HashMap<Identifier, image::DynamicImage> =
-            self.load_all(root).expect("Cannot read textures");
+        self.load_to_atlas_with(root, renderer, SamplerPreset::BILINEAR)
+    }
+    /// load all the textures inside a atlas sampled with the preset, Ex: `SamplerPreset::PIXEL_ART`
+    /// the mip levels of the atlas are generated if the preset uses them
+    /// the import options of every file are applied except the color space, the mip levels and
+    /// the sampler, that are the same for the whole atlas
+    pub fn load_to_atlas_with(
+        &self,
+        root: &str,
+        renderer: std::sync::Arc<Renderer>,
+        preset: SamplerPreset,
+    ) -> (
+        crate::bindings::Bindings,
+        HashMap<Identifier, TextureBounds>,
+    ) {
+        let images: HashMap<Identifier, image::RgbaImage> = self
+            .load_all(root)
+            .expect("Cannot read textures")
+            .into_iter()
+            .filter(|(id, image)| {
+                let low = Precision::of(image) == Precision::Unorm8;
+                if !low {
+                    warn!(
+                        "Keeping \"{}\" out of the 8 bit atlas, load it with load_texture",
+                        id
+                    );
+                }
+                low
+            })
+            .map(|(id, image)| {
+                let options = self.options_or_default(&id);
+                (id, options.apply(image))
+            })
+            .collect();
 
         let mut atlas_dim_width = 0;
         let mut atlas_dim_height = 0;
 
         for (_, image) in &images {
-            let dim = image.to_rgba8();
-            let (width, height) = dim.dimensions();
+            let (width, height) = image.dimensions();
 
             atlas_dim_width += width + 10;
 
@@ -54,8 +112,7 @@ impl TextureResourceLoader {
         let mut bounds: HashMap<Identifier, TextureBounds> = HashMap::new();
         let mut atlas = vec![0u8; atlas_dim_width as usize * atlas_dim_height as usize * 4];
 
-        for (id, image) in images {
-            let img = image.to_rgba8();
+        for (id, img) in images {
             let (width, height) = img.dimensions();
             let pixels = img.as_raw();
 
@@ -86,10 +143,14 @@ impl TextureResourceLoader {
             x += width + 10;
         }
 
+        let mut builder = TextureBuilder::new();
+        if preset.uses_mipmaps() {
+            builder = builder.generate_mipmaps();
+        }
         let mut texture = renderer.init_texture(
             "Global Texture Atlas",
             None,
-            TextureBuilder::new()
+            builder
                 .data(atlas)
                 .usage(TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST)
                 .format(TextureFormat::Rgba8UnormSrgb)
@@ -98,22 +159,11 @@ impl TextureResourceLoader {
                 .origin(wgpu::Origin3d::ZERO)
                 .aspect(wgpu::TextureAspect::All)
                 .buffer_offset(0)
-                .bytes_per_row((atlas_dim_width * 4) as u32)
+                .bytes_per_row(atlas_dim_width * 4)
                 .rows_per_image(atlas_dim_height),
         );
         texture.texture_view(wgpu::TextureViewDescriptor::default());
-        texture.texture_sampler(
-            wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
-            },
-            &renderer,
-        );
+        texture.sampler_preset(preset, &renderer);
 
         let (layout, bind) =
             texture.default_bind_group("Global Texture Atlas Bind Group", &renderer);
@@ -122,11 +172,221 @@ impl TextureResourceLoader {
 
         (atlas, bounds)
     }
+    /// load all the textures inside a bindless table with `capacity` slots
+    /// the indices are usually per instance, so it falls back to `load_to_atlas` if the renderer
+    /// doesn't support the table or indexing it with values that aren't uniform
+    /// the textures that don't fit in the table are skipped with an error
+    #[cfg(feature = "bindless-textures")]
+    pub fn load_to_table<'a>(
+        &self,
+        root: &str,
+        renderer: std::sync::Arc<Renderer<'a>>,
+        capacity: u32,
+    ) -> LoadedTextures<'a> {
+        use crate::bindless::TextureTable;
+
+        if !TextureTable::supported(&renderer, capacity)
+            || !TextureTable::supports_non_uniform_indexing(&renderer)
+        {
+            warn!("Bindless textures aren't supported, using an atlas");
+            let (atlas, bounds) = self.load_to_atlas(root, renderer);
+            return LoadedTextures::Atlas { atlas, bounds };
+        }
+        let table = TextureTable::new(renderer.clone(), "Global Texture Table", capacity)
+            .expect("Texture table support checked before");
+        let images: HashMap<Identifier, image::DynamicImage> =
+            self.load_all(root).expect("Cannot read textures");
+
+        let mut indices = HashMap::new();
+        for (id, image) in images {
+            let options = self.options_or_default(&id);
+            let img = options.import(image, renderer.features());
+            let mut builder = TextureBuilder::new();
+            if options.generates_mipmaps() {
+                builder = builder.generate_mipmaps();
+            }
+            let texture = renderer.init_texture(
+                "Global Texture Table Texture",
+                None,
+                builder
+                    .data(img.data)
+                    .format(img.format)
+                    .dimension(TextureDimensions::new_2d(img.width, img.height)),
+            );
+            match table.insert(&texture) {
+                Ok(index) => {
+                    indices.insert(id, index);
+                }
+                Err(err) => error!(
+                    "Skipping a texture that can't be added to the table -- {}, {}",
+                    id, err
+                ),
+            }
+        }
+        LoadedTextures::Bindless { table, indices }
+    }
+    /// load a texture with the import options of the file, see `import_options`
+    pub fn load_texture(&self, path: &str, renderer: &Renderer) -> Result<Texture, Error> {
+        let options = self.import_options(path)?;
+        let image = self.load_from_path(path)?;
+        Ok(renderer.texture_from_image("Texture", image, &options)?)
+    }
+    /// Gets the import options of a file, the defaults follow the name of the file and are
+    /// overridden by a sidecar next to it, Ex: `brick_normal.png.import`
+    pub fn import_options(&self, path: &str) -> Result<ImportOptions, Error> {
+        let options = ImportOptions::from_name(path);
+        let sidecar = format!("{}.{}", path, SIDECAR_EXTENSION);
+        if !std::path::Path::new(&sidecar).exists() {
+            return Ok(options);
+        }
+        trace!("Reading import options -- {}", sidecar);
+        Ok(options.with_sidecar(&std::fs::read_to_string(&sidecar)?)?)
+    }
+    fn options_or_default(&self, id: &Identifier) -> ImportOptions {
+        let path = id.path();
+        self.import_options(&path).unwrap_or_else(|err| {
+            error!("Error reading import options of \"{}\" - {}", path, err);
+            ImportOptions::from_name(&path)
+        })
+    }
+    /// load a cubemap from the images of the faces, in order +X, -X, +Y, -Y, +Z, -Z
+    /// the faces keep the precision of 16 bit and float images, Ex: HDR environment maps
+    pub fn load_cubemap(&self, faces: [&str; 6], renderer: &Renderer) -> Result<Texture, Error> {
+        let mut size = None;
+        let mut images = Vec::with_capacity(6);
+        for face in faces {
+            let image = self.load_from_path(face)?;
+            let (width, height) = (image.width(), image.height());
+            if width != height || size.is_some_and(|size| size != width) {
+                return Err(Error::CubemapFaces);
+            }
+            size = Some(width);
+            images.push(image);
+        }
+        let precision = images
+            .iter()
+            .map(Precision::of)
+            .max()
+            .unwrap_or(Precision::Unorm8);
+        let format = cubemap_format(precision, renderer);
+        let layers = images
+            .into_iter()
+            .map(|image| encode(format, cubemap_face(image, format).as_raw()))
+            .collect();
+        Ok(cubemap(
+            size.expect("A cubemap has six faces"),
+            format,
+            layers,
+            renderer,
+        ))
+    }
+    /// load a cubemap from an equirectangular panorama, the faces are squares of `size` pixels
+    /// HDR and EXR panoramas are projected into a float cubemap
+    pub fn load_panorama(
+        &self,
+        path: &str,
+        size: u32,
+        renderer: &Renderer,
+    ) -> Result<Texture, Error> {
+        let image = self.load_from_path(path)?;
+        let format = cubemap_format(Precision::of(&image), renderer);
+        let image = cubemap_face(image, format);
+        trace!("Projecting panorama into a cubemap -- {}", path);
+        let layers = (0..6)
+            .map(|face| encode(format, &project_face(&image, face, size)))
+            .collect();
+        Ok(cubemap(size, format, layers, renderer))
+    }
+}
+
+fn cubemap_format(precision: Precision, renderer: &Renderer) -> TextureFormat {
+    let options = ImportOptions::new();
+    match precision {
+        Precision::Unorm8 => TextureFormat::Rgba8UnormSrgb,
+        Precision::Unorm16 => options.unorm16_format(renderer.features()),
+        Precision::Float => options.float_format(renderer.features()),
+    }
+}
+
+/// Pixels of a face in the format of the cubemap, the 8 bit cubemaps keep the sRGB values and
+/// the others are linear, so the 8 bit faces of a float cubemap are decoded
+fn cubemap_face(image: image::DynamicImage, format: TextureFormat) -> image::Rgba32FImage {
+    match format {
+        TextureFormat::Rgba8UnormSrgb => image.into_rgba32f(),
+        _ => ImportOptions::new().apply_f32(image),
+    }
+}
+
+fn cubemap(size: u32, format: TextureFormat, layers: Vec<Vec<u8>>, renderer: &Renderer) -> Texture {
+    let mut texture = renderer.init_texture(
+        "Cubemap",
+        None,
+        TextureBuilder::new()
+            .layers(layers)
+            .format(format)
+            .dimension(TextureDimensions::new_cube(size)),
+    );
+    texture.texture_view(wgpu::TextureViewDescriptor::default());
+    texture.sampler_preset(SamplerPreset::BILINEAR, renderer);
+    texture
+}
+
+/// Direction of a pixel of a face, `u` and `v` go from -1 to 1
+fn face_direction(face: u32, u: f32, v: f32) -> [f32; 3] {
+    match face {
+        0 => [1.0, -v, -u],
+        1 => [-1.0, -v, u],
+        2 => [u, 1.0, v],
+        3 => [u, -1.0, -v],
+        4 => [u, -v, 1.0],
+        _ => [-u, -v, -1.0],
+    }
+}
+
+/// Samples the panorama in the directions of the pixels of a face
+fn project_face(panorama: &image::Rgba32FImage, face: u32, size: u32) -> Vec<f32> {
+    use std::f32::consts::PI;
+
+    let (width, height) = panorama.dimensions();
+    let mut result = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let u = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+            let v = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+            let [dx, dy, dz] = face_direction(face, u, v);
+            let length = (dx * dx + dy * dy + dz * dz).sqrt();
+            let longitude = dz.atan2(dx);
+            let latitude = (dy / length).asin();
+            let px = (longitude / (2.0 * PI) + 0.5) * width as f32 - 0.5;
+            let py = (0.5 - latitude / PI) * height as f32 - 0.5;
+            result.extend_from_slice(&bilinear(panorama, px, py));
+        }
+    }
+    result
+}
+
+/// Bilinear sample, wraps horizontally and clamps vertically
+fn bilinear(image: &image::Rgba32FImage, x: f32, y: f32) -> [f32; 4] {
+    let (width, height) = image.dimensions();
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let pixel = |x: f32, y: f32| {
+        let x = (x as i64).rem_euclid(width as i64) as u32;
+        let y = (y as i64).clamp(0, height as i64 - 1) as u32;
+        image.get_pixel(x, y).0
+    };
+    let (a, b) = (pixel(x0, y0), pixel(x0 + 1.0, y0));
+    let (c, d) = (pixel(x0, y0 + 1.0), pixel(x0 + 1.0, y0 + 1.0));
+    std::array::from_fn(|channel| {
+        let top = a[channel] * (1.0 - fx) + b[channel] * fx;
+        let bottom = c[channel] * (1.0 - fx) + d[channel] * fx;
+        top * (1.0 - fy) + bottom * fy
+    })
 }
 
 impl ResourceLoader for TextureResourceLoader {
     type Resource = image::DynamicImage;
-    type Error = crate::errors::Error;
+    type Error = Error;
     fn label(&self) -> &'static str {
         "Texture Resource Loader"
     }
@@ -134,4 +394,7 @@ impl ResourceLoader for TextureResourceLoader {
         let image = image::load_from_memory(&bytes)?;
         Ok(image)
     }
+    fn accepts(&self, path: &str) -> bool {
+        !path.ends_with(&format!(".{}", SIDECAR_EXTENSION))
+    }
 }
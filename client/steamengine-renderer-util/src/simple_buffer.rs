@@ -1,8 +1,10 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use steamengine_renderer::Renderer;
 use tracing::*;
 use wgpu::Buffer;
 use wgpu::BufferUsages;
+use wgpu::DownlevelFlags;
+use wgpu::Features;
 use wgpu::util::DrawIndexedIndirectArgs;
 
 /// Abstraction of a buffer
@@ -12,7 +14,7 @@ pub trait SimpleBuffer<'a, T: bytemuck::NoUninit> {
     fn new(renderer: Arc<Renderer<'a>>, limit: u64) -> Self;
     /// Sets a unic entry inside the buffer
     fn set(&self, index: u64, data: T) {
-        if index > self.limit() {
+        if !fits(index, 1, self.limit()) {
             error!(
                 "attempt to nest an entity outside the limits of the buffer, SimpleBuffer Overflow"
             );
@@ -23,7 +25,7 @@ pub trait SimpleBuffer<'a, T: bytemuck::NoUninit> {
     }
     /// Sets all the buffer
     fn set_all(&self, data: &[T]) {
-        if data.len() as u64 > self.limit() {
+        if !fits(0, data.len() as u64, self.limit()) {
             error!(
                 "attempt to nest an entity outside the limits of the buffer, SimpleBuffer Overflow"
             );
@@ -32,7 +34,7 @@ pub trait SimpleBuffer<'a, T: bytemuck::NoUninit> {
         self.renderer().update_buffer(self.buffer(), data);
     }
     /// Converts the buffert to a binding resource
-    fn as_entrie(&self) -> wgpu::BindingResource {
+    fn as_entrie(&self) -> wgpu::BindingResource<'_> {
         self.buffer().as_entire_binding()
     }
     /// Gets the buffer
@@ -41,7 +43,27 @@ pub trait SimpleBuffer<'a, T: bytemuck::NoUninit> {
     fn limit(&self) -> u64;
 }
 
+/// returns true if `len` entries from `first` fit in a buffer of `limit` entries
+fn fits(first: u64, len: u64, limit: u64) -> bool {
+    first.checked_add(len).is_some_and(|end| end <= limit)
+}
+
+/// Sets a command of the CPU copy, the gaps are filled with empty commands
+fn set_command(
+    commands: &mut Vec<DrawIndexedIndirectArgs>,
+    index: usize,
+    data: DrawIndexedIndirectArgs,
+) {
+    if commands.len() <= index {
+        commands.resize(index + 1, DrawIndexedIndirectArgs::default());
+    }
+    commands[index] = data;
+}
+
 /// Implementation of simple buffer for commands buffer
+/// The commands are also kept in the CPU to draw them when the device can't draw indirect
+/// The CPU copy only has the commands of `set` and `set_all`, the commands written in the GPU,
+/// Ex: by a compute pass, aren't drawn in `DrawQueueMode::Direct`
 pub struct DrawQueueBuffer<'a> {
     /// Wgpu Buffer
     buffer: Buffer,
@@ -49,13 +71,32 @@ pub struct DrawQueueBuffer<'a> {
     renderer: Arc<Renderer<'a>>,
     /// Limit of the buffer
     limit: u64,
+    /// Copy of the commands in the CPU
+    commands: Mutex<Vec<DrawIndexedIndirectArgs>>,
+}
+impl DrawQueueBuffer<'_> {
+    /// Gets the commands from the CPU copy
+    pub fn commands(&self) -> Vec<DrawIndexedIndirectArgs> {
+        self.commands
+            .lock()
+            .expect("Cannot lock draw queue")
+            .clone()
+    }
 }
 impl<'a> SimpleBuffer<'a, DrawIndexedIndirectArgs> for DrawQueueBuffer<'a> {
     fn new(renderer: Arc<Renderer<'a>>, limit: u64) -> Self {
         let lock = renderer.clone();
+        let mut usage = BufferUsages::INDIRECT | BufferUsages::COPY_DST;
+        // the commands can be written by compute passes, Ex: GPU culling
+        if lock
+            .downlevel_flags()
+            .contains(DownlevelFlags::COMPUTE_SHADERS)
+        {
+            usage |= BufferUsages::STORAGE;
+        }
         let buffer = lock.create_buffer(
             "Indexed Indirect Buffer",
-            BufferUsages::INDIRECT | BufferUsages::COPY_DST,
+            usage,
             limit * std::mem::size_of::<DrawIndexedIndirectArgs>() as u64,
         );
 
@@ -63,8 +104,33 @@ impl<'a> SimpleBuffer<'a, DrawIndexedIndirectArgs> for DrawQueueBuffer<'a> {
             buffer,
             renderer,
             limit,
+            commands: Mutex::new(Vec::new()),
         }
     }
+    fn set(&self, index: u64, data: DrawIndexedIndirectArgs) {
+        if !fits(index, 1, self.limit()) {
+            error!(
+                "attempt to nest an entity outside the limits of the buffer, SimpleBuffer Overflow"
+            );
+            return;
+        }
+        set_command(
+            &mut self.commands.lock().expect("Cannot lock draw queue"),
+            index as usize,
+            data,
+        );
+        self.renderer.update_buffer_entry(&self.buffer, index, data);
+    }
+    fn set_all(&self, data: &[DrawIndexedIndirectArgs]) {
+        if !fits(0, data.len() as u64, self.limit()) {
+            error!(
+                "attempt to nest an entity outside the limits of the buffer, SimpleBuffer Overflow"
+            );
+            return;
+        }
+        *self.commands.lock().expect("Cannot lock draw queue") = data.to_vec();
+        self.renderer.update_buffer(&self.buffer, data);
+    }
     fn buffer(&self) -> &wgpu::Buffer {
         &self.buffer
     }
@@ -75,3 +141,131 @@ impl<'a> SimpleBuffer<'a, DrawIndexedIndirectArgs> for DrawQueueBuffer<'a> {
         self.limit
     }
 }
+
+/// Way to draw a `DrawQueueBuffer` with the features of the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawQueueMode {
+    /// A single `multi_draw_indexed_indirect`, needs `Features::MULTI_DRAW_INDIRECT`
+    MultiDrawIndirect,
+    /// A `draw_indexed_indirect` per command, needs indirect execution and
+    /// `Features::INDIRECT_FIRST_INSTANCE`
+    DrawIndirect,
+    /// A `draw_indexed` per command with the CPU copy of the commands
+    /// The commands written in the GPU are ignored, `GpuCulling` is disabled in this mode
+    Direct,
+}
+impl DrawQueueMode {
+    /// Gets the best mode supported by the renderer
+    pub fn of(renderer: &Renderer) -> Self {
+        Self::from_features(renderer.features(), renderer.downlevel_flags())
+    }
+    /// Gets the best mode supported by the features of a device
+    pub fn from_features(features: Features, downlevel: DownlevelFlags) -> Self {
+        if features.contains(Features::MULTI_DRAW_INDIRECT) {
+            Self::MultiDrawIndirect
+        } else if features.contains(Features::INDIRECT_FIRST_INSTANCE)
+            && downlevel.contains(DownlevelFlags::INDIRECT_EXECUTION)
+        {
+            Self::DrawIndirect
+        } else {
+            Self::Direct
+        }
+    }
+}
+
+/// Function to draw a `DrawQueueBuffer` in a render pass
+/// Uses `multi_draw_indexed_indirect` when the device supports it, otherwise
+/// emulates it with one draw per command
+pub trait RenderPassDrawQueue {
+    fn draw_queue(&mut self, queue: &DrawQueueBuffer, count: u32);
+}
+impl RenderPassDrawQueue for wgpu::RenderPass<'_> {
+    fn draw_queue(&mut self, queue: &DrawQueueBuffer, count: u32) {
+        let size = std::mem::size_of::<DrawIndexedIndirectArgs>() as u64;
+        match DrawQueueMode::of(&queue.renderer) {
+            DrawQueueMode::MultiDrawIndirect => {
+                self.multi_draw_indexed_indirect(&queue.buffer, 0, count)
+            }
+            DrawQueueMode::DrawIndirect => {
+                for index in 0..count as u64 {
+                    self.draw_indexed_indirect(&queue.buffer, index * size);
+                }
+            }
+            DrawQueueMode::Direct => {
+                let commands = queue.commands.lock().expect("Cannot lock draw queue");
+                for command in commands.iter().take(count as usize) {
+                    self.draw_indexed(
+                        command.first_index..command.first_index + command.index_count,
+                        command.base_vertex,
+                        command.first_instance..command.first_instance + command.instance_count,
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_mode_is_picked_from_the_features() {
+        let indirect = DownlevelFlags::INDIRECT_EXECUTION;
+        assert_eq!(
+            DrawQueueMode::from_features(Features::MULTI_DRAW_INDIRECT, DownlevelFlags::empty()),
+            DrawQueueMode::MultiDrawIndirect
+        );
+        assert_eq!(
+            DrawQueueMode::from_features(
+                Features::MULTI_DRAW_INDIRECT | Features::INDIRECT_FIRST_INSTANCE,
+                indirect
+            ),
+            DrawQueueMode::MultiDrawIndirect
+        );
+        assert_eq!(
+            DrawQueueMode::from_features(Features::INDIRECT_FIRST_INSTANCE, indirect),
+            DrawQueueMode::DrawIndirect
+        );
+        // the draws without the first instance or the indirect execution are direct
+        assert_eq!(
+            DrawQueueMode::from_features(
+                Features::INDIRECT_FIRST_INSTANCE,
+                DownlevelFlags::empty()
+            ),
+            DrawQueueMode::Direct
+        );
+        assert_eq!(
+            DrawQueueMode::from_features(Features::empty(), indirect),
+            DrawQueueMode::Direct
+        );
+    }
+
+    #[test]
+    fn the_limit_is_outside_the_buffer() {
+        assert!(fits(3, 1, 4));
+        assert!(!fits(4, 1, 4));
+        assert!(!fits(5, 1, 4));
+        assert!(fits(0, 4, 4));
+        assert!(!fits(0, 5, 4));
+        assert!(fits(0, 0, 0));
+        assert!(!fits(u64::MAX, 1, u64::MAX));
+    }
+
+    #[test]
+    fn the_cpu_copy_fills_the_gaps() {
+        let command = DrawIndexedIndirectArgs {
+            index_count: 6,
+            instance_count: 2,
+            ..Default::default()
+        };
+        let mut commands = Vec::new();
+        set_command(&mut commands, 2, command);
+        assert_eq!(commands.len(), 3);
+        assert_eq!(commands[0].index_count, 0);
+        assert_eq!(commands[2].instance_count, 2);
+        set_command(&mut commands, 0, command);
+        assert_eq!(commands.len(), 3);
+        assert_eq!(commands[0].index_count, 6);
+    }
+}
@@ -0,0 +1,233 @@
+use std::num::NonZero;
+use std::sync::{Arc, Mutex};
+use steamengine_renderer::Renderer;
+use steamengine_renderer::bind_group::BindGroupEntryBuilder;
+use tracing::*;
+use wgpu::{Buffer, BufferBinding, BufferUsages};
+
+/// Regions of the frames and values pushed in the current frame that are not uploaded yet
+struct RingState {
+    /// size of the binding, the maximum size of a value
+    binding_size: u64,
+    /// size of a value aligned to the offset alignment
+    stride: u64,
+    /// size of the region of a frame
+    frame_size: u64,
+    frames: u64,
+    frame: u64,
+    /// offset of the next value inside the frame
+    offset: u64,
+    data: Vec<u8>,
+}
+impl RingState {
+    fn new(binding_size: u64, capacity: u64, frames: u64, alignment: u64) -> Self {
+        let stride = binding_size.div_ceil(alignment) * alignment;
+        Self {
+            binding_size,
+            stride,
+            frame_size: stride * capacity,
+            frames: frames.max(1),
+            frame: 0,
+            offset: 0,
+            data: Vec::new(),
+        }
+    }
+    /// Pushes a value in the current frame, returns the dynamic offset of the value
+    fn push(&mut self, bytes: &[u8]) -> Option<u32> {
+        if bytes.len() as u64 > self.binding_size {
+            error!(
+                "attempt to push a value bigger than the binding of the ring, UniformRing Overflow"
+            );
+            return None;
+        }
+        if self.offset + self.stride > self.frame_size {
+            error!(
+                "attempt to push more values than the capacity of the ring, UniformRing Overflow"
+            );
+            return None;
+        }
+        let offset = self.offset;
+        let start = self.data.len();
+        self.data.resize(start + self.stride as usize, 0);
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+        self.offset += self.stride;
+        Some((self.frame * self.frame_size + offset) as u32)
+    }
+    /// Takes the values pushed since the last call with the offset of the first one
+    fn take_pending(&mut self) -> Option<(u64, Vec<u8>)> {
+        if self.data.is_empty() {
+            return None;
+        }
+        // the pending values end at the current offset of the frame
+        let start = self.frame * self.frame_size + self.offset - self.data.len() as u64;
+        Some((start, std::mem::take(&mut self.data)))
+    }
+    /// Moves to the region of the next frame, the pending values must be taken before
+    fn next_frame(&mut self) {
+        self.frame = (self.frame + 1) % self.frames;
+        self.offset = 0;
+    }
+}
+
+/// Ring of uniform buffers with dynamic offsets
+/// Every frame has its own region of the buffer, the values are pushed in the region aligned to
+/// `min_uniform_buffer_offset_alignment` and bound with the returned offset, so all the objects
+/// share a single bind group
+/// ## Example
+/// ```rust,ignore
+/// let uniforms = UniformRing::new(renderer.clone(), "Objects", size_of::<Object>() as u64, 1024, 2);
+/// let bindings = renderer.new_bindings("Objects", &[uniforms.entry(0).on(ShaderStages::VERTEX)]);
+///
+/// uniforms.begin_frame();
+/// let offsets: Vec<u32> = objects.iter().map(|object| uniforms.push(object).unwrap()).collect();
+/// uniforms.flush();
+///
+/// for (object, offset) in objects.iter().zip(offsets) {
+///     render_pass.set_bind_group(2, bindings.bind().as_ref(), &[offset]);
+///     // draw the object
+/// }
+/// ```
+pub struct UniformRing<'a> {
+    renderer: Arc<Renderer<'a>>,
+    buffer: Buffer,
+    /// size of the binding, the maximum size of a value
+    binding_size: u64,
+    state: Mutex<RingState>,
+}
+impl<'a> UniformRing<'a> {
+    /// create a new ring with `capacity` values of `binding_size` bytes per frame
+    /// `frames` is the number of frames that use the ring at the same time, Ex: 2 or 3
+    pub fn new(
+        renderer: Arc<Renderer<'a>>,
+        label: &str,
+        binding_size: u64,
+        capacity: u64,
+        frames: u64,
+    ) -> Self {
+        let alignment = renderer
+            .device()
+            .limits()
+            .min_uniform_buffer_offset_alignment as u64;
+        let state = RingState::new(binding_size, capacity, frames, alignment);
+        let buffer = renderer.create_buffer(
+            label,
+            BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            state.frame_size * state.frames,
+        );
+        Self {
+            renderer,
+            buffer,
+            binding_size,
+            state: Mutex::new(state),
+        }
+    }
+    /// Moves to the region of the next frame, the values of the last frame are uploaded
+    pub fn begin_frame(&self) {
+        self.flush();
+        self.state
+            .lock()
+            .expect("Cannot lock uniform ring")
+            .next_frame();
+    }
+    /// Pushes a value in the current frame, returns the dynamic offset of the value
+    /// returns None if the region of the frame is full
+    pub fn push<T: bytemuck::NoUninit>(&self, value: &T) -> Option<u32> {
+        self.state
+            .lock()
+            .expect("Cannot lock uniform ring")
+            .push(bytemuck::bytes_of(value))
+    }
+    /// Uploads the values pushed since the last flush
+    pub fn flush(&self) {
+        let pending = self
+            .state
+            .lock()
+            .expect("Cannot lock uniform ring")
+            .take_pending();
+        if let Some((offset, data)) = pending {
+            self.renderer
+                .queue()
+                .write_buffer(&self.buffer, offset, &data);
+        }
+    }
+    /// Gets the entry of the bind group with the ring, visible in the vertex shader by default
+    pub fn entry(&self, binding: u32) -> BindGroupEntryBuilder<'_> {
+        BindGroupEntryBuilder::new(binding)
+            .on(wgpu::ShaderStages::VERTEX)
+            .uniform()
+            .has_dynamic_offset(true)
+            .min_binding_size(NonZero::new(self.binding_size))
+            .with(wgpu::BindingResource::Buffer(BufferBinding {
+                buffer: &self.buffer,
+                offset: 0,
+                size: NonZero::new(self.binding_size),
+            }))
+    }
+    /// Gets the buffer
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+    /// Gets the number of values that fit in a frame
+    pub fn capacity(&self) -> u64 {
+        let state = self.state.lock().expect("Cannot lock uniform ring");
+        state.frame_size / state.stride
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_aligned_to_the_offset_alignment() {
+        let mut state = RingState::new(80, 4, 2, 256);
+        assert_eq!(state.stride, 256);
+        assert_eq!(state.frame_size, 1024);
+        assert_eq!(state.push(&[1; 80]), Some(0));
+        assert_eq!(state.push(&[2; 4]), Some(256));
+
+        // the values are padded to the stride
+        let (offset, data) = state.take_pending().unwrap();
+        assert_eq!(offset, 0);
+        assert_eq!(data.len(), 512);
+        assert!(data[..80].iter().all(|byte| *byte == 1));
+        assert!(data[80..256].iter().all(|byte| *byte == 0));
+        assert_eq!(data[256..260], [2; 4]);
+        assert!(state.take_pending().is_none());
+
+        // a binding of a multiple of the alignment isn't padded
+        assert_eq!(RingState::new(512, 1, 1, 256).stride, 512);
+    }
+
+    #[test]
+    fn pushes_past_the_capacity_or_the_binding_fail() {
+        let mut state = RingState::new(16, 2, 1, 256);
+        assert_eq!(state.push(&[0; 32]), None);
+        assert_eq!(state.push(&[0; 16]), Some(0));
+        assert_eq!(state.push(&[0; 16]), Some(256));
+        assert_eq!(state.push(&[0; 16]), None);
+        assert_eq!(state.take_pending().unwrap().1.len(), 512);
+    }
+
+    #[test]
+    fn begin_frame_moves_to_the_next_region() {
+        let mut state = RingState::new(16, 2, 3, 64);
+        assert_eq!(state.push(&[0; 16]), Some(0));
+        assert_eq!(state.push(&[0; 16]), Some(64));
+        state.take_pending();
+        state.next_frame();
+        // the full region of the last frame doesn't limit the next one
+        assert_eq!(state.push(&[0; 16]), Some(128));
+        state.take_pending();
+        // the values pushed after a flush start at the offset of the frame
+        assert_eq!(state.push(&[0; 16]), Some(192));
+        assert_eq!(state.take_pending().unwrap().0, 192);
+        state.next_frame();
+        assert_eq!(state.push(&[0; 16]), Some(256));
+        state.take_pending();
+        // the ring wraps to the first region
+        state.next_frame();
+        assert_eq!(state.push(&[0; 16]), Some(0));
+        assert_eq!(state.take_pending().unwrap().0, 0);
+    }
+}
@@ -1,6 +1,15 @@
+use std::collections::HashMap;
 use std::num::NonZero;
+use std::sync::{Arc, RwLock};
 
-use wgpu::{BindingResource, BindingType, BufferBindingType, SamplerBindingType, ShaderStages};
+use tracing::*;
+use wgpu::{
+    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+    BufferBindingType, BufferUsages, SamplerBindingType, ShaderStages, StorageTextureAccess,
+    TextureFormat, TextureSampleType, TextureViewDimension,
+};
+
+use super::errors::BindGroupError;
 
 /// this a builder for create a new bind group
 pub struct BindGroupEntryBuilder<'a> {
@@ -42,6 +51,76 @@ impl<'a> BindGroupEntryBuilder<'a> {
         self.count = Some(count);
         self
     }
+    /// sets if the buffer is bound with a dynamic offset, Ex: `set_bind_group(0, &bind_group, &[offset])`
+    /// call it after the type of the bind group, `uniform` resets it
+    pub fn has_dynamic_offset(mut self, dynamic: bool) -> Self {
+        match &mut self.ty {
+            BindingType::Buffer {
+                has_dynamic_offset, ..
+            } => *has_dynamic_offset = dynamic,
+            _ => warn!(
+                "Dynamic offset in a binding that isn't a buffer -- {}",
+                self.binding
+            ),
+        }
+        self
+    }
+    /// sets the minimum size of the buffer binding
+    pub fn min_binding_size(mut self, size: Option<NonZero<u64>>) -> Self {
+        match &mut self.ty {
+            BindingType::Buffer {
+                min_binding_size, ..
+            } => *min_binding_size = size,
+            _ => warn!(
+                "Minimum binding size in a binding that isn't a buffer -- {}",
+                self.binding
+            ),
+        }
+        self
+    }
+    /// sets the minimum size of the buffer binding to the size of `T`
+    pub fn sized<T>(self) -> Self {
+        self.min_binding_size(NonZero::new(std::mem::size_of::<T>() as u64))
+    }
+    /// sets the dimension of the texture binding
+    pub fn dimension(mut self, dimension: TextureViewDimension) -> Self {
+        match &mut self.ty {
+            BindingType::Texture { view_dimension, .. }
+            | BindingType::StorageTexture { view_dimension, .. } => *view_dimension = dimension,
+            _ => warn!(
+                "View dimension in a binding that isn't a texture -- {}",
+                self.binding
+            ),
+        }
+        self
+    }
+    /// sets the sample type of the texture binding, Ex: `Uint` for `texture_2d<u32>`
+    pub fn sample_type(mut self, sample_type: TextureSampleType) -> Self {
+        match &mut self.ty {
+            BindingType::Texture {
+                sample_type: value, ..
+            } => *value = sample_type,
+            _ => warn!(
+                "Sample type in a binding that isn't a texture -- {}",
+                self.binding
+            ),
+        }
+        self
+    }
+    /// sets if the texture binding is multisampled
+    pub fn multisampled(mut self, multisampled: bool) -> Self {
+        match &mut self.ty {
+            BindingType::Texture {
+                multisampled: value,
+                ..
+            } => *value = multisampled,
+            _ => warn!(
+                "Multisampled in a binding that isn't a texture -- {}",
+                self.binding
+            ),
+        }
+        self
+    }
     pub fn uniform(self) -> Self {
         self.of(BindingType::Buffer {
             ty: BufferBindingType::Uniform,
@@ -49,4 +128,359 @@ impl<'a> BindGroupEntryBuilder<'a> {
             min_binding_size: None,
         })
     }
+    /// storage buffer, `var<storage, read>` if it is read only or `var<storage, read_write>`
+    pub fn storage(self, read_only: bool) -> Self {
+        self.of(BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        })
+    }
+    /// 2D storage texture with the access and format
+    pub fn storage_texture(self, access: StorageTextureAccess, format: TextureFormat) -> Self {
+        self.of(BindingType::StorageTexture {
+            access,
+            format,
+            view_dimension: TextureViewDimension::D2,
+        })
+    }
+    /// 2D filterable float texture
+    pub fn texture(self) -> Self {
+        self.of(BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        })
+    }
+    /// 2D depth texture, `texture_depth_2d`
+    pub fn depth_texture(self) -> Self {
+        self.of(BindingType::Texture {
+            sample_type: TextureSampleType::Depth,
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        })
+    }
+    /// filtering sampler
+    pub fn sampler(self) -> Self {
+        self.of(BindingType::Sampler(SamplerBindingType::Filtering))
+    }
+    /// comparison sampler, `sampler_comparison`, Ex: shadow maps
+    pub fn comparison_sampler(self) -> Self {
+        self.of(BindingType::Sampler(SamplerBindingType::Comparison))
+    }
+    /// gets the entry of the layout, without the resource
+    pub fn layout_entry(&self) -> BindGroupLayoutEntry {
+        BindGroupLayoutEntry {
+            binding: self.binding,
+            visibility: self.visibility,
+            ty: self.ty,
+            count: self.count,
+        }
+    }
+    /// checks that the resource matches the type of the binding
+    pub fn validate(&self) -> Result<(), BindGroupError> {
+        let binding = self.binding;
+        let resource = self
+            .resource
+            .as_ref()
+            .ok_or(BindGroupError::MissingResource(binding))?;
+        let (found, len) = match resource {
+            BindingResource::Buffer(_) => ("a buffer", None),
+            BindingResource::BufferArray(buffers) => ("a buffer", Some(buffers.len())),
+            BindingResource::Sampler(_) => ("a sampler", None),
+            BindingResource::SamplerArray(samplers) => ("a sampler", Some(samplers.len())),
+            BindingResource::TextureView(_) => ("a texture", None),
+            BindingResource::TextureViewArray(views) => ("a texture", Some(views.len())),
+            BindingResource::AccelerationStructure(_) => ("an acceleration structure", None),
+            _ => ("an unknown resource", None),
+        };
+        let expected = match self.ty {
+            BindingType::Buffer { .. } => "a buffer",
+            BindingType::Sampler(_) => "a sampler",
+            BindingType::Texture { .. } | BindingType::StorageTexture { .. } => "a texture",
+            BindingType::AccelerationStructure { .. } => "an acceleration structure",
+        };
+        if expected != found {
+            return Err(BindGroupError::ResourceMismatch {
+                binding,
+                expected,
+                found,
+            });
+        }
+        match (self.count, len) {
+            (None, None) => {}
+            (Some(count), Some(len)) if count.get() as usize == len => {}
+            (count, len) => {
+                return Err(BindGroupError::CountMismatch {
+                    binding,
+                    count: count.map_or(1, NonZero::get),
+                    found: len.unwrap_or(1) as u32,
+                });
+            }
+        }
+
+        if let BindingType::Buffer {
+            ty,
+            min_binding_size,
+            ..
+        } = self.ty
+        {
+            let usage = match ty {
+                BufferBindingType::Uniform => BufferUsages::UNIFORM,
+                BufferBindingType::Storage { .. } => BufferUsages::STORAGE,
+            };
+            let buffers = match resource {
+                BindingResource::Buffer(buffer) => std::slice::from_ref(buffer),
+                BindingResource::BufferArray(buffers) => buffers,
+                _ => &[],
+            };
+            for buffer in buffers {
+                if !buffer.buffer.usage().contains(usage) {
+                    return Err(BindGroupError::MissingUsage { binding, usage });
+                }
+                let remaining = buffer.buffer.size().checked_sub(buffer.offset).ok_or(
+                    BindGroupError::OffsetOutOfBounds {
+                        binding,
+                        offset: buffer.offset,
+                        size: buffer.buffer.size(),
+                    },
+                )?;
+                let size = buffer.size.map_or(remaining, NonZero::get);
+                if let Some(min) = min_binding_size
+                    && size < min.get()
+                {
+                    return Err(BindGroupError::BindingTooSmall {
+                        binding,
+                        size,
+                        min: min.get(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// This is a cache of bind group layouts, the layouts with the same entries are shared
+/// The entries are sorted by binding, so the order of the entries doesn't matter
+pub struct BindGroupLayoutCache {
+    layouts: RwLock<HashMap<Vec<BindGroupLayoutEntry>, Arc<BindGroupLayout>>>,
+}
+impl Default for BindGroupLayoutCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl BindGroupLayoutCache {
+    pub fn new() -> Self {
+        Self {
+            layouts: RwLock::new(HashMap::new()),
+        }
+    }
+    /// gets the layout of the entries, creates it if it isn't in the cache
+    /// the label is only used when the layout is created
+    pub fn get_or_create(
+        &self,
+        label: &str,
+        entries: &[BindGroupLayoutEntry],
+        device: &wgpu::Device,
+    ) -> Arc<BindGroupLayout> {
+        let mut key = entries.to_vec();
+        key.sort_by_key(|entry| entry.binding);
+        if let Some(layout) = self
+            .layouts
+            .read()
+            .expect("Cannot read bind group layouts")
+            .get(&key)
+        {
+            return layout.clone();
+        }
+        trace!("Creating bind group layout -- {}", label);
+        let layout = Arc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &key,
+        }));
+        self.layouts
+            .write()
+            .expect("Cannot write bind group layouts")
+            .entry(key)
+            .or_insert(layout)
+            .clone()
+    }
+    /// removes all the layouts, the bind groups and pipelines that use them keep working
+    pub fn clear(&self) {
+        self.layouts
+            .write()
+            .expect("Cannot write bind group layouts")
+            .clear();
+    }
+    /// number of cached layouts
+    pub fn len(&self) -> usize {
+        self.layouts
+            .read()
+            .expect("Cannot read bind group layouts")
+            .len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reflection::ShaderReflection;
+
+    #[test]
+    fn reflected_layout_is_shared_with_builder_layout() {
+        let (device, _queue) = crate::testing::device();
+        let cache = BindGroupLayoutCache::new();
+        let reflection = ShaderReflection::from_wgsl(
+            r#"
+            @group(0) @binding(0) var<uniform> camera: mat4x4<f32>;
+            @group(0) @binding(1) var diffuse: texture_2d<f32>;
+            @group(0) @binding(2) var diffuse_sampler: sampler;
+
+            @vertex
+            fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+                return camera * vec4<f32>(position, 1.0);
+            }
+
+            @fragment
+            fn fs_main() -> @location(0) vec4<f32> {
+                return textureSample(diffuse, diffuse_sampler, vec2<f32>(0.5));
+            }
+            "#,
+        )
+        .unwrap();
+        let groups = reflection.bind_group_layout_entries().unwrap();
+        let reflected = cache.get_or_create("reflected", &groups[0], &device);
+
+        // the builder entries in other order
+        let built = cache.get_or_create(
+            "built",
+            &[
+                BindGroupEntryBuilder::new(2).sampler().layout_entry(),
+                BindGroupEntryBuilder::new(0)
+                    .on(ShaderStages::VERTEX)
+                    .uniform()
+                    .layout_entry(),
+                BindGroupEntryBuilder::new(1).texture().layout_entry(),
+            ],
+            &device,
+        );
+        assert!(Arc::ptr_eq(&reflected, &built));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn different_entries_get_different_layouts() {
+        let (device, _queue) = crate::testing::device();
+        let cache = BindGroupLayoutCache::new();
+        let uniform = BindGroupEntryBuilder::new(0).uniform();
+        let a = cache.get_or_create("a", &[uniform.layout_entry()], &device);
+        let sized = BindGroupEntryBuilder::new(0).uniform().sized::<[f32; 16]>();
+        let b = cache.get_or_create("b", &[sized.layout_entry()], &device);
+        assert!(!Arc::ptr_eq(&a, &b));
+        assert_eq!(cache.len(), 2);
+    }
+
+    fn buffer(device: &wgpu::Device, usage: BufferUsages, size: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn binding(buffer: &wgpu::Buffer, offset: u64, size: Option<u64>) -> BindingResource<'_> {
+        BindingResource::Buffer(wgpu::BufferBinding {
+            buffer,
+            offset,
+            size: size.and_then(NonZero::new),
+        })
+    }
+
+    #[test]
+    fn buffers_need_the_usage_of_the_binding() {
+        let (device, _queue) = crate::testing::device();
+        let vertices = buffer(&device, BufferUsages::VERTEX, 64);
+        let entry = BindGroupEntryBuilder::new(3)
+            .uniform()
+            .with(binding(&vertices, 0, None));
+        assert!(matches!(
+            entry.validate(),
+            Err(BindGroupError::MissingUsage {
+                binding: 3,
+                usage: BufferUsages::UNIFORM
+            })
+        ));
+
+        let uniform = buffer(&device, BufferUsages::UNIFORM, 64);
+        let entry = BindGroupEntryBuilder::new(3)
+            .storage(true)
+            .with(binding(&uniform, 0, None));
+        assert!(matches!(
+            entry.validate(),
+            Err(BindGroupError::MissingUsage {
+                usage: BufferUsages::STORAGE,
+                ..
+            })
+        ));
+        let entry = BindGroupEntryBuilder::new(3)
+            .uniform()
+            .with(binding(&uniform, 0, None));
+        assert!(entry.validate().is_ok());
+    }
+
+    #[test]
+    fn bindings_smaller_than_the_min_size_are_rejected() {
+        let (device, _queue) = crate::testing::device();
+        let uniform = buffer(&device, BufferUsages::UNIFORM, 128);
+        let entry = |offset, size| {
+            BindGroupEntryBuilder::new(0)
+                .uniform()
+                .sized::<[f32; 16]>()
+                .with(binding(&uniform, offset, size))
+        };
+        assert!(entry(0, None).validate().is_ok());
+        assert!(entry(64, None).validate().is_ok());
+        assert!(entry(0, Some(64)).validate().is_ok());
+        // the rest of the buffer from the offset
+        assert!(matches!(
+            entry(96, None).validate(),
+            Err(BindGroupError::BindingTooSmall {
+                binding: 0,
+                size: 32,
+                min: 64
+            })
+        ));
+        // the explicit size of the binding
+        assert!(matches!(
+            entry(0, Some(32)).validate(),
+            Err(BindGroupError::BindingTooSmall { size: 32, .. })
+        ));
+    }
+
+    #[test]
+    fn offsets_past_the_end_of_the_buffer_are_rejected() {
+        let (device, _queue) = crate::testing::device();
+        let uniform = buffer(&device, BufferUsages::UNIFORM, 128);
+        let entry = |offset| {
+            BindGroupEntryBuilder::new(1)
+                .uniform()
+                .with(binding(&uniform, offset, None))
+        };
+        assert!(matches!(
+            entry(256).validate(),
+            Err(BindGroupError::OffsetOutOfBounds {
+                binding: 1,
+                offset: 256,
+                size: 128
+            })
+        ));
+        // the end of the buffer is an empty binding
+        assert!(entry(128).validate().is_ok());
+    }
 }
@@ -7,6 +7,22 @@ pub enum TextureError {
     Loading(#[from] ImageError),
     #[error("Error reading texture")]
     Reading(#[from] std::io::Error),
+    #[error("Format {0:?} can't be copied with this aspect, use DepthOnly or StencilOnly")]
+    UnsupportedFormat(wgpu::TextureFormat),
+    #[error("Bytes per row {bytes_per_row} is smaller than a row of the texture, {min}")]
+    RowPitch { bytes_per_row: u32, min: u32 },
+    #[error("Rows per image {rows_per_image} is smaller than the rows of the texture, {min}")]
+    RowsPerImage { rows_per_image: u32, min: u32 },
+    #[error("Texture data has {found} bytes but the layout needs {expected}")]
+    DataSize { expected: u64, found: u64 },
+    #[error("{layers} layers don't fit in the texture, the maximum is {max}")]
+    LayerCount { layers: usize, max: u32 },
+    #[error("Region is outside of the texture")]
+    RegionOutOfBounds,
+    #[error("Region isn't aligned to the blocks of the format, {0}x{1}")]
+    RegionAlignment(u32, u32),
+    #[error("Invalid import option in line {line}, {reason}")]
+    ImportOption { line: usize, reason: &'static str },
 }
 
 #[derive(Debug, Error)]
@@ -24,3 +40,68 @@ pub enum RendererSetupError {
     #[error("Failed to get device, {0}")]
     DeviceRequest(#[from] wgpu::RequestDeviceError),
 }
+
+#[derive(Debug, Error)]
+pub enum ReflectionError {
+    #[error("Failed to parse shader, {0}")]
+    Parse(String),
+    #[error("Failed to validate shader, {0}")]
+    Validation(String),
+    #[error("Entry point {0} not found in shader")]
+    MissingEntryPoint(String),
+    #[error("Binding {binding} of group {group} is not supported, {reason}")]
+    UnsupportedBinding {
+        group: u32,
+        binding: u32,
+        reason: String,
+    },
+    #[error("Vertex input at location {location} has an unsupported type {ty}")]
+    UnsupportedVertexInput { location: u32, ty: String },
+    #[error("No vertex buffer provides the attribute at location {0}")]
+    MissingVertexAttribute(u32),
+    #[error(
+        "Vertex attribute at location {location} is {found:?} but the shader expects {expected:?}"
+    )]
+    VertexFormatMismatch {
+        location: u32,
+        expected: wgpu::VertexFormat,
+        found: wgpu::VertexFormat,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum BindGroupError {
+    #[error("Resource of binding {0} not defined")]
+    MissingResource(u32),
+    #[error("Binding {binding} is declared as {expected} but the resource is {found}")]
+    ResourceMismatch {
+        binding: u32,
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("Binding {binding} is an array of {count} resources but has {found}")]
+    CountMismatch {
+        binding: u32,
+        count: u32,
+        found: u32,
+    },
+    #[error("Buffer of binding {binding} is missing the usage {usage:?}")]
+    MissingUsage {
+        binding: u32,
+        usage: wgpu::BufferUsages,
+    },
+    #[error("Buffer of binding {binding} has {size} bytes but needs at least {min}")]
+    BindingTooSmall { binding: u32, size: u64, min: u64 },
+    #[error("Buffer of binding {binding} has {size} bytes but is bound from the offset {offset}")]
+    OffsetOutOfBounds {
+        binding: u32,
+        offset: u64,
+        size: u64,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum PushConstantError {
+    #[error("Uniform fallback of {label} is full, it has {capacity} values per frame")]
+    Overflow { label: String, capacity: u64 },
+}
@@ -0,0 +1,473 @@
+use half::f16;
+use image::{DynamicImage, Rgba32FImage, RgbaImage, imageops::FilterType};
+use tracing::*;
+use wgpu::{Features, TextureFormat};
+
+use super::{
+    errors::TextureError,
+    mipmap::{linear_to_srgb, srgb_to_linear},
+    sampler::{SamplerFilter, SamplerPreset},
+};
+
+/// Suffixes of the file names that hold data instead of colors, Ex: `brick_normal.png`
+const LINEAR_SUFFIXES: &[&str] = &[
+    "normal",
+    "nrm",
+    "n",
+    "roughness",
+    "rough",
+    "metallic",
+    "metal",
+    "ao",
+    "orm",
+    "height",
+    "mask",
+    "linear",
+];
+
+const IDENTITY: [Channel; 4] = [Channel::R, Channel::G, Channel::B, Channel::A];
+
+/// How the values of the color channels are stored
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// colors, decoded to linear when they are sampled
+    Srgb,
+    /// data that is sampled as it is stored, Ex: normal or roughness maps
+    Linear,
+}
+
+/// Source of a channel in a swizzle
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Channel {
+    R,
+    G,
+    B,
+    A,
+    Zero,
+    One,
+}
+impl Channel {
+    fn parse(channel: char) -> Option<Self> {
+        match channel {
+            'r' => Some(Self::R),
+            'g' => Some(Self::G),
+            'b' => Some(Self::B),
+            'a' => Some(Self::A),
+            '0' => Some(Self::Zero),
+            '1' => Some(Self::One),
+            _ => None,
+        }
+    }
+    fn pick<T: Copy>(&self, pixel: [T; 4], zero: T, one: T) -> T {
+        match self {
+            Self::R => pixel[0],
+            Self::G => pixel[1],
+            Self::B => pixel[2],
+            Self::A => pixel[3],
+            Self::Zero => zero,
+            Self::One => one,
+        }
+    }
+}
+
+/// Options of an imported image, the default is an sRGB texture with a bilinear sampler
+/// The options of a file can be read from a sidecar with `key = value` lines
+/// ## Example
+/// ```rust
+/// use steamengine_renderer::import::{Channel, ColorSpace, ImportOptions};
+///
+/// let options = ImportOptions::from_name("textures/brick_roughness.png")
+///     .with_sidecar("# roughness in the green channel\nswizzle = ggg1\nmax_size = 512")
+///     .unwrap();
+/// assert_eq!(options.color_space, ColorSpace::Linear);
+/// assert_eq!(options.swizzle, [Channel::G, Channel::G, Channel::G, Channel::One]);
+/// assert_eq!(options.max_size, Some(512));
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ImportOptions {
+    pub color_space: ColorSpace,
+    pub flip_y: bool,
+    pub premultiply_alpha: bool,
+    pub swizzle: [Channel; 4],
+    /// the image is downscaled if the width or the height is bigger
+    pub max_size: Option<u32>,
+    pub mipmaps: bool,
+    pub sampler: SamplerPreset,
+    /// float images use `Rgba32Float` instead of `Rgba16Float`
+    pub full_float: bool,
+}
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl ImportOptions {
+    pub const fn new() -> Self {
+        Self {
+            color_space: ColorSpace::Srgb,
+            flip_y: false,
+            premultiply_alpha: false,
+            swizzle: IDENTITY,
+            max_size: None,
+            mipmaps: false,
+            sampler: SamplerPreset::BILINEAR,
+            full_float: false,
+        }
+    }
+    /// Default options of a file, the files that end with a suffix like `_normal` or `_roughness`
+    /// are linear
+    pub fn from_name(path: &str) -> Self {
+        let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+        let stem = name.split('.').next().unwrap_or(name).to_lowercase();
+        let mut options = Self::new();
+        if let Some((_, suffix)) = stem.rsplit_once(['_', '-'])
+            && LINEAR_SUFFIXES.contains(&suffix)
+        {
+            options.color_space = ColorSpace::Linear;
+        }
+        options
+    }
+    /// Overrides the options with the lines of a sidecar, the keys are `color_space`, `flip_y`,
+    /// `premultiply_alpha`, `swizzle`, `max_size`, `mipmaps`, `full_float`, `filter` and `address`
+    pub fn with_sidecar(mut self, text: &str) -> Result<Self, TextureError> {
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |reason| TextureError::ImportOption {
+                line: index + 1,
+                reason,
+            };
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(error("expected `key = value`"))?;
+            let value = value.trim().to_lowercase();
+            let flag = || match value.as_str() {
+                "true" | "yes" | "1" => Ok(true),
+                "false" | "no" | "0" => Ok(false),
+                _ => Err(error("expected true or false")),
+            };
+            match key.trim() {
+                "color_space" => {
+                    self.color_space = match value.as_str() {
+                        "srgb" => ColorSpace::Srgb,
+                        "linear" => ColorSpace::Linear,
+                        _ => return Err(error("expected srgb or linear")),
+                    }
+                }
+                "flip_y" => self.flip_y = flag()?,
+                "premultiply_alpha" => self.premultiply_alpha = flag()?,
+                "mipmaps" => self.mipmaps = flag()?,
+                "full_float" => self.full_float = flag()?,
+                "swizzle" => {
+                    let channels: Option<Vec<Channel>> =
+                        value.chars().map(Channel::parse).collect();
+                    self.swizzle = channels
+                        .and_then(|channels| channels.try_into().ok())
+                        .ok_or(error("expected four channels of rgba01"))?;
+                }
+                "max_size" => {
+                    self.max_size = match value.as_str() {
+                        "none" => None,
+                        _ => Some(
+                            value
+                                .parse()
+                                .map_err(|_| error("expected a size in pixels"))?,
+                        ),
+                    }
+                }
+                "filter" => {
+                    self.sampler.filter = match value.as_str() {
+                        "nearest" | "pixel_art" => SamplerFilter::Nearest,
+                        "bilinear" => SamplerFilter::Bilinear,
+                        "trilinear" => SamplerFilter::Trilinear,
+                        "anisotropic" => SamplerFilter::Anisotropic(16),
+                        _ => {
+                            return Err(error(
+                                "expected nearest, bilinear, trilinear or anisotropic",
+                            ));
+                        }
+                    }
+                }
+                "address" => {
+                    self.sampler = match value.as_str() {
+                        "clamp" => self.sampler.clamp(),
+                        "repeat" => self.sampler.repeat(),
+                        "mirror" => self.sampler.mirror(),
+                        _ => return Err(error("expected clamp, repeat or mirror")),
+                    }
+                }
+                _ => return Err(error("unknown key")),
+            }
+        }
+        Ok(self)
+    }
+    /// the image is stored as linear data, Ex: normal or roughness maps
+    pub fn linear(mut self) -> Self {
+        self.color_space = ColorSpace::Linear;
+        self
+    }
+    pub fn srgb(mut self) -> Self {
+        self.color_space = ColorSpace::Srgb;
+        self
+    }
+    /// flips the rows of the image, Ex: textures of models with the origin at the bottom
+    pub fn flip_y(mut self) -> Self {
+        self.flip_y = true;
+        self
+    }
+    /// multiplies the colors by the alpha
+    pub fn premultiply_alpha(mut self) -> Self {
+        self.premultiply_alpha = true;
+        self
+    }
+    /// picks the channels of the texture, Ex: `[Channel::G, Channel::G, Channel::G, Channel::One]`
+    pub fn swizzle(mut self, swizzle: [Channel; 4]) -> Self {
+        self.swizzle = swizzle;
+        self
+    }
+    /// downscales the images bigger than `max_size`, keeping the aspect ratio
+    pub fn max_size(mut self, max_size: u32) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+    pub fn mipmaps(mut self) -> Self {
+        self.mipmaps = true;
+        self
+    }
+    pub fn sampler(mut self, sampler: SamplerPreset) -> Self {
+        self.sampler = sampler;
+        self
+    }
+    /// loads float images in `Rgba32Float`, they are filtered only with
+    /// `Features::FLOAT32_FILTERABLE`, without it they use `Rgba16Float`
+    pub fn full_float(mut self) -> Self {
+        self.full_float = true;
+        self
+    }
+    /// returns true if the mip levels are generated, also when the sampler uses them
+    pub fn generates_mipmaps(&self) -> bool {
+        self.mipmaps || self.sampler.uses_mipmaps()
+    }
+    /// Gets the format of the 8 bit textures
+    pub fn format(&self) -> TextureFormat {
+        match self.color_space {
+            ColorSpace::Srgb => TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => TextureFormat::Rgba8Unorm,
+        }
+    }
+    /// Gets the format of the float textures, Ex: HDR or EXR images
+    pub fn float_format(&self, features: Features) -> TextureFormat {
+        match self.full_float {
+            true if features.contains(Features::FLOAT32_FILTERABLE) => TextureFormat::Rgba32Float,
+            true => {
+                warn!("Rgba32Float isn't filterable without FLOAT32_FILTERABLE, using Rgba16Float");
+                TextureFormat::Rgba16Float
+            }
+            false => TextureFormat::Rgba16Float,
+        }
+    }
+    /// Gets the format of the images with 16 bit channels, `Rgba16Unorm` needs
+    /// `Features::TEXTURE_FORMAT_16BIT_NORM`, without it the channels are stored as floats
+    pub fn unorm16_format(&self, features: Features) -> TextureFormat {
+        if features.contains(Features::TEXTURE_FORMAT_16BIT_NORM) {
+            TextureFormat::Rgba16Unorm
+        } else if features.contains(Features::FLOAT32_FILTERABLE) {
+            TextureFormat::Rgba32Float
+        } else {
+            TextureFormat::Rgba16Float
+        }
+    }
+    /// Converts the image into the pixels and the format of the texture
+    /// 16 bit and float images keep its precision and are stored as linear data, the 16 bit
+    /// images with the sRGB color space are decoded to linear, see `apply_f32`
+    pub fn import(&self, image: DynamicImage, features: Features) -> ImportedImage {
+        let format = match Precision::of(&image) {
+            Precision::Unorm8 => {
+                let image = self.apply(image);
+                return ImportedImage {
+                    format: self.format(),
+                    width: image.width(),
+                    height: image.height(),
+                    data: image.into_raw(),
+                };
+            }
+            Precision::Unorm16 => self.unorm16_format(features),
+            Precision::Float => self.float_format(features),
+        };
+        let image = self.apply_f32(image);
+        ImportedImage {
+            format,
+            width: image.width(),
+            height: image.height(),
+            data: encode(format, image.as_raw()),
+        }
+    }
+    /// Converts the image into the pixels of an 8 bit texture
+    pub fn apply(&self, image: DynamicImage) -> RgbaImage {
+        let mut image = self.downscale(image).to_rgba8();
+        if self.flip_y {
+            image::imageops::flip_vertical_in_place(&mut image);
+        }
+        if self.swizzle != IDENTITY || self.premultiply_alpha {
+            for pixel in image.pixels_mut() {
+                let source = pixel.0;
+                pixel.0 = self.swizzle.map(|channel| channel.pick(source, 0, 255));
+                if self.premultiply_alpha {
+                    self.premultiply(&mut pixel.0);
+                }
+            }
+        }
+        image
+    }
+    /// Converts the image into linear float pixels, the channels aren't clamped
+    /// the colors of the 8 and 16 bit images with the sRGB color space are decoded to linear,
+    /// the float images are already linear
+    pub fn apply_f32(&self, image: DynamicImage) -> Rgba32FImage {
+        let decode =
+            self.color_space == ColorSpace::Srgb && Precision::of(&image) != Precision::Float;
+        let mut image = self.downscale(image).into_rgba32f();
+        if decode {
+            for pixel in image.pixels_mut() {
+                pixel.0[..3]
+                    .iter_mut()
+                    .for_each(|value| *value = srgb_to_linear(*value));
+            }
+        }
+        if self.flip_y {
+            image::imageops::flip_vertical_in_place(&mut image);
+        }
+        if self.swizzle != IDENTITY || self.premultiply_alpha {
+            for pixel in image.pixels_mut() {
+                let source = pixel.0;
+                pixel.0 = self.swizzle.map(|channel| channel.pick(source, 0.0, 1.0));
+                if self.premultiply_alpha {
+                    let alpha = pixel.0[3];
+                    pixel.0[..3].iter_mut().for_each(|value| *value *= alpha);
+                }
+            }
+        }
+        image
+    }
+    fn downscale(&self, image: DynamicImage) -> DynamicImage {
+        match self.max_size {
+            Some(max_size) if image.width().max(image.height()) > max_size => {
+                trace!(
+                    "Downscaling image {}x{} to {}",
+                    image.width(),
+                    image.height(),
+                    max_size
+                );
+                let max_size = max_size.max(1);
+                image.resize(max_size, max_size, FilterType::Triangle)
+            }
+            _ => image,
+        }
+    }
+    /// sRGB colors are multiplied in linear space
+    fn premultiply(&self, pixel: &mut [u8; 4]) {
+        let alpha = pixel[3] as f32 / 255.0;
+        for value in &mut pixel[..3] {
+            let color = *value as f32 / 255.0;
+            let color = match self.color_space {
+                ColorSpace::Srgb => linear_to_srgb(srgb_to_linear(color) * alpha),
+                ColorSpace::Linear => color * alpha,
+            };
+            *value = (color * 255.0).round() as u8;
+        }
+    }
+}
+
+/// Pixels of an image in the format of its texture, rows without padding
+pub struct ImportedImage {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Precision of the channels of a decoded image, ordered from the lowest
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precision {
+    Unorm8,
+    /// Ex: 16 bit PNG or TIFF
+    Unorm16,
+    /// Ex: HDR or EXR
+    Float,
+}
+impl Precision {
+    pub fn of(image: &DynamicImage) -> Self {
+        match image {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => Self::Float,
+            DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_)
+            | DynamicImage::ImageRgb16(_)
+            | DynamicImage::ImageRgba16(_) => Self::Unorm16,
+            _ => Self::Unorm8,
+        }
+    }
+}
+
+/// Encodes RGBA float pixels in a format, the supported formats are `Rgba8Unorm`,
+/// `Rgba8UnormSrgb`, `Rgba16Unorm`, `Rgba16Float` and `Rgba32Float`
+/// the values are stored as they are, the unorm formats clamp them from 0 to 1
+/// ```rust
+/// use steamengine_renderer::import::encode;
+/// use wgpu::TextureFormat;
+///
+/// let data = encode(TextureFormat::Rgba16Float, &[1.0, 0.5, 0.0, 1.0]);
+/// assert_eq!(data, [0x00, 0x3c, 0x00, 0x38, 0x00, 0x00, 0x00, 0x3c]);
+/// ```
+pub fn encode(format: TextureFormat, pixels: &[f32]) -> Vec<u8> {
+    match format {
+        TextureFormat::Rgba32Float => pixels.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        TextureFormat::Rgba16Float => pixels
+            .iter()
+            .flat_map(|v| f16::from_f32(*v).to_le_bytes())
+            .collect(),
+        TextureFormat::Rgba16Unorm => pixels
+            .iter()
+            .flat_map(|v| ((v.clamp(0.0, 1.0) * 65535.0).round() as u16).to_le_bytes())
+            .collect(),
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => pixels
+            .iter()
+            .map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+            .collect(),
+        _ => panic!("Cannot encode float pixels in {:?}", format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    fn pixel(image: DynamicImage, options: ImportOptions) -> [f32; 4] {
+        options.apply_f32(image).get_pixel(0, 0).0
+    }
+
+    #[test]
+    fn apply_f32_decodes_srgb_colors() {
+        let unorm8 =
+            DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, Rgba([188, 0, 255, 128])));
+        let [r, g, b, a] = pixel(unorm8.clone(), ImportOptions::new());
+        assert!((r - 0.5).abs() < 0.01);
+        assert_eq!((g, b), (0.0, 1.0));
+        assert!((a - 128.0 / 255.0).abs() < 1e-6);
+        let [r, ..] = pixel(unorm8, ImportOptions::new().linear());
+        assert!((r - 188.0 / 255.0).abs() < 1e-6);
+
+        let unorm16 =
+            DynamicImage::ImageRgba16(ImageBuffer::from_pixel(1, 1, Rgba([48316, 0, 0, 65535])));
+        let [r, ..] = pixel(unorm16, ImportOptions::new());
+        assert!((r - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn apply_f32_keeps_float_images() {
+        let float =
+            DynamicImage::ImageRgba32F(ImageBuffer::from_pixel(1, 1, Rgba([0.5, 2.0, 0.0, 1.0])));
+        assert_eq!(pixel(float, ImportOptions::new()), [0.5, 2.0, 0.0, 1.0]);
+    }
+}
@@ -1,5 +1,8 @@
 use wgpu::VertexBufferLayout;
 
+/// Derive macro of `RawInstance`, computes the layout from the fields of the struct
+pub use steamengine_renderer_derive::RawInstance;
+
 pub trait Instance<A: RawInstance> {
     fn to_raw(&self) -> A;
 }
@@ -1,21 +1,28 @@
 /// This module constrains an api to communicate to WGPU
-use std::{fs::File, io::Read};
+use std::{fs::File, io::Read, sync::Arc};
 
 use bind_group::BindGroupEntryBuilder;
 use bytemuck::NoUninit;
-use errors::{RendererSetupError, TextureError};
+use errors::{BindGroupError, RendererSetupError, TextureError};
+use import::ImportOptions;
+use sampler::SamplerPreset;
 use texture::{Texture, TextureBuilder, TextureDimensions};
 use tracing::*;
 use vertex::Vertex;
 use wgpu::{
-    BackendOptions, Backends, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
-    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, InstanceFlags, PresentMode,
-    SurfaceTexture, TextureFormat, TextureView, TextureViewDescriptor, Trace,
+    BackendOptions, Backends, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, Buffer,
+    BufferDescriptor, BufferUsages, CommandEncoder, InstanceFlags, PresentMode, SurfaceTexture,
+    TextureFormat, TextureView, TextureViewDescriptor, Trace,
     util::{BufferInitDescriptor, DeviceExt},
 };
 
 use winit::window::Window;
 
+// the derive macros refer to this crate by its name
+extern crate self as steamengine_renderer;
+/// The wgpu version used by the renderer
+pub use wgpu;
+
 /// This module is an utility to build bind groups
 pub mod bind_group;
 /// This module contrains the errors
@@ -23,14 +30,33 @@ pub mod errors;
 /// This module contrains a macro to build a simple render pass
 #[macro_use]
 pub mod render_pass;
+/// This module contrains the options to import images into textures
+pub mod import;
 pub mod instances;
+/// This module contrains the generation of the mip levels of the textures
+pub mod mipmap;
+/// This module contrains a registry that caches the compiled pipelines
+pub mod pipeline_registry;
+/// This module contrains typed push constants with an uniform buffer fallback
+pub mod push_constants;
+/// This module contrains an utility to reflect the layouts of a shader
+pub mod reflection;
 /// This module contrains an utilities to create a render pipeline
 pub mod render_pipeline;
+/// This module contrains the textures that a render pass draws into
+pub mod render_target;
+/// This module contrains the sampler presets and a cache of samplers
+pub mod sampler;
+/// This module contrains the sources of the shaders
+pub mod shader;
 /// This module contrains a utility to create textures
 pub mod texture;
 /// This module contrains an utilities to load vertex
 pub mod vertex;
 
+#[cfg(test)]
+mod testing;
+
 /// This the builder of the renderer, in this builder you can to set the parameters of the renderer Ex: performance mode
 pub struct RendererBuilder {
     backends: Backends,
@@ -39,7 +65,9 @@ pub struct RendererBuilder {
     power_preference: wgpu::PowerPreference,
     force_fallback_adapter: bool,
     required_features: wgpu::Features,
+    optional_features: wgpu::Features,
     required_limits: wgpu::Limits,
+    max_push_constant_size: u32,
     memory_hints: wgpu::MemoryHints,
     surface_format: fn(caps: &wgpu::SurfaceCapabilities) -> wgpu::TextureFormat,
     usage: wgpu::TextureUsages,
@@ -58,7 +86,9 @@ impl RendererBuilder {
             power_preference: wgpu::PowerPreference::default(),
             force_fallback_adapter: false,
             required_features: wgpu::Features::empty(),
+            optional_features: wgpu::Features::empty(),
             required_limits: wgpu::Limits::downlevel_defaults(),
+            max_push_constant_size: 0,
             memory_hints: wgpu::MemoryHints::default(),
             surface_format: |caps| {
                 caps.formats
@@ -103,6 +133,17 @@ impl RendererBuilder {
         self.required_features = required_features;
         self
     }
+    /// Sets the features that are requested only if the adapter supports them
+    pub fn optional_features(mut self, optional_features: wgpu::Features) -> Self {
+        self.optional_features = optional_features;
+        self
+    }
+    /// Request push constants of up to `max_size` bytes if the adapter supports them
+    pub fn push_constants(mut self, max_size: u32) -> Self {
+        self.optional_features |= wgpu::Features::PUSH_CONSTANTS;
+        self.max_push_constant_size = max_size;
+        self
+    }
     pub fn required_limits(mut self, required_limits: wgpu::Limits) -> Self {
         self.required_limits = required_limits;
         self
@@ -173,17 +214,29 @@ impl RendererBuilder {
             })
             .await?;
         let features = adapter.features();
+        let downlevel = adapter.get_downlevel_capabilities();
         if !features.contains(self.required_features) {
             error!("The device dont support the features")
         }
+        // the formats can use the features of the adapter, Ex: the mipmaps of Rgba8Snorm are
+        // rendered where the adapter can render Rgba8Snorm
+        let optional_features = features
+            & (self.optional_features | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        let mut required_limits = self.required_limits;
+        if optional_features.contains(wgpu::Features::PUSH_CONSTANTS) {
+            required_limits.max_push_constant_size = required_limits
+                .max_push_constant_size
+                .max(self.max_push_constant_size)
+                .min(adapter.limits().max_push_constant_size);
+        }
         trace!("Adapter created");
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: None,
-                required_features: self.required_features,
+                required_features: self.required_features | optional_features,
                 // WebGL doesn't support all of wgpu's features, so if
                 // we're building for the web we'll have to disable some.
-                required_limits: self.required_limits,
+                required_limits,
                 memory_hints: self.memory_hints,
                 trace: self.trace,
             })
@@ -209,6 +262,7 @@ impl RendererBuilder {
         let surface = std::sync::RwLock::new(surface);
         let config = std::sync::RwLock::new(config);
         let size = std::sync::RwLock::new(size);
+        let sample_count = std::sync::RwLock::new(1);
         trace!("Renderer builded");
         Ok(Renderer {
             surface,
@@ -216,9 +270,20 @@ impl RendererBuilder {
             queue,
             config,
             size,
+            sample_count,
+            adapter,
+            downlevel,
+            pipelines: pipeline_registry::PipelineRegistry::new(),
+            layouts: bind_group::BindGroupLayoutCache::new(),
+            samplers: sampler::SamplerCache::new(),
         })
     }
 }
+impl Default for RendererBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 /// this struct contrais all the components to render
 pub struct Renderer<'a> {
     pub surface: std::sync::RwLock<wgpu::Surface<'a>>,
@@ -226,6 +291,13 @@ pub struct Renderer<'a> {
     pub queue: wgpu::Queue,
     pub config: std::sync::RwLock<wgpu::SurfaceConfiguration>,
     pub size: std::sync::RwLock<(u32, u32)>,
+    /// sample count of the default pipelines, 1 without MSAA
+    pub sample_count: std::sync::RwLock<u32>,
+    pub adapter: wgpu::Adapter,
+    pub downlevel: wgpu::DownlevelCapabilities,
+    pub pipelines: pipeline_registry::PipelineRegistry,
+    pub layouts: bind_group::BindGroupLayoutCache,
+    pub samplers: sampler::SamplerCache,
 }
 
 impl<'a> Renderer<'a> {
@@ -253,7 +325,7 @@ impl<'a> Renderer<'a> {
         ))
     }
     /// gets the surface
-    pub fn surface(&self) -> std::sync::RwLockReadGuard<wgpu::Surface> {
+    pub fn surface(&self) -> std::sync::RwLockReadGuard<'_, wgpu::Surface<'a>> {
         self.surface.read().expect("Cannot read surface")
     }
     /// gets the device
@@ -264,17 +336,83 @@ impl<'a> Renderer<'a> {
     pub fn queue(&self) -> &wgpu::Queue {
         &self.queue
     }
+    /// gets the features enabled in the device
+    pub fn features(&self) -> wgpu::Features {
+        self.device.features()
+    }
+    /// gets the features of a format in the device, the adapter specific features are used when
+    /// the device has `TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES`
+    pub fn format_features(&self, format: TextureFormat) -> wgpu::TextureFormatFeatures {
+        let features = self.features();
+        match features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+            true => self.adapter.get_texture_format_features(format),
+            false => format.guaranteed_format_features(features),
+        }
+    }
+    /// gets the capabilities that the adapter has under the WebGPU standard
+    pub fn downlevel_flags(&self) -> wgpu::DownlevelFlags {
+        self.downlevel.flags
+    }
     /// gets the config
-    pub fn config(&self) -> std::sync::RwLockReadGuard<wgpu::SurfaceConfiguration> {
+    pub fn config(&self) -> std::sync::RwLockReadGuard<'_, wgpu::SurfaceConfiguration> {
         self.config.read().expect("Cannot read config")
     }
     /// gets the size
     pub fn size(&self) -> (u32, u32) {
-        self.size.read().unwrap().clone()
+        *self.size.read().unwrap()
+    }
+    /// gets the compiled pipeline from the registry, compiles it the first time
+    pub fn pipeline<P: render_pipeline::RenderPipeline + ?Sized>(
+        &self,
+        pipeline: &P,
+    ) -> std::sync::Arc<wgpu::RenderPipeline> {
+        self.pipelines.get_or_create(pipeline, self)
+    }
+    /// gets the compiled pipeline from the registry, returns an error if the layout can't be
+    /// reflected
+    pub fn try_pipeline<P: render_pipeline::RenderPipeline + ?Sized>(
+        &self,
+        pipeline: &P,
+    ) -> Result<std::sync::Arc<wgpu::RenderPipeline>, errors::ReflectionError> {
+        self.pipelines.try_get_or_create(pipeline, self)
+    }
+    /// gets the sample count of the default pipelines
+    pub fn sample_count(&self) -> u32 {
+        *self.sample_count.read().expect("Cannot read sample count")
+    }
+    /// changes the sample count of the default pipelines, Ex: 4 to enable MSAA
+    /// the pipelines that use this count are removed from the registry, the ones with their own
+    /// `RenderPipeline::sample_count` are kept, the render passes must draw into targets with the
+    /// same count
+    pub fn set_sample_count(&self, sample_count: u32) {
+        let mut current = self
+            .sample_count
+            .write()
+            .expect("Cannot write sample count");
+        if *current == sample_count {
+            return;
+        }
+        *current = sample_count;
+        drop(current);
+        self.pipelines.invalidate_sample_count(sample_count);
+    }
+    /// changes the format of the surface, the pipelines that render to the old format are removed
+    /// from the registry
+    pub fn set_surface_format(&self, format: TextureFormat) {
+        let old = self.config().format;
+        if old == format {
+            return;
+        }
+        self.config.write().expect("Cannot write config").format = format;
+        self.surface
+            .write()
+            .expect("Cannot write surface")
+            .configure(&self.device, &self.config());
+        self.pipelines.invalidate_format(old);
     }
     pub fn resize(&self, new_size: &(u32, u32)) {
         if new_size.0 > 0 && new_size.1 > 0 {
-            *self.size.write().expect("Cannot write size") = new_size.clone();
+            *self.size.write().expect("Cannot write size") = *new_size;
             self.config.write().expect("Cannot write config").width = new_size.0;
             self.config.write().expect("Cannot write config").height = new_size.1;
             self.surface
@@ -302,48 +440,74 @@ impl<'a> Renderer<'a> {
             mapped_at_creation: false,
         })
     }
-    /// create a new bind group
+    /// create a new bind group, the layout is shared with the bind groups with the same entries
+    /// panics if a resource doesn't match the type of its binding, see `try_bind_group`
     pub fn bind_group(
         &self,
         label: &str,
         entries: &[BindGroupEntryBuilder],
-    ) -> (wgpu::BindGroup, wgpu::BindGroupLayout) {
+    ) -> (wgpu::BindGroup, Arc<BindGroupLayout>) {
+        self.try_bind_group(label, entries)
+            .unwrap_or_else(|err| panic!("Cannot create bind group {}, {}", label, err))
+    }
+    /// create a new bind group, checks that the resources match the types of the bindings
+    pub fn try_bind_group(
+        &self,
+        label: &str,
+        entries: &[BindGroupEntryBuilder],
+    ) -> Result<(wgpu::BindGroup, Arc<BindGroupLayout>), BindGroupError> {
         trace!("Renderer building bind group -- {}", label);
-        let layout_entries: Vec<wgpu::BindGroupLayoutEntry> = entries
+        let layout_entries: Vec<BindGroupLayoutEntry> = entries
             .iter()
-            .map(|entry| BindGroupLayoutEntry {
-                binding: entry.binding,
-                visibility: entry.visibility,
-                ty: entry.ty,
-                count: entry.count,
-            })
+            .map(BindGroupEntryBuilder::layout_entry)
             .collect();
         trace!("With {} entries -- {}", layout_entries.len(), label);
-        let layout = self
-            .device
-            .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some(label),
-                entries: &layout_entries,
-            });
-        trace!("Layout created -- {}", label);
+        let layout = self.bind_group_layout(label, &layout_entries);
+        let bind_group = self.bind_group_with_layout(label, &layout, entries)?;
+        Ok((bind_group, layout))
+    }
+    /// gets the layout of the entries from the cache, creates it if it isn't in the cache
+    pub fn bind_group_layout(
+        &self,
+        label: &str,
+        entries: &[BindGroupLayoutEntry],
+    ) -> Arc<BindGroupLayout> {
+        self.layouts.get_or_create(label, entries, &self.device)
+    }
+    /// create a new bind group with an existing layout, Ex: a layout of a pipeline
+    /// checks that the resources match the types of the bindings
+    pub fn bind_group_with_layout(
+        &self,
+        label: &str,
+        layout: &BindGroupLayout,
+        entries: &[BindGroupEntryBuilder],
+    ) -> Result<wgpu::BindGroup, BindGroupError> {
+        for entry in entries {
+            entry.validate()?;
+        }
         let entries: Vec<_> = entries
             .iter()
             .map(|entry| BindGroupEntry {
                 binding: entry.binding,
-                resource: entry
-                    .resource
-                    .clone()
-                    .expect("Resource binding in bind group not defined"),
+                resource: entry.resource.clone().expect("Resource validated before"),
             })
             .collect();
         trace!("{} Entries builded into BindGroupEntry", label);
         let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some(label),
-            layout: &layout,
+            layout,
             entries: &entries,
         });
         trace!("Finnish bind group creation -- {}", label);
-        (bind_group, layout)
+        Ok(bind_group)
+    }
+    /// gets the sampler of the descriptor, the samplers with the same descriptor are shared
+    pub fn sampler(&self, descriptor: &wgpu::SamplerDescriptor) -> wgpu::Sampler {
+        self.samplers.get_or_create(descriptor, &self.device)
+    }
+    /// gets the sampler of a preset
+    pub fn sampler_preset(&self, preset: SamplerPreset) -> wgpu::Sampler {
+        self.sampler(&preset.descriptor())
     }
     /// init a new texture
     pub fn init_texture(
@@ -354,6 +518,15 @@ impl<'a> Renderer<'a> {
     ) -> Texture {
         builder.build(label, view_formats, self)
     }
+    /// init a new texture, returns an error if the data doesn't match the layout
+    pub fn try_init_texture(
+        &self,
+        label: &'static str,
+        view_formats: Option<&'static [TextureFormat]>,
+        builder: TextureBuilder,
+    ) -> Result<Texture, TextureError> {
+        builder.try_build(label, view_formats, self)
+    }
     /// simple load a png texture from file
     pub fn simple_png_texture_file(&self, file: &mut File) -> Result<Texture, TextureError> {
         let mut buffer = Vec::new();
@@ -362,33 +535,54 @@ impl<'a> Renderer<'a> {
     }
     /// simple load a png texture from bytes
     pub fn simple_png_texture_bytes(&self, bytes: &[u8]) -> Result<Texture, TextureError> {
-        let diffuse_image = image::load_from_memory(bytes)?;
-        let diffuse_rgba = diffuse_image.to_rgba8();
-        use image::GenericImageView;
-        let dimensions = diffuse_image.dimensions();
+        self.simple_png_texture_bytes_with_preset(bytes, SamplerPreset::BILINEAR)
+    }
+    /// simple load a png texture from bytes with all the mip levels
+    pub fn simple_png_texture_bytes_with_mipmaps(
+        &self,
+        bytes: &[u8],
+    ) -> Result<Texture, TextureError> {
+        self.simple_png_texture_bytes_with_preset(bytes, SamplerPreset::TRILINEAR)
+    }
+    /// simple load a png texture from bytes with the sampler of the preset
+    /// the mip levels are generated if the preset uses them
+    pub fn simple_png_texture_bytes_with_preset(
+        &self,
+        bytes: &[u8],
+        preset: SamplerPreset,
+    ) -> Result<Texture, TextureError> {
+        self.simple_png_texture_bytes_with_options(bytes, &ImportOptions::new().sampler(preset))
+    }
+    /// simple load a png texture from bytes converted with the import options
+    pub fn simple_png_texture_bytes_with_options(
+        &self,
+        bytes: &[u8],
+        options: &ImportOptions,
+    ) -> Result<Texture, TextureError> {
+        let image = image::load_from_memory(bytes)?;
+        self.texture_from_image("texture", image, options)
+    }
+    /// init a texture with a view, a sampler and a bind group from an image
+    pub fn texture_from_image(
+        &self,
+        label: &'static str,
+        image: image::DynamicImage,
+        options: &ImportOptions,
+    ) -> Result<Texture, TextureError> {
+        let image = options.import(image, self.features());
 
-        let mut texture = self.init_texture(
-            "texture",
-            None,
-            TextureBuilder::new()
-                .dimension(TextureDimensions::D2(dimensions.0, dimensions.1))
-                .data(diffuse_rgba.to_vec()),
-        );
+        let mut builder = TextureBuilder::new()
+            .dimension(TextureDimensions::D2(image.width, image.height))
+            .format(image.format)
+            .data(image.data);
+        if options.generates_mipmaps() {
+            builder = builder.generate_mipmaps();
+        }
+        let mut texture = self.try_init_texture(label, None, builder)?;
 
         texture.texture_view(TextureViewDescriptor::default());
 
-        texture.texture_sampler(
-            wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
-            },
-            self,
-        );
+        texture.sampler_preset(options.sampler, self);
 
         texture.default_bind_group("texture bind group", self);
         Ok(texture)
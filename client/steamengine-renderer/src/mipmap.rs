@@ -0,0 +1,360 @@
+use tracing::*;
+use wgpu::{
+    Extent3d, TextureFormat, TextureFormatFeatureFlags, TextureFormatFeatures, TextureUsages,
+    TextureViewDescriptor,
+};
+
+use super::{
+    Renderer, bind_group::BindGroupEntryBuilder, render_pipeline::RenderPipeline,
+    sampler::SamplerPreset,
+};
+
+const SHADER: &str = r#"
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+
+// a triangle that covers the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(source, source_sampler, in.uv);
+}
+"#;
+
+/// Pipeline that renders a mip level from the previous one with a linear filter
+/// sRGB views are decoded when they are sampled and encoded when they are rendered, so the levels
+/// are filtered in linear space
+struct MipmapPipeline {
+    format: TextureFormat,
+}
+impl RenderPipeline for MipmapPipeline {
+    fn label(&self) -> &str {
+        "Mipmap Generator"
+    }
+    fn source(&self) -> crate::shader::ShaderSource<'_> {
+        SHADER.into()
+    }
+    fn targets(&self, _renderer: &Renderer) -> Vec<Option<wgpu::ColorTargetState>> {
+        vec![Some(self.format.into())]
+    }
+    fn primitive(&self) -> wgpu::PrimitiveState {
+        wgpu::PrimitiveState::default()
+    }
+    /// the mip levels are never multisampled
+    fn sample_count(&self) -> Option<u32> {
+        Some(1)
+    }
+}
+
+/// returns true if the mip levels of the format can be rendered by the GPU
+/// the format must be renderable and filterable in the device, Ex: Rgba8Snorm, Rgba16Unorm without
+/// `TEXTURE_FORMAT_16BIT_NORM` or Rgba32Float without `FLOAT32_FILTERABLE` use the CPU
+pub fn can_render(format: TextureFormat, renderer: &Renderer) -> bool {
+    renders(renderer.format_features(format))
+}
+
+fn renders(features: TextureFormatFeatures) -> bool {
+    features
+        .allowed_usages
+        .contains(TextureUsages::RENDER_ATTACHMENT)
+        && features
+            .flags
+            .contains(TextureFormatFeatureFlags::FILTERABLE)
+}
+
+/// returns true if the mip levels of the format can be generated in the CPU
+pub fn can_downsample(format: TextureFormat) -> bool {
+    channels(format).is_some()
+}
+
+/// Encoding of a channel in the formats supported by the CPU fallback
+#[derive(Copy, Clone)]
+enum Channel {
+    Unorm8,
+    Snorm8,
+    Unorm16,
+    Snorm16,
+    Float16,
+    Float32,
+}
+impl Channel {
+    fn size(self) -> usize {
+        match self {
+            Self::Unorm8 | Self::Snorm8 => 1,
+            Self::Unorm16 | Self::Snorm16 | Self::Float16 => 2,
+            Self::Float32 => 4,
+        }
+    }
+    fn read(self, bytes: &[u8]) -> f32 {
+        match self {
+            Self::Unorm8 => bytes[0] as f32 / 255.0,
+            Self::Snorm8 => (bytes[0] as i8 as f32 / 127.0).max(-1.0),
+            Self::Unorm16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 65535.0,
+            Self::Snorm16 => (i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32767.0).max(-1.0),
+            Self::Float16 => half::f16::from_le_bytes([bytes[0], bytes[1]]).to_f32(),
+            Self::Float32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+    fn write(self, value: f32, bytes: &mut [u8]) {
+        match self {
+            Self::Unorm8 => bytes[0] = (value.clamp(0.0, 1.0) * 255.0).round() as u8,
+            Self::Snorm8 => bytes[0] = (value.clamp(-1.0, 1.0) * 127.0).round() as i8 as u8,
+            Self::Unorm16 => bytes
+                .copy_from_slice(&((value.clamp(0.0, 1.0) * 65535.0).round() as u16).to_le_bytes()),
+            Self::Snorm16 => bytes.copy_from_slice(
+                &((value.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes(),
+            ),
+            Self::Float16 => bytes.copy_from_slice(&half::f16::from_f32(value).to_le_bytes()),
+            Self::Float32 => bytes.copy_from_slice(&value.to_le_bytes()),
+        }
+    }
+}
+
+/// encoding and number of channels of the formats supported by the CPU fallback
+fn channels(format: TextureFormat) -> Option<(Channel, usize)> {
+    use TextureFormat as F;
+    let channel = match format {
+        F::R8Unorm
+        | F::Rg8Unorm
+        | F::Rgba8Unorm
+        | F::Rgba8UnormSrgb
+        | F::Bgra8Unorm
+        | F::Bgra8UnormSrgb => Channel::Unorm8,
+        F::R8Snorm | F::Rg8Snorm | F::Rgba8Snorm => Channel::Snorm8,
+        F::R16Unorm | F::Rg16Unorm | F::Rgba16Unorm => Channel::Unorm16,
+        F::R16Snorm | F::Rg16Snorm | F::Rgba16Snorm => Channel::Snorm16,
+        F::R16Float | F::Rg16Float | F::Rgba16Float => Channel::Float16,
+        F::R32Float | F::Rg32Float | F::Rgba32Float => Channel::Float32,
+        _ => return None,
+    };
+    Some((channel, format.components() as usize))
+}
+
+/// Fills the mip levels after the first one with the GPU, in every layer of the texture
+/// the texture must have `TextureUsages::RENDER_ATTACHMENT` and a format where `can_render` is true
+pub fn generate(renderer: &Renderer, texture: &wgpu::Texture) {
+    let format = texture.format();
+    let pipeline = renderer.pipeline(&MipmapPipeline { format });
+    let layout = pipeline.get_bind_group_layout(0);
+    let sampler = renderer.sampler_preset(SamplerPreset::BILINEAR);
+    let mut encoder = renderer
+        .device()
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+    for layer in 0..texture.depth_or_array_layers() {
+        let views: Vec<wgpu::TextureView> = (0..texture.mip_level_count())
+            .map(|level| {
+                texture.create_view(&TextureViewDescriptor {
+                    label: Some("Mipmap Level"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        for (level, pair) in views.windows(2).enumerate() {
+            trace!("Generating mip level {} of layer {}", level + 1, layer);
+            let bind_group = renderer
+                .bind_group_with_layout(
+                    "Mipmap Level",
+                    &layout,
+                    &[
+                        BindGroupEntryBuilder::new(0)
+                            .texture()
+                            .with(wgpu::BindingResource::TextureView(&pair[0])),
+                        BindGroupEntryBuilder::new(1)
+                            .sampler()
+                            .with(wgpu::BindingResource::Sampler(&sampler)),
+                    ],
+                )
+                .expect("Cannot create the bind group of a mip level");
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Mipmap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &pair[1],
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+    renderer.queue().submit(std::iter::once(encoder.finish()));
+}
+
+/// Generates the mip levels after the first one in the CPU with a box filter
+/// `data` is the first level with rows of `size.width` pixels without padding
+/// returns None if the format isn't supported, see `can_downsample`
+pub fn downsample(
+    format: TextureFormat,
+    size: Extent3d,
+    levels: u32,
+    data: &[u8],
+) -> Option<Vec<Vec<u8>>> {
+    let (encoding, channels) = channels(format)?;
+    let (channel_size, pixel_size) = (encoding.size(), encoding.size() * channels);
+    // the alpha of the sRGB formats is linear
+    let srgb = format.is_srgb();
+    let color = if channels == 4 { 3 } else { channels };
+
+    let mut result = Vec::new();
+    let (mut width, mut height) = (size.width as usize, size.height as usize);
+    for _ in 1..levels {
+        let previous = result.last().map_or(data, Vec::as_slice);
+        let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+        let mut level = vec![0u8; next_width * next_height * pixel_size];
+        for y in 0..next_height {
+            for x in 0..next_width {
+                for channel in 0..channels {
+                    let mut sum = 0.0;
+                    for (sx, sy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let sx = (x * 2 + sx).min(width - 1);
+                        let sy = (y * 2 + sy).min(height - 1);
+                        let start = (sy * width + sx) * pixel_size + channel * channel_size;
+                        let value = encoding.read(&previous[start..start + channel_size]);
+                        sum += if srgb && channel < color {
+                            srgb_to_linear(value)
+                        } else {
+                            value
+                        };
+                    }
+                    let value = sum / 4.0;
+                    let value = if srgb && channel < color {
+                        linear_to_srgb(value)
+                    } else {
+                        value
+                    };
+                    let start = (y * next_width + x) * pixel_size + channel * channel_size;
+                    encoding.write(value, &mut level[start..start + channel_size]);
+                }
+            }
+        }
+        result.push(level);
+        (width, height) = (next_width, next_height);
+    }
+    Some(result)
+}
+
+pub(crate) fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub(crate) fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::Features;
+
+    fn size(width: u32, height: u32) -> Extent3d {
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        }
+    }
+
+    #[test]
+    fn formats_that_cant_be_rendered_use_the_cpu() {
+        let renders_in =
+            |format: TextureFormat, features| renders(format.guaranteed_format_features(features));
+        assert!(renders_in(TextureFormat::Rgba8UnormSrgb, Features::empty()));
+        assert!(renders_in(TextureFormat::Rgba16Float, Features::empty()));
+        assert!(!renders_in(TextureFormat::Rgba8Snorm, Features::empty()));
+        assert!(!renders_in(TextureFormat::Rgba32Float, Features::empty()));
+        assert!(renders_in(
+            TextureFormat::Rgba32Float,
+            Features::FLOAT32_FILTERABLE
+        ));
+        assert!(!renders_in(TextureFormat::Rgba16Unorm, Features::empty()));
+        for format in [
+            TextureFormat::Rgba8Snorm,
+            TextureFormat::Rgba32Float,
+            TextureFormat::Rgba16Unorm,
+        ] {
+            assert!(can_downsample(format));
+        }
+        assert!(!can_downsample(TextureFormat::Bc1RgbaUnorm));
+    }
+
+    #[test]
+    fn downsample_averages_the_pixels() {
+        let data = [
+            0, 20, 40, 255, 255, 40, 80, 255, 0, 40, 80, 255, 255, 60, 120, 255,
+        ];
+        let levels = downsample(TextureFormat::Rgba8Unorm, size(2, 2), 2, &data).unwrap();
+        assert_eq!(levels, vec![vec![128, 40, 80, 255]]);
+    }
+
+    #[test]
+    fn downsample_averages_srgb_in_linear_space() {
+        let data = [0, 0, 0, 0, 255, 255, 255, 255];
+        let levels = downsample(TextureFormat::Rgba8UnormSrgb, size(2, 1), 2, &data).unwrap();
+        assert_eq!(levels, vec![vec![188, 188, 188, 128]]);
+    }
+
+    #[test]
+    fn downsample_every_level_of_odd_sizes() {
+        let data = vec![255; 5 * 3];
+        let levels = downsample(TextureFormat::R8Unorm, size(5, 3), 3, &data).unwrap();
+        assert_eq!(levels, vec![vec![255; 2], vec![255]]);
+    }
+
+    #[test]
+    fn downsample_formats_that_cant_be_rendered() {
+        let snorm = [-127i8, 127, -64, 0].map(|value| value as u8);
+        let levels = downsample(TextureFormat::R8Snorm, size(2, 2), 2, &snorm).unwrap();
+        assert_eq!(levels, vec![vec![(-16i8) as u8]]);
+
+        let float: Vec<u8> = [1.0f32, 2.0, 3.0, 6.0]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let levels = downsample(TextureFormat::R32Float, size(2, 2), 2, &float).unwrap();
+        assert_eq!(levels, vec![3.0f32.to_le_bytes().to_vec()]);
+
+        let unorm: Vec<u8> = [0u16, 65535, 0, 65535]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let levels = downsample(TextureFormat::R16Unorm, size(2, 2), 2, &unorm).unwrap();
+        assert_eq!(levels, vec![32768u16.to_le_bytes().to_vec()]);
+
+        assert!(downsample(TextureFormat::Bc1RgbaUnorm, size(4, 4), 2, &[0; 8]).is_none());
+    }
+}
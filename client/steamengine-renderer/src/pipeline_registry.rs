@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, RwLock};
+
+use tracing::*;
+use wgpu::TextureFormat;
+
+use super::render_pipeline::{RenderPipeline, merge_constants};
+use super::{Renderer, errors::ReflectionError};
+
+/// Identifies a compiled pipeline inside the registry
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub label: String,
+    /// hash of the shader sources
+    pub source: u64,
+    /// formats of the color targets
+    pub formats: Vec<Option<TextureFormat>>,
+    /// format of the depth stencil
+    pub depth_format: Option<TextureFormat>,
+    pub sample_count: u32,
+    /// true if the sample count comes from `Renderer::sample_count`
+    pub renderer_sample_count: bool,
+    /// hash of the entry points, constants, buffers and states of the pipeline
+    pub variant: u64,
+}
+impl PipelineKey {
+    /// Create the key of a pipeline with its current configuration
+    pub fn new<P: RenderPipeline + ?Sized>(pipeline: &P, renderer: &Renderer) -> Self {
+        Self::with_states(
+            pipeline,
+            pipeline.targets(renderer),
+            pipeline.multisample(renderer),
+        )
+    }
+    /// Create the key with the states that depend on the renderer
+    pub(crate) fn with_states<P: RenderPipeline + ?Sized>(
+        pipeline: &P,
+        targets: Vec<Option<wgpu::ColorTargetState>>,
+        multisample: wgpu::MultisampleState,
+    ) -> Self {
+        let mut source = DefaultHasher::new();
+        pipeline.source().hash(&mut source);
+        pipeline.fragment_source().hash(&mut source);
+
+        let mut variant = DefaultHasher::new();
+        pipeline.vertex_entry_point().hash(&mut variant);
+        pipeline.fragment_entry_point().hash(&mut variant);
+        let constants = pipeline.constants();
+        for compilation in [
+            pipeline.vertex_compilation(),
+            pipeline.fragment_compilation(),
+        ] {
+            for (name, value) in merge_constants(&constants, compilation.constants) {
+                (name, value.to_bits()).hash(&mut variant);
+            }
+            compilation
+                .zero_initialize_workgroup_memory
+                .hash(&mut variant);
+        }
+        pipeline.buffers().hash(&mut variant);
+        targets.hash(&mut variant);
+        pipeline.primitive().hash(&mut variant);
+        pipeline.depth_stencil().hash(&mut variant);
+        multisample.hash(&mut variant);
+        pipeline.multiview().hash(&mut variant);
+        pipeline.push_constants().hash(&mut variant);
+        if let Some(layout) = pipeline.layout() {
+            layout.bind_group_layouts.hash(&mut variant);
+            layout.push_constant_ranges.hash(&mut variant);
+        }
+
+        Self {
+            label: pipeline.label().to_owned(),
+            source: source.finish(),
+            formats: targets
+                .iter()
+                .map(|target| target.as_ref().map(|target| target.format))
+                .collect(),
+            depth_format: pipeline.depth_stencil().map(|depth| depth.format),
+            sample_count: multisample.count,
+            renderer_sample_count: pipeline.sample_count().is_none(),
+            variant: variant.finish(),
+        }
+    }
+}
+
+/// This is a cache of compiled pipelines, the pipelines with the same key are shared
+pub struct PipelineRegistry {
+    pipelines: RwLock<HashMap<PipelineKey, Arc<wgpu::RenderPipeline>>>,
+}
+impl Default for PipelineRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl PipelineRegistry {
+    pub fn new() -> Self {
+        Self {
+            pipelines: RwLock::new(HashMap::new()),
+        }
+    }
+    /// gets the compiled pipeline, compiles it if it isn't in the registry
+    pub fn get_or_create<P: RenderPipeline + ?Sized>(
+        &self,
+        pipeline: &P,
+        renderer: &Renderer,
+    ) -> Arc<wgpu::RenderPipeline> {
+        self.try_get_or_create(pipeline, renderer)
+            .unwrap_or_else(|err| {
+                panic!("Cannot create the pipeline {}, {}", pipeline.label(), err)
+            })
+    }
+    /// gets the compiled pipeline, returns an error if the layout can't be reflected
+    pub fn try_get_or_create<P: RenderPipeline + ?Sized>(
+        &self,
+        pipeline: &P,
+        renderer: &Renderer,
+    ) -> Result<Arc<wgpu::RenderPipeline>, ReflectionError> {
+        self.get_or_insert_with(pipeline.key(renderer), || pipeline.try_to_wgpu(renderer))
+    }
+    fn get_or_insert_with<E>(
+        &self,
+        key: PipelineKey,
+        compile: impl FnOnce() -> Result<wgpu::RenderPipeline, E>,
+    ) -> Result<Arc<wgpu::RenderPipeline>, E> {
+        if let Some(compiled) = self
+            .pipelines
+            .read()
+            .expect("Cannot read pipelines")
+            .get(&key)
+        {
+            return Ok(compiled.clone());
+        }
+        trace!("Compiling pipeline -- {}", key.label);
+        let compiled = Arc::new(compile()?);
+        Ok(self
+            .pipelines
+            .write()
+            .expect("Cannot write pipelines")
+            .entry(key)
+            .or_insert(compiled)
+            .clone())
+    }
+    /// gets the compiled pipeline if it is in the registry
+    pub fn get(&self, key: &PipelineKey) -> Option<Arc<wgpu::RenderPipeline>> {
+        self.pipelines
+            .read()
+            .expect("Cannot read pipelines")
+            .get(key)
+            .cloned()
+    }
+    /// removes the pipelines that match the predicate
+    pub fn invalidate(&self, predicate: impl Fn(&PipelineKey) -> bool) {
+        self.pipelines
+            .write()
+            .expect("Cannot write pipelines")
+            .retain(|key, _| {
+                let invalid = predicate(key);
+                if invalid {
+                    trace!("Invalidating pipeline -- {}", key.label);
+                }
+                !invalid
+            });
+    }
+    /// removes the pipelines that render to the format
+    pub fn invalidate_format(&self, format: TextureFormat) {
+        self.invalidate(|key| key.formats.contains(&Some(format)));
+    }
+    /// removes the pipelines that use the sample count of the renderer and were compiled with
+    /// other count, the pipelines with their own `RenderPipeline::sample_count` are kept
+    pub fn invalidate_sample_count(&self, sample_count: u32) {
+        self.invalidate(|key| key.renderer_sample_count && key.sample_count != sample_count);
+    }
+    /// removes all the pipelines
+    pub fn clear(&self) {
+        self.pipelines
+            .write()
+            .expect("Cannot write pipelines")
+            .clear();
+    }
+    /// number of compiled pipelines
+    pub fn len(&self) -> usize {
+        self.pipelines.read().expect("Cannot read pipelines").len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::ShaderSource;
+
+    const SHADER: &str = r#"
+    @vertex
+    fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+        return vec4<f32>(f32(index), 0.0, 0.0, 1.0);
+    }
+
+    @fragment
+    fn fs_main() -> @location(0) vec4<f32> {
+        return vec4<f32>(1.0);
+    }
+    "#;
+
+    struct TestPipeline {
+        constant: f64,
+    }
+    impl RenderPipeline for TestPipeline {
+        fn label(&self) -> &str {
+            "Test Pipeline"
+        }
+        fn source(&self) -> ShaderSource<'_> {
+            SHADER.into()
+        }
+        fn constants(&self) -> HashMap<String, f64> {
+            HashMap::from([("value".to_owned(), self.constant)])
+        }
+    }
+
+    /// a pipeline that is never multisampled, Ex: an offscreen pass
+    struct SingleSamplePipeline;
+    impl RenderPipeline for SingleSamplePipeline {
+        fn label(&self) -> &str {
+            "Single Sample Pipeline"
+        }
+        fn source(&self) -> ShaderSource<'_> {
+            SHADER.into()
+        }
+        fn sample_count(&self) -> Option<u32> {
+            Some(1)
+        }
+    }
+
+    fn target(format: TextureFormat) -> Vec<Option<wgpu::ColorTargetState>> {
+        vec![Some(format.into())]
+    }
+
+    fn multisample(count: u32) -> wgpu::MultisampleState {
+        wgpu::MultisampleState {
+            count,
+            ..Default::default()
+        }
+    }
+
+    fn key(constant: f64, format: TextureFormat, count: u32) -> PipelineKey {
+        PipelineKey::with_states(
+            &TestPipeline { constant },
+            target(format),
+            multisample(count),
+        )
+    }
+
+    /// compiles a pipeline in the noop device
+    fn compile(device: &wgpu::Device) -> Result<wgpu::RenderPipeline, ()> {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        Ok(
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: None,
+                layout: None,
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: Some("fs_main"),
+                    targets: &target(TextureFormat::Rgba8Unorm),
+                    compilation_options: Default::default(),
+                }),
+                primitive: Default::default(),
+                depth_stencil: None,
+                multisample: Default::default(),
+                multiview: None,
+                cache: None,
+            }),
+        )
+    }
+
+    #[test]
+    fn keys_of_the_same_configuration_are_equal() {
+        let format = TextureFormat::Bgra8UnormSrgb;
+        assert_eq!(key(1.0, format, 1), key(1.0, format, 1));
+        assert_ne!(key(1.0, format, 1), key(2.0, format, 1));
+        assert_ne!(key(1.0, format, 1), key(1.0, TextureFormat::Rgba16Float, 1));
+        assert_ne!(key(1.0, format, 1), key(1.0, format, 4));
+        assert_eq!(key(1.0, format, 4).sample_count, 4);
+        assert_eq!(key(1.0, format, 1).formats, vec![Some(format)]);
+    }
+
+    #[test]
+    fn pipelines_with_the_same_key_are_shared() {
+        let (device, _queue) = crate::testing::device();
+        let registry = PipelineRegistry::new();
+        let format = TextureFormat::Rgba8Unorm;
+        let a = registry
+            .get_or_insert_with(key(1.0, format, 1), || compile(&device))
+            .unwrap();
+        let b = registry
+            .get_or_insert_with(key(1.0, format, 1), || -> Result<_, ()> {
+                panic!("compiled twice")
+            })
+            .unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(registry.len(), 1);
+        assert!(registry.get(&key(1.0, format, 1)).is_some());
+    }
+
+    #[test]
+    fn invalidation_removes_the_matching_pipelines() {
+        let (device, _queue) = crate::testing::device();
+        let registry = PipelineRegistry::new();
+        let keys = [
+            key(1.0, TextureFormat::Rgba8Unorm, 1),
+            key(1.0, TextureFormat::Bgra8Unorm, 1),
+            key(1.0, TextureFormat::Rgba8Unorm, 4),
+        ];
+        for key in &keys {
+            registry
+                .get_or_insert_with(key.clone(), || compile(&device))
+                .unwrap();
+        }
+        assert_eq!(registry.len(), 3);
+
+        registry.invalidate_sample_count(4);
+        assert_eq!(registry.len(), 1);
+        assert!(registry.get(&keys[2]).is_some());
+
+        registry.invalidate_format(TextureFormat::Rgba8Unorm);
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn pipelines_with_their_own_sample_count_survive_msaa_changes() {
+        let renderer = crate::testing::renderer();
+        let default = TestPipeline { constant: 1.0 };
+        let single = SingleSamplePipeline;
+        let default_key = default.key(&renderer);
+        let single_key = single.key(&renderer);
+        assert!(default_key.renderer_sample_count);
+        assert!(!single_key.renderer_sample_count);
+
+        renderer.pipeline(&default);
+        let compiled = renderer.pipeline(&single);
+        assert_eq!(renderer.pipelines.len(), 2);
+
+        renderer.set_sample_count(4);
+        assert_eq!(renderer.sample_count(), 4);
+        assert!(renderer.pipelines.get(&default_key).is_none());
+        assert!(Arc::ptr_eq(
+            &renderer.pipelines.get(&single_key).unwrap(),
+            &compiled
+        ));
+        assert_eq!(default.key(&renderer).sample_count, 4);
+        assert_eq!(single.key(&renderer), single_key);
+
+        renderer.set_sample_count(1);
+        assert!(renderer.pipelines.get(&single_key).is_some());
+    }
+}
@@ -0,0 +1,339 @@
+use std::marker::PhantomData;
+use std::num::NonZero;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytemuck::Pod;
+use tracing::*;
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, BufferBinding, BufferUsages, Features, PushConstantRange,
+    ShaderStages,
+};
+
+use super::{Renderer, bind_group::BindGroupEntryBuilder, errors::PushConstantError};
+
+/// Layout that a push constant adds to a pipeline
+#[derive(Hash)]
+pub enum PushConstantLayout<'a> {
+    /// The device supports push constants, the value is pushed in this range
+    Range(PushConstantRange),
+    /// The device doesn't support push constants, the value is read from a uniform buffer
+    /// bound in this group
+    Uniform {
+        group: u32,
+        layout: &'a BindGroupLayout,
+    },
+}
+
+/// Uniform buffer used when the device doesn't support push constants
+/// Every value is written in its own slot and bound with a dynamic offset
+struct UniformFallback {
+    label: String,
+    queue: wgpu::Queue,
+    buffer: Buffer,
+    layout: Arc<BindGroupLayout>,
+    bind_group: BindGroup,
+    group: u32,
+    stride: u64,
+    capacity: u64,
+    next: AtomicU64,
+}
+
+/// Typed push constant of a pipeline
+/// Uses native push constants when the renderer was built with `RendererBuilder::push_constants`
+/// and the adapter supports them, otherwise falls back to a uniform buffer in `group`
+/// ## Example
+/// ```rust
+/// use steamengine_renderer::Renderer;
+/// use steamengine_renderer::errors::PushConstantError;
+/// use steamengine_renderer::push_constants::{PushConstants, RenderPassPushConstants};
+/// use steamengine_renderer::wgpu::{self, ShaderStages};
+///
+/// const SHADER: &str = "/* the shader that reads object_id */";
+///
+/// fn draw(renderer: &Renderer, render_pass: &mut wgpu::RenderPass) -> Result<(), PushConstantError> {
+///     let object_id = PushConstants::<u32>::new("Object id", renderer, ShaderStages::VERTEX, 1, 1024);
+///     // declare the push constant in the shader
+///     let source = format!("{}\n{}", object_id.wgsl("object_id", "u32"), SHADER);
+///
+///     // at the start of the frame
+///     object_id.reset();
+///     // for each draw
+///     for id in 0..16u32 {
+///         render_pass.push_constants(&object_id, &id)?;
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct PushConstants<T: Pod> {
+    stages: ShaderStages,
+    fallback: Option<UniformFallback>,
+    _marker: PhantomData<T>,
+}
+impl<T: Pod> PushConstants<T> {
+    /// create a new push constant visible in `stages`
+    /// `group` and `capacity` are only used by the uniform fallback, capacity is the number of
+    /// values that can be set between two calls to `reset`
+    pub fn new(
+        label: &str,
+        renderer: &Renderer,
+        stages: ShaderStages,
+        group: u32,
+        capacity: u64,
+    ) -> Self {
+        let size = std::mem::size_of::<T>() as u64;
+        assert!(
+            size.is_multiple_of(4),
+            "The size of a push constant must be a multiple of 4 -- {}",
+            label
+        );
+        let native = renderer.features().contains(Features::PUSH_CONSTANTS)
+            && size <= renderer.device().limits().max_push_constant_size as u64;
+        if native {
+            trace!("Using native push constants -- {}", label);
+            return Self {
+                stages,
+                fallback: None,
+                _marker: PhantomData,
+            };
+        }
+
+        trace!("Using uniform buffer for push constants -- {}", label);
+        let alignment = renderer
+            .device()
+            .limits()
+            .min_uniform_buffer_offset_alignment as u64;
+        let stride = size.div_ceil(alignment) * alignment;
+        let buffer = renderer.create_buffer(
+            label,
+            BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            stride * capacity,
+        );
+        let (bind_group, layout) = renderer.bind_group(
+            label,
+            &[BindGroupEntryBuilder::new(0)
+                .on(stages)
+                .uniform()
+                .has_dynamic_offset(true)
+                .min_binding_size(NonZero::new(size))
+                .with(wgpu::BindingResource::Buffer(BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: NonZero::new(size),
+                }))],
+        );
+        Self {
+            stages,
+            fallback: Some(UniformFallback {
+                label: label.to_owned(),
+                queue: renderer.queue().clone(),
+                buffer,
+                layout,
+                bind_group,
+                group,
+                stride,
+                capacity,
+                next: AtomicU64::new(0),
+            }),
+            _marker: PhantomData,
+        }
+    }
+    /// returns true if the device supports push constants
+    pub fn is_native(&self) -> bool {
+        self.fallback.is_none()
+    }
+    /// returns the layout that the pipeline needs
+    pub fn layout(&self) -> PushConstantLayout<'_> {
+        match &self.fallback {
+            None => PushConstantLayout::Range(PushConstantRange {
+                stages: self.stages,
+                range: 0..std::mem::size_of::<T>() as u32,
+            }),
+            Some(fallback) => PushConstantLayout::Uniform {
+                group: fallback.group,
+                layout: &fallback.layout,
+            },
+        }
+    }
+    /// returns the WGSL declaration of the push constant with the name and type
+    pub fn wgsl(&self, name: &str, ty: &str) -> String {
+        match &self.fallback {
+            None => format!("var<push_constant> {}: {};", name, ty),
+            Some(fallback) => format!(
+                "@group({}) @binding(0) var<uniform> {}: {};",
+                fallback.group, name, ty
+            ),
+        }
+    }
+    /// starts a new frame, the slots of the uniform fallback are reused
+    pub fn reset(&self) {
+        if let Some(fallback) = &self.fallback {
+            fallback.next.store(0, Ordering::Relaxed);
+        }
+    }
+    /// sets the value for the next draws of the render pass
+    /// returns an error if the uniform fallback has no free slots, the render pass isn't changed
+    /// so the draw must be skipped
+    pub fn set(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        value: &T,
+    ) -> Result<(), PushConstantError> {
+        let Some(fallback) = &self.fallback else {
+            render_pass.set_push_constants(self.stages, 0, bytemuck::bytes_of(value));
+            return Ok(());
+        };
+        let offset = fallback.next_offset()?;
+        fallback
+            .queue
+            .write_buffer(&fallback.buffer, offset, bytemuck::bytes_of(value));
+        render_pass.set_bind_group(fallback.group, &fallback.bind_group, &[offset as u32]);
+        Ok(())
+    }
+}
+impl UniformFallback {
+    /// reserves the next slot, returns its offset in the buffer
+    fn next_offset(&self) -> Result<u64, PushConstantError> {
+        let slot = self.next.fetch_add(1, Ordering::Relaxed);
+        if slot >= self.capacity {
+            error!(
+                "attempt to set more push constants than the capacity of the uniform fallback, PushConstants Overflow -- {}",
+                self.label
+            );
+            return Err(PushConstantError::Overflow {
+                label: self.label.clone(),
+                capacity: self.capacity,
+            });
+        }
+        Ok(slot * self.stride)
+    }
+}
+
+/// Function to set push constants in a render pass
+pub trait RenderPassPushConstants {
+    fn push_constants<T: Pod>(
+        &mut self,
+        constants: &PushConstants<T>,
+        value: &T,
+    ) -> Result<(), PushConstantError>;
+}
+impl RenderPassPushConstants for wgpu::RenderPass<'_> {
+    fn push_constants<T: Pod>(
+        &mut self,
+        constants: &PushConstants<T>,
+        value: &T,
+    ) -> Result<(), PushConstantError> {
+        constants.set(self, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_constant_renderer(max_push_constant_size: u32) -> Renderer<'static> {
+        crate::testing::renderer_with(
+            Features::PUSH_CONSTANTS,
+            wgpu::Limits {
+                max_push_constant_size,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn native_push_constants_are_used_when_supported() {
+        let renderer = push_constant_renderer(128);
+        let constants = PushConstants::<u32>::new("id", &renderer, ShaderStages::VERTEX, 1, 4);
+        assert!(constants.is_native());
+        assert!(matches!(
+            constants.layout(),
+            PushConstantLayout::Range(PushConstantRange { stages: ShaderStages::VERTEX, range })
+                if range == (0..4)
+        ));
+        assert_eq!(constants.wgsl("id", "u32"), "var<push_constant> id: u32;");
+    }
+
+    #[test]
+    fn uniform_fallback_is_used_without_the_feature_or_the_space() {
+        let renderer = crate::testing::renderer();
+        let constants = PushConstants::<u32>::new("id", &renderer, ShaderStages::VERTEX, 1, 4);
+        assert!(!constants.is_native());
+        assert!(matches!(
+            constants.layout(),
+            PushConstantLayout::Uniform { group: 1, .. }
+        ));
+        assert_eq!(
+            constants.wgsl("id", "u32"),
+            "@group(1) @binding(0) var<uniform> id: u32;"
+        );
+
+        // bigger than the push constant limit
+        let renderer = push_constant_renderer(128);
+        let constants =
+            PushConstants::<[u32; 64]>::new("ids", &renderer, ShaderStages::VERTEX, 2, 4);
+        assert!(!constants.is_native());
+    }
+
+    #[test]
+    fn uniform_fallback_slots_are_aligned_and_limited() {
+        let renderer = crate::testing::renderer();
+        let alignment = renderer
+            .device()
+            .limits()
+            .min_uniform_buffer_offset_alignment as u64;
+        let constants =
+            PushConstants::<[f32; 4]>::new("color", &renderer, ShaderStages::FRAGMENT, 0, 2);
+        let fallback = constants.fallback.as_ref().unwrap();
+        assert_eq!(fallback.stride, alignment);
+        assert_eq!(fallback.buffer.size(), alignment * 2);
+
+        assert_eq!(fallback.next_offset().unwrap(), 0);
+        assert_eq!(fallback.next_offset().unwrap(), alignment);
+        assert!(matches!(
+            fallback.next_offset(),
+            Err(PushConstantError::Overflow { capacity: 2, .. })
+        ));
+        assert!(fallback.next_offset().is_err());
+
+        constants.reset();
+        assert_eq!(fallback.next_offset().unwrap(), 0);
+    }
+
+    #[test]
+    fn setting_more_values_than_the_capacity_is_an_error() {
+        let renderer = crate::testing::renderer();
+        let constants = PushConstants::<u32>::new("id", &renderer, ShaderStages::FRAGMENT, 0, 2);
+        let texture = renderer.device().create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: 4,
+                height: 4,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&Default::default());
+        let mut encoder = renderer
+            .device()
+            .create_command_encoder(&Default::default());
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: Default::default(),
+            })],
+            ..Default::default()
+        });
+        assert!(render_pass.push_constants(&constants, &1).is_ok());
+        assert!(render_pass.push_constants(&constants, &2).is_ok());
+        assert!(render_pass.push_constants(&constants, &3).is_err());
+        constants.reset();
+        assert!(render_pass.push_constants(&constants, &4).is_ok());
+    }
+}
@@ -0,0 +1,768 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use naga::valid::{Capabilities, ModuleInfo, ValidationFlags, Validator};
+use naga::{AddressSpace, ImageClass, ImageDimension, ScalarKind, ShaderStage, TypeInner};
+use tracing::*;
+use wgpu::{
+    BindGroupLayout, BindGroupLayoutEntry, BindingType, BufferBindingType, PushConstantRange,
+    SamplerBindingType, ShaderStages, StorageTextureAccess, TextureFormat, TextureSampleType,
+    TextureViewDimension, VertexBufferLayout, VertexFormat,
+};
+
+use super::{Renderer, errors::ReflectionError, push_constants::PushConstantLayout};
+
+/// A vertex input expected by an entry point of the shader
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReflectedVertexInput {
+    /// value of `@location` in the shader
+    pub location: u32,
+    /// format that matches the type declared in the shader
+    pub format: VertexFormat,
+}
+
+/// This is the reflection of a shader, parsed and validated with naga
+/// ## Example
+/// ```rust
+/// use steamengine_renderer::bind_group::BindGroupEntryBuilder;
+/// use steamengine_renderer::reflection::ShaderReflection;
+///
+/// let reflection = ShaderReflection::from_wgsl(
+///     r#"
+///     @group(0) @binding(0) var<uniform> camera: mat4x4<f32>;
+///
+///     @vertex
+///     fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+///         return camera * vec4<f32>(position, 1.0);
+///     }
+///     "#,
+/// )
+/// .unwrap();
+///
+/// let groups = reflection.bind_group_layout_entries().unwrap();
+/// assert_eq!(groups.len(), 1);
+/// assert_eq!(groups[0][0].visibility, wgpu::ShaderStages::VERTEX);
+/// assert_eq!(
+///     groups[0][0],
+///     BindGroupEntryBuilder::new(0)
+///         .on(wgpu::ShaderStages::VERTEX)
+///         .uniform()
+///         .layout_entry()
+/// );
+///
+/// let inputs = reflection.vertex_inputs("vs_main").unwrap();
+/// assert_eq!(inputs[0].format, wgpu::VertexFormat::Float32x3);
+/// ```
+pub struct ShaderReflection {
+    modules: Vec<ReflectedModule>,
+}
+
+/// A validated naga module
+struct ReflectedModule {
+    module: naga::Module,
+    info: ModuleInfo,
+}
+
+impl ShaderReflection {
+    /// Parse and validate a WGSL source
+    pub fn from_wgsl(source: &str) -> Result<Self, ReflectionError> {
+        let module = naga::front::wgsl::parse_str(source)
+            .map_err(|err| ReflectionError::Parse(err.emit_to_string(source)))?;
+        Self::from_module(module, source)
+    }
+    /// Validate an already parsed naga module, the source is only used for the error messages
+    pub fn from_module(module: naga::Module, source: &str) -> Result<Self, ReflectionError> {
+        let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(&module)
+            .map_err(|err| ReflectionError::Validation(err.emit_to_string(source)))?;
+        Ok(Self {
+            modules: vec![ReflectedModule { module, info }],
+        })
+    }
+    /// Join the reflection of other module, Ex: the fragment stage of a GLSL pipeline
+    /// The bindings declared by both modules are merged
+    pub fn merge(mut self, other: ShaderReflection) -> Self {
+        self.modules.extend(other.modules);
+        self
+    }
+    /// gets the naga modules
+    pub fn modules(&self) -> impl Iterator<Item = &naga::Module> {
+        self.modules.iter().map(|reflected| &reflected.module)
+    }
+
+    /// Returns the layout entries of every bind group, indexed by the `@group` number
+    /// Groups without bindings are returned as empty lists
+    /// The visibility of a binding is the stages of the entry points that use it, and the buffers
+    /// don't have a minimum binding size, so the entries match the ones of `BindGroupEntryBuilder`
+    /// with `.on(stages)` and without `.sized::<T>()`
+    pub fn bind_group_layout_entries(
+        &self,
+    ) -> Result<Vec<Vec<BindGroupLayoutEntry>>, ReflectionError> {
+        let mut groups: BTreeMap<u32, BTreeMap<u32, BindGroupLayoutEntry>> = BTreeMap::new();
+        for reflected in &self.modules {
+            for (group, entry) in reflected.bind_group_layout_entries()? {
+                groups
+                    .entry(group)
+                    .or_default()
+                    .entry(entry.binding)
+                    .and_modify(|merged| merged.visibility |= entry.visibility)
+                    .or_insert(entry);
+            }
+        }
+
+        let len = groups.keys().last().map(|last| last + 1).unwrap_or(0);
+        let mut result = vec![Vec::new(); len as usize];
+        for (group, entries) in groups {
+            result[group as usize] = entries.into_values().collect();
+        }
+        Ok(result)
+    }
+    /// Create the bind group layouts of the shader, one per `@group`
+    /// The layouts come from the cache of the renderer, so the bind groups created with
+    /// `Renderer::bind_group` and the same entries can be used with the pipeline, see
+    /// `bind_group_layout_entries` for the visibility and the size of the entries
+    pub fn bind_group_layouts(
+        &self,
+        label: &str,
+        renderer: &Renderer,
+    ) -> Result<Vec<Arc<BindGroupLayout>>, ReflectionError> {
+        let groups = self.bind_group_layout_entries()?;
+        trace!("Reflected {} bind groups -- {}", groups.len(), label);
+        Ok(groups
+            .iter()
+            .enumerate()
+            .map(|(group, entries)| {
+                renderer.bind_group_layout(format!("{} - GROUP {}", label, group).as_str(), entries)
+            })
+            .collect())
+    }
+
+    /// Returns the push constant ranges used by the entry points
+    pub fn push_constant_ranges(&self) -> Vec<PushConstantRange> {
+        let mut ranges: Vec<PushConstantRange> = Vec::new();
+        for range in self
+            .modules
+            .iter()
+            .flat_map(|reflected| reflected.push_constant_ranges())
+        {
+            match ranges.iter_mut().find(|merged| merged.range == range.range) {
+                Some(merged) => merged.stages |= range.stages,
+                None => ranges.push(range),
+            }
+        }
+        ranges
+    }
+
+    /// Create a pipeline layout with the reflected bind groups and push constants
+    /// The push constants of the pipeline replace the reflected ranges, and the groups of the
+    /// uniform fallbacks use the layout of the fallback
+    pub fn pipeline_layout(
+        &self,
+        label: &str,
+        renderer: &Renderer,
+        push_constants: &[PushConstantLayout],
+    ) -> Result<wgpu::PipelineLayout, ReflectionError> {
+        let reflected = self.bind_group_layouts(label, renderer)?;
+        let mut bind_group_layouts: Vec<&BindGroupLayout> =
+            reflected.iter().map(Arc::as_ref).collect();
+        let len = push_constants
+            .iter()
+            .filter_map(|push_constant| match push_constant {
+                PushConstantLayout::Uniform { group, .. } => Some(*group as usize + 1),
+                PushConstantLayout::Range(_) => None,
+            })
+            .max()
+            .unwrap_or(0);
+        let empty = (len > reflected.len()).then(|| renderer.bind_group_layout(label, &[]));
+        if let Some(empty) = &empty {
+            bind_group_layouts.resize(len, empty);
+        }
+        let mut push_constant_ranges = Vec::new();
+        for push_constant in push_constants {
+            match push_constant {
+                PushConstantLayout::Range(range) => push_constant_ranges.push(range.clone()),
+                PushConstantLayout::Uniform { group, layout } => {
+                    bind_group_layouts[*group as usize] = layout;
+                }
+            }
+        }
+        if push_constant_ranges.is_empty() {
+            push_constant_ranges = self.push_constant_ranges();
+        }
+        Ok(renderer
+            .device()
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &bind_group_layouts,
+                push_constant_ranges: &push_constant_ranges,
+            }))
+    }
+
+    /// Returns the vertex inputs of the entry point sorted by location
+    pub fn vertex_inputs(
+        &self,
+        entry_point: &str,
+    ) -> Result<Vec<ReflectedVertexInput>, ReflectionError> {
+        self.modules
+            .iter()
+            .find_map(|reflected| reflected.vertex_inputs(entry_point))
+            .ok_or_else(|| ReflectionError::MissingEntryPoint(entry_point.to_owned()))?
+    }
+
+    /// Checks that the vertex buffers provide every input of the entry point with a compatible
+    /// format
+    pub fn check_vertex_buffers(
+        &self,
+        entry_point: &str,
+        buffers: &[VertexBufferLayout],
+    ) -> Result<(), ReflectionError> {
+        for input in self.vertex_inputs(entry_point)? {
+            let attribute = buffers
+                .iter()
+                .flat_map(|buffer| buffer.attributes.iter())
+                .find(|attribute| attribute.shader_location == input.location)
+                .ok_or(ReflectionError::MissingVertexAttribute(input.location))?;
+            if vertex_format_kind(attribute.format) != vertex_format_kind(input.format) {
+                return Err(ReflectionError::VertexFormatMismatch {
+                    location: input.location,
+                    expected: input.format,
+                    found: attribute.format,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ReflectedModule {
+    /// Stages of the entry points that use the global variable
+    fn visibility(&self, handle: naga::Handle<naga::GlobalVariable>) -> ShaderStages {
+        let mut used = ShaderStages::NONE;
+        let mut declared = ShaderStages::NONE;
+        for (index, entry_point) in self.module.entry_points.iter().enumerate() {
+            let stage = map_stage(entry_point.stage);
+            declared |= stage;
+            if !self.info.get_entry_point(index)[handle].is_empty() {
+                used |= stage;
+            }
+        }
+        // Bindings that are declared but unused stay in the layout so the group keeps the
+        // shape written in the shader
+        if used.is_empty() { declared } else { used }
+    }
+
+    /// Returns the group and the layout entry of every binding
+    fn bind_group_layout_entries(
+        &self,
+    ) -> Result<Vec<(u32, BindGroupLayoutEntry)>, ReflectionError> {
+        let mut entries = Vec::new();
+        for (handle, global) in self.module.global_variables.iter() {
+            let Some(binding) = &global.binding else {
+                continue;
+            };
+            let (ty, count) = match &self.module.types[global.ty].inner {
+                TypeInner::BindingArray { base, size } => {
+                    let count = match size {
+                        naga::ArraySize::Constant(count) => Some(*count),
+                        _ => {
+                            return Err(ReflectionError::UnsupportedBinding {
+                                group: binding.group,
+                                binding: binding.binding,
+                                reason: "binding arrays must have a constant size".to_owned(),
+                            });
+                        }
+                    };
+                    (*base, count)
+                }
+                _ => (global.ty, None),
+            };
+            let ty = self.binding_type(global.space, ty).map_err(|reason| {
+                ReflectionError::UnsupportedBinding {
+                    group: binding.group,
+                    binding: binding.binding,
+                    reason,
+                }
+            })?;
+            entries.push((
+                binding.group,
+                BindGroupLayoutEntry {
+                    binding: binding.binding,
+                    visibility: self.visibility(handle),
+                    ty,
+                    count,
+                },
+            ));
+        }
+        Ok(entries)
+    }
+
+    fn binding_type(
+        &self,
+        space: AddressSpace,
+        ty: naga::Handle<naga::Type>,
+    ) -> Result<BindingType, String> {
+        let inner = &self.module.types[ty].inner;
+        // the size is validated when the pipeline is used, a size here would make the layout
+        // different to the one of `BindGroupEntryBuilder::uniform`
+        let min_binding_size = None;
+        match (space, inner) {
+            (AddressSpace::Uniform, _) => Ok(BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size,
+            }),
+            (AddressSpace::Storage { access }, _) => Ok(BindingType::Buffer {
+                ty: BufferBindingType::Storage {
+                    read_only: !access.contains(naga::StorageAccess::STORE),
+                },
+                has_dynamic_offset: false,
+                min_binding_size,
+            }),
+            (AddressSpace::Handle, TypeInner::Sampler { comparison }) => {
+                Ok(BindingType::Sampler(if *comparison {
+                    SamplerBindingType::Comparison
+                } else {
+                    SamplerBindingType::Filtering
+                }))
+            }
+            (
+                AddressSpace::Handle,
+                TypeInner::Image {
+                    dim,
+                    arrayed,
+                    class,
+                },
+            ) => {
+                let view_dimension = map_view_dimension(*dim, *arrayed);
+                match class {
+                    ImageClass::Sampled { kind, multi } => Ok(BindingType::Texture {
+                        sample_type: match kind {
+                            ScalarKind::Float => TextureSampleType::Float { filterable: !multi },
+                            ScalarKind::Sint => TextureSampleType::Sint,
+                            ScalarKind::Uint => TextureSampleType::Uint,
+                            kind => return Err(format!("textures of {:?} can't be sampled", kind)),
+                        },
+                        view_dimension,
+                        multisampled: *multi,
+                    }),
+                    ImageClass::Depth { multi } => Ok(BindingType::Texture {
+                        sample_type: TextureSampleType::Depth,
+                        view_dimension,
+                        multisampled: *multi,
+                    }),
+                    ImageClass::Storage { format, access } => Ok(BindingType::StorageTexture {
+                        access: if access.contains(naga::StorageAccess::ATOMIC) {
+                            StorageTextureAccess::Atomic
+                        } else if access
+                            .contains(naga::StorageAccess::LOAD | naga::StorageAccess::STORE)
+                        {
+                            StorageTextureAccess::ReadWrite
+                        } else if access.contains(naga::StorageAccess::STORE) {
+                            StorageTextureAccess::WriteOnly
+                        } else {
+                            StorageTextureAccess::ReadOnly
+                        },
+                        format: map_storage_format(*format),
+                        view_dimension,
+                    }),
+                }
+            }
+            (AddressSpace::Handle, TypeInner::AccelerationStructure { vertex_return }) => {
+                Ok(BindingType::AccelerationStructure {
+                    vertex_return: *vertex_return,
+                })
+            }
+            (space, inner) => Err(format!("{:?} in {:?} can't be bound", inner, space)),
+        }
+    }
+
+    fn push_constant_ranges(&self) -> Vec<PushConstantRange> {
+        self.module
+            .global_variables
+            .iter()
+            .filter(|(_, global)| global.space == AddressSpace::PushConstant)
+            .map(|(handle, global)| PushConstantRange {
+                stages: self.visibility(handle),
+                range: 0..self.module.types[global.ty]
+                    .inner
+                    .size(self.module.to_ctx()),
+            })
+            .collect()
+    }
+
+    /// Returns None if the module doesn't contrain the entry point
+    fn vertex_inputs(
+        &self,
+        entry_point: &str,
+    ) -> Option<Result<Vec<ReflectedVertexInput>, ReflectionError>> {
+        let entry_point = self
+            .module
+            .entry_points
+            .iter()
+            .find(|ep| ep.stage == ShaderStage::Vertex && ep.name == entry_point)?;
+
+        let mut inputs = Vec::new();
+        for argument in &entry_point.function.arguments {
+            let result = match (&argument.binding, &self.module.types[argument.ty].inner) {
+                (Some(binding), inner) => push_vertex_input(binding, inner, &mut inputs),
+                (None, TypeInner::Struct { members, .. }) => {
+                    members.iter().try_for_each(|member| match &member.binding {
+                        Some(binding) => push_vertex_input(
+                            binding,
+                            &self.module.types[member.ty].inner,
+                            &mut inputs,
+                        ),
+                        None => Ok(()),
+                    })
+                }
+                (None, _) => Ok(()),
+            };
+            if let Err(err) = result {
+                return Some(Err(err));
+            }
+        }
+        inputs.sort_by_key(|input| input.location);
+        Some(Ok(inputs))
+    }
+}
+
+fn push_vertex_input(
+    binding: &naga::Binding,
+    inner: &TypeInner,
+    inputs: &mut Vec<ReflectedVertexInput>,
+) -> Result<(), ReflectionError> {
+    let naga::Binding::Location { location, .. } = binding else {
+        return Ok(());
+    };
+    let format =
+        map_vertex_format(inner).ok_or_else(|| ReflectionError::UnsupportedVertexInput {
+            location: *location,
+            ty: format!("{:?}", inner),
+        })?;
+    inputs.push(ReflectedVertexInput {
+        location: *location,
+        format,
+    });
+    Ok(())
+}
+
+fn map_stage(stage: ShaderStage) -> ShaderStages {
+    match stage {
+        ShaderStage::Vertex => ShaderStages::VERTEX,
+        ShaderStage::Fragment => ShaderStages::FRAGMENT,
+        ShaderStage::Compute => ShaderStages::COMPUTE,
+        ShaderStage::Task => ShaderStages::TASK,
+        ShaderStage::Mesh => ShaderStages::MESH,
+    }
+}
+
+fn map_view_dimension(dim: ImageDimension, arrayed: bool) -> TextureViewDimension {
+    match (dim, arrayed) {
+        (ImageDimension::D1, _) => TextureViewDimension::D1,
+        (ImageDimension::D2, false) => TextureViewDimension::D2,
+        (ImageDimension::D2, true) => TextureViewDimension::D2Array,
+        (ImageDimension::D3, _) => TextureViewDimension::D3,
+        (ImageDimension::Cube, false) => TextureViewDimension::Cube,
+        (ImageDimension::Cube, true) => TextureViewDimension::CubeArray,
+    }
+}
+
+fn map_storage_format(format: naga::StorageFormat) -> TextureFormat {
+    use naga::StorageFormat as Sf;
+    match format {
+        Sf::R8Unorm => TextureFormat::R8Unorm,
+        Sf::R8Snorm => TextureFormat::R8Snorm,
+        Sf::R8Uint => TextureFormat::R8Uint,
+        Sf::R8Sint => TextureFormat::R8Sint,
+        Sf::R16Uint => TextureFormat::R16Uint,
+        Sf::R16Sint => TextureFormat::R16Sint,
+        Sf::R16Float => TextureFormat::R16Float,
+        Sf::Rg8Unorm => TextureFormat::Rg8Unorm,
+        Sf::Rg8Snorm => TextureFormat::Rg8Snorm,
+        Sf::Rg8Uint => TextureFormat::Rg8Uint,
+        Sf::Rg8Sint => TextureFormat::Rg8Sint,
+        Sf::R32Uint => TextureFormat::R32Uint,
+        Sf::R32Sint => TextureFormat::R32Sint,
+        Sf::R32Float => TextureFormat::R32Float,
+        Sf::Rg16Uint => TextureFormat::Rg16Uint,
+        Sf::Rg16Sint => TextureFormat::Rg16Sint,
+        Sf::Rg16Float => TextureFormat::Rg16Float,
+        Sf::Rgba8Unorm => TextureFormat::Rgba8Unorm,
+        Sf::Rgba8Snorm => TextureFormat::Rgba8Snorm,
+        Sf::Rgba8Uint => TextureFormat::Rgba8Uint,
+        Sf::Rgba8Sint => TextureFormat::Rgba8Sint,
+        Sf::Bgra8Unorm => TextureFormat::Bgra8Unorm,
+        Sf::Rgb10a2Uint => TextureFormat::Rgb10a2Uint,
+        Sf::Rgb10a2Unorm => TextureFormat::Rgb10a2Unorm,
+        Sf::Rg11b10Ufloat => TextureFormat::Rg11b10Ufloat,
+        Sf::R64Uint => TextureFormat::R64Uint,
+        Sf::Rg32Uint => TextureFormat::Rg32Uint,
+        Sf::Rg32Sint => TextureFormat::Rg32Sint,
+        Sf::Rg32Float => TextureFormat::Rg32Float,
+        Sf::Rgba16Uint => TextureFormat::Rgba16Uint,
+        Sf::Rgba16Sint => TextureFormat::Rgba16Sint,
+        Sf::Rgba16Float => TextureFormat::Rgba16Float,
+        Sf::Rgba32Uint => TextureFormat::Rgba32Uint,
+        Sf::Rgba32Sint => TextureFormat::Rgba32Sint,
+        Sf::Rgba32Float => TextureFormat::Rgba32Float,
+        Sf::R16Unorm => TextureFormat::R16Unorm,
+        Sf::R16Snorm => TextureFormat::R16Snorm,
+        Sf::Rg16Unorm => TextureFormat::Rg16Unorm,
+        Sf::Rg16Snorm => TextureFormat::Rg16Snorm,
+        Sf::Rgba16Unorm => TextureFormat::Rgba16Unorm,
+        Sf::Rgba16Snorm => TextureFormat::Rgba16Snorm,
+    }
+}
+
+fn map_vertex_format(inner: &TypeInner) -> Option<VertexFormat> {
+    let (scalar, size) = match inner {
+        TypeInner::Scalar(scalar) => (*scalar, 1),
+        TypeInner::Vector { size, scalar } => (*scalar, *size as u8),
+        _ => return None,
+    };
+    use VertexFormat as Vf;
+    Some(match (scalar.kind, scalar.width, size) {
+        (ScalarKind::Float, 4, 1) => Vf::Float32,
+        (ScalarKind::Float, 4, 2) => Vf::Float32x2,
+        (ScalarKind::Float, 4, 3) => Vf::Float32x3,
+        (ScalarKind::Float, 4, 4) => Vf::Float32x4,
+        (ScalarKind::Float, 2, 1) => Vf::Float16,
+        (ScalarKind::Float, 2, 2) => Vf::Float16x2,
+        (ScalarKind::Float, 2, 4) => Vf::Float16x4,
+        (ScalarKind::Float, 8, 1) => Vf::Float64,
+        (ScalarKind::Float, 8, 2) => Vf::Float64x2,
+        (ScalarKind::Float, 8, 3) => Vf::Float64x3,
+        (ScalarKind::Float, 8, 4) => Vf::Float64x4,
+        (ScalarKind::Sint, 4, 1) => Vf::Sint32,
+        (ScalarKind::Sint, 4, 2) => Vf::Sint32x2,
+        (ScalarKind::Sint, 4, 3) => Vf::Sint32x3,
+        (ScalarKind::Sint, 4, 4) => Vf::Sint32x4,
+        (ScalarKind::Uint, 4, 1) => Vf::Uint32,
+        (ScalarKind::Uint, 4, 2) => Vf::Uint32x2,
+        (ScalarKind::Uint, 4, 3) => Vf::Uint32x3,
+        (ScalarKind::Uint, 4, 4) => Vf::Uint32x4,
+        _ => return None,
+    })
+}
+
+/// Normalized formats are read as floats by the shader, so only the kind has to match
+fn vertex_format_kind(format: VertexFormat) -> ScalarKind {
+    use VertexFormat as Vf;
+    match format {
+        Vf::Uint8 | Vf::Uint8x2 | Vf::Uint8x4 | Vf::Uint16 | Vf::Uint16x2 | Vf::Uint16x4 => {
+            ScalarKind::Uint
+        }
+        Vf::Uint32 | Vf::Uint32x2 | Vf::Uint32x3 | Vf::Uint32x4 => ScalarKind::Uint,
+        Vf::Sint8 | Vf::Sint8x2 | Vf::Sint8x4 | Vf::Sint16 | Vf::Sint16x2 | Vf::Sint16x4 => {
+            ScalarKind::Sint
+        }
+        Vf::Sint32 | Vf::Sint32x2 | Vf::Sint32x3 | Vf::Sint32x4 => ScalarKind::Sint,
+        _ => ScalarKind::Float,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bind_group::BindGroupEntryBuilder;
+
+    const VERTEX: &str = r#"
+    @group(0) @binding(0) var<uniform> camera: mat4x4<f32>;
+
+    struct VertexInput {
+        @location(0) position: vec3<f32>,
+        @location(2) color: vec4<f32>,
+    }
+
+    @vertex
+    fn vs_main(input: VertexInput, @location(5) id: u32) -> @builtin(position) vec4<f32> {
+        return camera * vec4<f32>(input.position, f32(id));
+    }
+    "#;
+
+    const FRAGMENT: &str = r#"
+    @group(0) @binding(0) var<uniform> camera: mat4x4<f32>;
+    @group(2) @binding(0) var diffuse: texture_2d<f32>;
+    @group(2) @binding(1) var diffuse_sampler: sampler;
+    @group(2) @binding(2) var shadow: texture_depth_2d;
+    @group(2) @binding(3) var shadow_sampler: sampler_comparison;
+    @group(2) @binding(4) var<storage, read> lights: array<vec4<f32>>;
+    @group(2) @binding(5) var<storage, read_write> counters: array<u32>;
+
+    @fragment
+    fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+        counters[0] = 1u;
+        let depth = textureSampleCompare(shadow, shadow_sampler, position.xy, 0.5);
+        let color = textureSample(diffuse, diffuse_sampler, position.xy);
+        return camera[0] * color * depth + lights[0];
+    }
+    "#;
+
+    fn attribute(format: VertexFormat, shader_location: u32) -> wgpu::VertexAttribute {
+        wgpu::VertexAttribute {
+            format,
+            offset: 0,
+            shader_location,
+        }
+    }
+
+    fn buffer(attributes: &[wgpu::VertexAttribute]) -> VertexBufferLayout<'_> {
+        VertexBufferLayout {
+            array_stride: 64,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes,
+        }
+    }
+
+    #[test]
+    fn entries_of_each_group_are_indexed_by_the_group() {
+        let reflection = ShaderReflection::from_wgsl(FRAGMENT).unwrap();
+        let groups = reflection.bind_group_layout_entries().unwrap();
+        assert_eq!(groups.len(), 3);
+        assert!(groups[1].is_empty());
+
+        let fragment = wgpu::ShaderStages::FRAGMENT;
+        assert_eq!(
+            groups[2],
+            vec![
+                BindGroupEntryBuilder::new(0)
+                    .on(fragment)
+                    .texture()
+                    .layout_entry(),
+                BindGroupEntryBuilder::new(1)
+                    .on(fragment)
+                    .sampler()
+                    .layout_entry(),
+                BindGroupEntryBuilder::new(2)
+                    .on(fragment)
+                    .depth_texture()
+                    .layout_entry(),
+                BindGroupEntryBuilder::new(3)
+                    .on(fragment)
+                    .comparison_sampler()
+                    .layout_entry(),
+                BindGroupEntryBuilder::new(4)
+                    .on(fragment)
+                    .storage(true)
+                    .layout_entry(),
+                BindGroupEntryBuilder::new(5)
+                    .on(fragment)
+                    .storage(false)
+                    .layout_entry(),
+            ]
+        );
+    }
+
+    #[test]
+    fn binding_arrays_keep_their_count() {
+        let reflection = ShaderReflection::from_wgsl(
+            r#"
+            @group(0) @binding(0) var textures: binding_array<texture_2d<f32>, 8>;
+            @group(0) @binding(1) var texture_sampler: sampler;
+
+            @fragment
+            fn fs_main() -> @location(0) vec4<f32> {
+                return textureSample(textures[0], texture_sampler, vec2<f32>());
+            }
+            "#,
+        )
+        .unwrap();
+        let groups = reflection.bind_group_layout_entries().unwrap();
+        assert_eq!(groups[0][0].count, std::num::NonZero::new(8));
+        assert_eq!(groups[0][1].count, None);
+    }
+
+    #[test]
+    fn merged_modules_join_the_visibility_of_shared_bindings() {
+        let reflection = ShaderReflection::from_wgsl(VERTEX)
+            .unwrap()
+            .merge(ShaderReflection::from_wgsl(FRAGMENT).unwrap());
+        assert_eq!(reflection.modules().count(), 2);
+
+        let groups = reflection.bind_group_layout_entries().unwrap();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].len(), 1);
+        assert_eq!(
+            groups[0][0].visibility,
+            wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT
+        );
+        assert!(
+            groups[2]
+                .iter()
+                .all(|entry| entry.visibility == wgpu::ShaderStages::FRAGMENT)
+        );
+        // the vertex inputs are found in the module that declares the entry point
+        assert_eq!(reflection.vertex_inputs("vs_main").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn vertex_inputs_are_sorted_by_location() {
+        let reflection = ShaderReflection::from_wgsl(VERTEX).unwrap();
+        assert_eq!(
+            reflection.vertex_inputs("vs_main").unwrap(),
+            vec![
+                ReflectedVertexInput {
+                    location: 0,
+                    format: VertexFormat::Float32x3,
+                },
+                ReflectedVertexInput {
+                    location: 2,
+                    format: VertexFormat::Float32x4,
+                },
+                ReflectedVertexInput {
+                    location: 5,
+                    format: VertexFormat::Uint32,
+                },
+            ]
+        );
+        assert!(matches!(
+            reflection.vertex_inputs("main"),
+            Err(ReflectionError::MissingEntryPoint(name)) if name == "main"
+        ));
+    }
+
+    #[test]
+    fn vertex_buffers_must_provide_every_input() {
+        let reflection = ShaderReflection::from_wgsl(VERTEX).unwrap();
+        let vertex = [
+            attribute(VertexFormat::Float32x3, 0),
+            // normalized formats are read as floats
+            attribute(VertexFormat::Unorm8x4, 2),
+        ];
+        let instance = [attribute(VertexFormat::Uint32, 5)];
+        assert!(
+            reflection
+                .check_vertex_buffers("vs_main", &[buffer(&vertex), buffer(&instance)])
+                .is_ok()
+        );
+
+        assert!(matches!(
+            reflection.check_vertex_buffers("vs_main", &[buffer(&vertex)]),
+            Err(ReflectionError::MissingVertexAttribute(5))
+        ));
+
+        let instance = [attribute(VertexFormat::Float32, 5)];
+        assert!(matches!(
+            reflection.check_vertex_buffers("vs_main", &[buffer(&vertex), buffer(&instance)]),
+            Err(ReflectionError::VertexFormatMismatch {
+                location: 5,
+                expected: VertexFormat::Uint32,
+                found: VertexFormat::Float32,
+            })
+        ));
+    }
+
+    #[test]
+    fn invalid_shaders_are_reported() {
+        assert!(matches!(
+            ShaderReflection::from_wgsl("fn broken( {"),
+            Err(ReflectionError::Parse(_))
+        ));
+        assert!(matches!(
+            ShaderReflection::from_wgsl(
+                "@vertex fn vs_main() -> @builtin(position) vec4<f32> { return 1u; }"
+            ),
+            Err(ReflectionError::Validation(_))
+        ));
+    }
+}
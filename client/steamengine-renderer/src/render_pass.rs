@@ -53,7 +53,7 @@ impl<'a> RenderPassDescriptorBuilder<'a> {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct RenderPassColorAttachmentBuilder<'a> {
     resolve_target: Option<&'a wgpu::TextureView>,
     ops: wgpu::Operations<wgpu::Color>,
@@ -92,6 +92,7 @@ impl<'a> RenderPassColorAttachmentBuilder<'a> {
     }
 }
 
+#[derive(Default)]
 pub struct RenderPassDepthStencilAttachmentBuilder {
     depth_ops: Option<wgpu::Operations<f32>>,
     stencil_ops: Option<wgpu::Operations<u32>>,
@@ -111,7 +112,7 @@ impl RenderPassDepthStencilAttachmentBuilder {
         self.stencil_ops = Some(ops);
         self
     }
-    pub fn build(self, view: &TextureView) -> RenderPassDepthStencilAttachment {
+    pub fn build(self, view: &TextureView) -> RenderPassDepthStencilAttachment<'_> {
         RenderPassDepthStencilAttachment {
             view,
             depth_ops: self.depth_ops,
@@ -1,22 +1,58 @@
+use std::collections::HashMap;
 use std::num::NonZero;
 
 use wgpu::{PipelineCache, PipelineCompilationOptions, VertexBufferLayout};
 
 use super::Renderer;
+use super::errors::ReflectionError;
+use super::pipeline_registry::PipelineKey;
+use super::push_constants::PushConstantLayout;
+use super::reflection::ShaderReflection;
+use super::shader::ShaderSource;
 
 /// this trait is for create of render pipelines
 pub trait RenderPipeline {
     fn label(&self) -> &str;
-    /// returns the shader code
-    fn source(&self) -> &str;
-    fn buffers(&self) -> Vec<VertexBufferLayout> {
+    /// returns the shader code, a `&str` is converted into WGSL
+    fn source(&self) -> ShaderSource<'_>;
+    /// returns the shader code of the fragment stage
+    /// if it is None the fragment stage uses `source`, GLSL pipelines need one source per stage
+    fn fragment_source(&self) -> Option<ShaderSource<'_>> {
+        None
+    }
+    fn buffers(&self) -> Vec<VertexBufferLayout<'_>> {
         vec![]
     }
-    fn vertex_compilation(&self) -> PipelineCompilationOptions {
+    /// returns the name of the vertex entry point, default `vs_main` in WGSL and `main` in others
+    fn vertex_entry_point(&self) -> &str {
+        self.source().default_entry_point(naga::ShaderStage::Vertex)
+    }
+    /// returns the name of the fragment entry point, default `fs_main` in WGSL and `main` in others
+    /// if it is None the pipeline is created without fragment stage, Ex: depth only pipelines
+    /// GLSL and SPIR-V pipelines without `fragment_source` have no fragment stage by default, a
+    /// SPIR-V module with both stages must return its fragment entry point here
+    fn fragment_entry_point(&self) -> Option<&str> {
+        match self.fragment_source() {
+            Some(source) => Some(source.default_entry_point(naga::ShaderStage::Fragment)),
+            None => {
+                let source = self.source();
+                source
+                    .is_wgsl()
+                    .then(|| source.default_entry_point(naga::ShaderStage::Fragment))
+            }
+        }
+    }
+    /// returns the values of the `override` constants of the shader, they are used in both stages
+    fn constants(&self) -> HashMap<String, f64> {
+        HashMap::new()
+    }
+    /// compilation options of the vertex stage
+    /// its constants are merged with `constants`, the ones of the options win
+    fn vertex_compilation(&self) -> PipelineCompilationOptions<'_> {
         PipelineCompilationOptions::default()
     }
-    fn targets<'a>(&self, renderer: &Renderer) -> Vec<Option<wgpu::ColorTargetState>> {
-        let format = renderer.config.read().unwrap().format.clone();
+    fn targets(&self, renderer: &Renderer) -> Vec<Option<wgpu::ColorTargetState>> {
+        let format = renderer.config.read().unwrap().format;
         vec![Some(wgpu::ColorTargetState {
             // 4.
             format,
@@ -24,7 +60,9 @@ pub trait RenderPipeline {
             write_mask: wgpu::ColorWrites::ALL,
         })]
     }
-    fn fragment_compilation(&self) -> PipelineCompilationOptions {
+    /// compilation options of the fragment stage
+    /// its constants are merged with `constants`, the ones of the options win
+    fn fragment_compilation(&self) -> PipelineCompilationOptions<'_> {
         PipelineCompilationOptions::default()
     }
     fn primitive(&self) -> wgpu::PrimitiveState {
@@ -44,9 +82,18 @@ pub trait RenderPipeline {
     fn depth_stencil(&self) -> Option<wgpu::DepthStencilState> {
         None
     }
-    fn multisample(&self) -> wgpu::MultisampleState {
+    /// returns the sample count of the pipeline
+    /// if it is None the pipeline uses `Renderer::sample_count` and it is compiled again when
+    /// the count of the renderer changes, Ex: `Some(1)` for offscreen passes without MSAA
+    fn sample_count(&self) -> Option<u32> {
+        None
+    }
+    /// multisample state, the count is `sample_count`
+    fn multisample(&self, renderer: &Renderer) -> wgpu::MultisampleState {
         wgpu::MultisampleState {
-            count: 1,
+            count: self
+                .sample_count()
+                .unwrap_or_else(|| renderer.sample_count()),
             mask: !0,
             alpha_to_coverage_enabled: false,
         }
@@ -57,47 +104,368 @@ pub trait RenderPipeline {
     fn cache(&self) -> Option<&PipelineCache> {
         None
     }
-    fn layout(&self) -> wgpu::PipelineLayoutDescriptor {
-        wgpu::PipelineLayoutDescriptor {
-            label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[],
-            push_constant_ranges: &[],
+    /// returns the layout of the pipeline, if it is None the layout is reflected from the shader
+    fn layout(&self) -> Option<wgpu::PipelineLayoutDescriptor<'_>> {
+        None
+    }
+    /// returns the push constants of the pipeline, they are added to the reflected layout
+    fn push_constants(&self) -> Vec<PushConstantLayout<'_>> {
+        vec![]
+    }
+    /// returns the reflection of the shader code
+    fn reflect(&self) -> Result<ShaderReflection, ReflectionError> {
+        let reflection = self.source().reflect()?;
+        match self.fragment_source() {
+            Some(source) => Ok(reflection.merge(source.reflect()?)),
+            None => Ok(reflection),
         }
     }
-    fn to_wgpu<'a>(&self, renderer: &Renderer) -> wgpu::RenderPipeline {
+    /// returns the key of the pipeline in the `PipelineRegistry`
+    /// pipelines with the same key share the compiled pipeline
+    fn key(&self, renderer: &Renderer) -> PipelineKey {
+        PipelineKey::new(self, renderer)
+    }
+    /// compiles the pipeline, panics if `try_to_wgpu` fails
+    fn to_wgpu(&self, renderer: &Renderer) -> wgpu::RenderPipeline {
+        self.try_to_wgpu(renderer)
+            .unwrap_or_else(|err| panic!("Cannot create the pipeline {}, {}", self.label(), err))
+    }
+    /// compiles the pipeline, returns an error if the layout can't be reflected or the vertex
+    /// buffers don't provide the inputs of the vertex entry point
+    fn try_to_wgpu(&self, renderer: &Renderer) -> Result<wgpu::RenderPipeline, ReflectionError> {
         let shader = renderer
             .device()
             .create_shader_module(wgpu::ShaderModuleDescriptor {
                 label: Some(format!("Shader Source of {}", self.label()).as_str()),
-                source: wgpu::ShaderSource::Wgsl(self.source().into()),
+                source: self.source().to_wgpu(),
             });
-        let layout = renderer.device().create_pipeline_layout(&self.layout());
-
-        let render_pipeline =
+        let fragment_shader = self.fragment_source().map(|source| {
             renderer
                 .device()
-                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some(format!("Render Pipeline of {}", self.label()).as_str()),
-                    layout: Some(&layout),
-                    vertex: wgpu::VertexState {
-                        module: &shader,
-                        entry_point: Some("vs_main"),
-                        buffers: self.buffers().as_slice(),
-                        compilation_options: self.vertex_compilation(),
-                    },
-                    fragment: Some(wgpu::FragmentState {
-                        module: &shader,
-                        entry_point: Some("fs_main"),
-                        targets: self.targets(renderer).as_slice(),
-                        compilation_options: self.fragment_compilation(),
+                .create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(format!("Fragment Shader Source of {}", self.label()).as_str()),
+                    source: source.to_wgpu(),
+                })
+        });
+        let layout = match self.layout() {
+            Some(layout) => renderer.device().create_pipeline_layout(&layout),
+            None => {
+                let label = format!("Render Pipeline Layout of {}", self.label());
+                let reflection = self.reflect()?;
+                reflection.check_vertex_buffers(self.vertex_entry_point(), &self.buffers())?;
+                reflection.pipeline_layout(&label, renderer, &self.push_constants())?
+            }
+        };
+
+        let constants = self.constants();
+        let vertex_compilation = self.vertex_compilation();
+        let vertex_constants = merge_constants(&constants, vertex_compilation.constants);
+        let vertex_compilation = PipelineCompilationOptions {
+            constants: &vertex_constants,
+            ..vertex_compilation
+        };
+        let fragment_compilation = self.fragment_compilation();
+        let fragment_constants = merge_constants(&constants, fragment_compilation.constants);
+        let fragment_compilation = PipelineCompilationOptions {
+            constants: &fragment_constants,
+            ..fragment_compilation
+        };
+        let targets = self.targets(renderer);
+
+        Ok(renderer
+            .device()
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(format!("Render Pipeline of {}", self.label()).as_str()),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some(self.vertex_entry_point()),
+                    buffers: self.buffers().as_slice(),
+                    compilation_options: vertex_compilation,
+                },
+                fragment: self
+                    .fragment_entry_point()
+                    .map(|entry_point| wgpu::FragmentState {
+                        module: fragment_shader.as_ref().unwrap_or(&shader),
+                        entry_point: Some(entry_point),
+                        targets: targets.as_slice(),
+                        compilation_options: fragment_compilation,
                     }),
-                    primitive: self.primitive(),
-                    depth_stencil: self.depth_stencil(),
-                    multisample: self.multisample(),
-                    multiview: self.multiview(),
-                    cache: self.cache(),
-                });
-
-        render_pipeline
+                primitive: self.primitive(),
+                depth_stencil: self.depth_stencil(),
+                multisample: self.multisample(renderer),
+                multiview: self.multiview(),
+                cache: self.cache(),
+            }))
+    }
+}
+
+/// Joins the constants of the pipeline with the ones of the compilation options, the options win
+/// The result is sorted by name
+pub(crate) fn merge_constants<'a>(
+    constants: &'a HashMap<String, f64>,
+    options: &[(&'a str, f64)],
+) -> Vec<(&'a str, f64)> {
+    let mut merged: Vec<(&str, f64)> = constants
+        .iter()
+        .map(|(name, value)| (name.as_str(), *value))
+        .filter(|(name, _)| !options.iter().any(|(option, _)| option == name))
+        .chain(options.iter().copied())
+        .collect();
+    merged.sort_by(|a, b| a.0.cmp(b.0));
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = r#"
+    @vertex
+    fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+        return vec4<f32>(position, 1.0);
+    }
+
+    @fragment
+    fn fs_main() -> @location(0) vec4<f32> {
+        return vec4<f32>(1.0);
+    }
+    "#;
+
+    const ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x3];
+    const WRONG_ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Uint32x2];
+
+    struct TestPipeline {
+        attributes: &'static [wgpu::VertexAttribute],
+    }
+    impl RenderPipeline for TestPipeline {
+        fn label(&self) -> &str {
+            "Test Pipeline"
+        }
+        fn source(&self) -> ShaderSource<'_> {
+            SHADER.into()
+        }
+        fn buffers(&self) -> Vec<VertexBufferLayout<'_>> {
+            vec![VertexBufferLayout {
+                array_stride: 12,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: self.attributes,
+            }]
+        }
+    }
+
+    const VARIANTS: &str = r#"
+    override scale: f32;
+    override bias: f32 = 0.0;
+
+    @vertex
+    fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+        return vec4<f32>(position * scale + bias, 1.0);
+    }
+
+    @vertex
+    fn vs_shadow(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+        return vec4<f32>(position, 1.0);
+    }
+
+    @fragment
+    fn fs_main() -> @location(0) vec4<f32> {
+        return vec4<f32>(scale);
+    }
+
+    @fragment
+    fn fs_outline() -> @location(0) vec4<f32> {
+        return vec4<f32>(bias);
+    }
+    "#;
+
+    /// a variant of the `VARIANTS` shader
+    struct VariantPipeline {
+        vertex: &'static str,
+        fragment: Option<&'static str>,
+        options: &'static [(&'static str, f64)],
+    }
+    impl RenderPipeline for VariantPipeline {
+        fn label(&self) -> &str {
+            "Variant Pipeline"
+        }
+        fn source(&self) -> ShaderSource<'_> {
+            VARIANTS.into()
+        }
+        fn buffers(&self) -> Vec<VertexBufferLayout<'_>> {
+            vec![VertexBufferLayout {
+                array_stride: 12,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &ATTRIBUTES,
+            }]
+        }
+        fn vertex_entry_point(&self) -> &str {
+            self.vertex
+        }
+        fn fragment_entry_point(&self) -> Option<&str> {
+            self.fragment
+        }
+        fn constants(&self) -> HashMap<String, f64> {
+            HashMap::from([("scale".to_owned(), 2.0), ("bias".to_owned(), 1.0)])
+        }
+        fn vertex_compilation(&self) -> PipelineCompilationOptions<'_> {
+            PipelineCompilationOptions {
+                constants: self.options,
+                ..Default::default()
+            }
+        }
+        fn depth_stencil(&self) -> Option<wgpu::DepthStencilState> {
+            Some(depth_state())
+        }
+    }
+
+    fn depth_state() -> wgpu::DepthStencilState {
+        wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }
+    }
+
+    #[cfg(feature = "glsl")]
+    #[test]
+    fn glsl_vertex_only_pipeline_has_no_fragment_stage() {
+        const GLSL: &str = r#"
+        #version 450
+        layout(location = 0) in vec3 position;
+
+        void main() {
+            gl_Position = vec4(position, 1.0);
+        }
+        "#;
+
+        struct GlslPipeline;
+        impl RenderPipeline for GlslPipeline {
+            fn label(&self) -> &str {
+                "GLSL Pipeline"
+            }
+            fn source(&self) -> ShaderSource<'_> {
+                ShaderSource::glsl(GLSL, naga::ShaderStage::Vertex)
+            }
+            fn buffers(&self) -> Vec<VertexBufferLayout<'_>> {
+                vec![VertexBufferLayout {
+                    array_stride: 12,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &ATTRIBUTES,
+                }]
+            }
+            fn depth_stencil(&self) -> Option<wgpu::DepthStencilState> {
+                Some(depth_state())
+            }
+        }
+
+        let pipeline = GlslPipeline;
+        assert_eq!(pipeline.vertex_entry_point(), "main");
+        assert_eq!(pipeline.fragment_entry_point(), None);
+        let reflection = pipeline.reflect().unwrap();
+        assert_eq!(
+            reflection.vertex_inputs("main").unwrap()[0].format,
+            wgpu::VertexFormat::Float32x3
+        );
+
+        let renderer = crate::testing::renderer();
+        assert!(pipeline.try_to_wgpu(&renderer).is_ok());
+    }
+
+    #[test]
+    fn wgsl_pipelines_use_the_default_entry_points() {
+        let pipeline = TestPipeline {
+            attributes: &ATTRIBUTES,
+        };
+        assert_eq!(pipeline.vertex_entry_point(), "vs_main");
+        assert_eq!(pipeline.fragment_entry_point(), Some("fs_main"));
+    }
+
+    #[test]
+    fn entry_points_select_the_variant() {
+        let renderer = crate::testing::renderer();
+        let variants = [
+            ("vs_main", Some("fs_main")),
+            ("vs_main", Some("fs_outline")),
+            // depth only
+            ("vs_shadow", None),
+        ];
+        let mut keys = Vec::new();
+        for (vertex, fragment) in variants {
+            let pipeline = VariantPipeline {
+                vertex,
+                fragment,
+                options: &[],
+            };
+            assert!(pipeline.try_to_wgpu(&renderer).is_ok());
+            keys.push(pipeline.key(&renderer));
+        }
+        assert_ne!(keys[0], keys[1]);
+        assert_ne!(keys[0], keys[2]);
+    }
+
+    #[test]
+    fn constants_of_the_options_win() {
+        let constants = HashMap::from([("scale".to_owned(), 2.0), ("bias".to_owned(), 1.0)]);
+        assert_eq!(
+            merge_constants(&constants, &[]),
+            vec![("bias", 1.0), ("scale", 2.0)]
+        );
+        assert_eq!(
+            merge_constants(&constants, &[("bias", 3.0), ("offset", 4.0)]),
+            vec![("bias", 3.0), ("offset", 4.0), ("scale", 2.0)]
+        );
+        assert_eq!(
+            merge_constants(&HashMap::new(), &[("bias", 3.0)]),
+            vec![("bias", 3.0)]
+        );
+    }
+
+    #[test]
+    fn constants_are_merged_into_the_pipeline() {
+        let renderer = crate::testing::renderer();
+        let pipeline = |options| VariantPipeline {
+            vertex: "vs_main",
+            fragment: Some("fs_main"),
+            options,
+        };
+        // `scale` has no default, it comes from `constants` while the options set `bias`
+        assert!(pipeline(&[("bias", 3.0)]).try_to_wgpu(&renderer).is_ok());
+        assert_ne!(
+            pipeline(&[("bias", 3.0)]).key(&renderer),
+            pipeline(&[]).key(&renderer)
+        );
+        assert_eq!(
+            pipeline(&[("bias", 1.0)]).key(&renderer),
+            pipeline(&[]).key(&renderer)
+        );
+    }
+
+    #[test]
+    fn pipeline_with_matching_buffers_is_created() {
+        let renderer = crate::testing::renderer();
+        let pipeline = TestPipeline {
+            attributes: &ATTRIBUTES,
+        };
+        assert!(pipeline.try_to_wgpu(&renderer).is_ok());
+    }
+
+    #[test]
+    fn mismatched_vertex_buffers_are_an_error() {
+        let renderer = crate::testing::renderer();
+        let pipeline = TestPipeline {
+            attributes: &WRONG_ATTRIBUTES,
+        };
+        assert!(matches!(
+            pipeline.try_to_wgpu(&renderer),
+            Err(ReflectionError::VertexFormatMismatch { location: 0, .. })
+        ));
+        let pipeline = TestPipeline { attributes: &[] };
+        assert!(matches!(
+            pipeline.try_to_wgpu(&renderer),
+            Err(ReflectionError::MissingVertexAttribute(0))
+        ));
     }
 }
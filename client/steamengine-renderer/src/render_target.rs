@@ -0,0 +1,309 @@
+use tracing::*;
+use wgpu::{
+    RenderPassColorAttachment, RenderPassDepthStencilAttachment, TextureFormat, TextureSampleType,
+    TextureUsages,
+};
+
+use super::Renderer;
+use super::render_pass::{
+    RenderPassColorAttachmentBuilder, RenderPassDepthStencilAttachmentBuilder,
+};
+use super::sampler::SamplerPreset;
+use super::texture::{Texture, TextureBuilder, TextureDimensions};
+
+/// Textures that a render pass draws into, Ex: mirrors, minimaps or thumbnails
+/// Every color texture and the depth texture have the same size and are sampleable after the pass
+/// The depth texture uses `SamplerPreset::SHADOW` by default, a comparison sampler that matches
+/// the `default_bind_group` of the depth formats, change it with `depth_sampler_preset`
+/// ## Example
+/// ```rust
+/// use steamengine_renderer::Renderer;
+/// use steamengine_renderer::render_pass::*;
+/// use steamengine_renderer::render_target::RenderTarget;
+/// use wgpu::TextureFormat;
+///
+/// fn minimap(renderer: &Renderer, encoder: &mut wgpu::CommandEncoder) {
+///     let target = RenderTarget::new(
+///         renderer,
+///         "Minimap",
+///         (256, 256),
+///         &[TextureFormat::Rgba8UnormSrgb],
+///         Some(TextureFormat::Depth32Float),
+///     );
+///
+///     let colors =
+///         target.color_attachments(RenderPassColorAttachmentBuilder::from_color(0.0, 0.0, 0.0, 1.0));
+///     let descriptor = RenderPassDescriptorBuilder::new("Minimap Pass").with_colors(&colors);
+///     let depth = RenderPassDepthStencilAttachmentBuilder::new().depth_ops(wgpu::Operations {
+///         load: wgpu::LoadOp::Clear(1.0),
+///         store: wgpu::StoreOp::Store,
+///     });
+///     let descriptor = match target.depth_attachment(depth) {
+///         Some(depth) => descriptor.with_depth(depth),
+///         None => descriptor,
+///     };
+///     let render_pass = encoder.begin_render_pass(&descriptor.build());
+///     // draw the minimap
+///     drop(render_pass);
+///
+///     let (minimap_layout, minimap) = target.color(0).default_bind_group("Minimap", renderer);
+/// }
+/// ```
+pub struct RenderTarget {
+    label: &'static str,
+    size: (u32, u32),
+    formats: Vec<TextureFormat>,
+    depth_format: Option<TextureFormat>,
+    depth_sampler: SamplerPreset,
+    colors: Vec<Texture>,
+    depth: Option<Texture>,
+}
+impl RenderTarget {
+    /// create a new target with a color texture per format and an optional depth texture
+    pub fn new(
+        renderer: &Renderer,
+        label: &'static str,
+        size: (u32, u32),
+        formats: &[TextureFormat],
+        depth_format: Option<TextureFormat>,
+    ) -> Self {
+        let mut target = Self {
+            label,
+            size,
+            formats: formats.to_vec(),
+            depth_format,
+            depth_sampler: SamplerPreset::SHADOW,
+            colors: Vec::new(),
+            depth: None,
+        };
+        target.create_textures(renderer);
+        target
+    }
+    /// recreates the textures with the new size, the bind groups of the old textures must be
+    /// created again
+    pub fn resize(&mut self, renderer: &Renderer, size: (u32, u32)) {
+        if self.size == size {
+            return;
+        }
+        self.size = size;
+        self.create_textures(renderer);
+    }
+    fn create_textures(&mut self, renderer: &Renderer) {
+        trace!(
+            "Creating render target {}x{} -- {}",
+            self.size.0, self.size.1, self.label
+        );
+        let (width, height) = (self.size.0.max(1), self.size.1.max(1));
+        let usage = TextureUsages::RENDER_ATTACHMENT
+            | TextureUsages::TEXTURE_BINDING
+            | TextureUsages::COPY_SRC;
+        self.colors = self
+            .formats
+            .iter()
+            .map(|format| {
+                let mut texture = renderer.init_texture(
+                    self.label,
+                    None,
+                    TextureBuilder::new()
+                        .dimension(TextureDimensions::new_2d(width, height))
+                        .format(*format)
+                        .usage(usage),
+                );
+                texture.texture_view(wgpu::TextureViewDescriptor::default());
+                // the formats that aren't filterable need a nearest sampler, Ex: R32Uint ids
+                let preset = match texture.sample_type(renderer.features()) {
+                    Some(TextureSampleType::Float { filterable: true }) => SamplerPreset::BILINEAR,
+                    _ => SamplerPreset::PIXEL_ART,
+                };
+                texture.sampler_preset(preset, renderer);
+                texture
+            })
+            .collect();
+        self.depth = self.depth_format.map(|format| {
+            let mut texture = renderer.init_texture(
+                self.label,
+                None,
+                TextureBuilder::new()
+                    .dimension(TextureDimensions::new_2d(width, height))
+                    .format(format)
+                    .usage(usage),
+            );
+            texture.texture_view(wgpu::TextureViewDescriptor::default());
+            texture.sampler_preset(self.depth_sampler, renderer);
+            texture
+        });
+    }
+    /// Sets the sampler of the depth texture, it's kept when the target is resized
+    /// The `default_bind_group` of the depth formats needs a comparison sampler, the other
+    /// presets need a bind group with a matching sampler binding
+    pub fn depth_sampler_preset(&mut self, preset: SamplerPreset, renderer: &Renderer) {
+        self.depth_sampler = preset;
+        if let Some(depth) = &mut self.depth {
+            depth.sampler_preset(preset, renderer);
+        }
+    }
+    /// Gets the color attachments of the pass, one per color texture
+    pub fn color_attachments<'a>(
+        &'a self,
+        builder: RenderPassColorAttachmentBuilder<'a>,
+    ) -> Vec<Option<RenderPassColorAttachment<'a>>> {
+        self.colors
+            .iter()
+            .map(|texture| Some(builder.clone().build(view_of(texture))))
+            .collect()
+    }
+    /// Gets the depth attachment of the pass, None if the target doesn't have depth
+    pub fn depth_attachment(
+        &self,
+        builder: RenderPassDepthStencilAttachmentBuilder,
+    ) -> Option<RenderPassDepthStencilAttachment<'_>> {
+        self.depth
+            .as_ref()
+            .map(|texture| builder.build(view_of(texture)))
+    }
+    /// Gets the color targets of the pipelines that render into the target
+    pub fn targets(&self) -> Vec<Option<wgpu::ColorTargetState>> {
+        self.formats
+            .iter()
+            .map(|format| {
+                Some(wgpu::ColorTargetState {
+                    format: *format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })
+            })
+            .collect()
+    }
+    /// Gets a color texture, panics if the index is outside of the formats
+    pub fn color(&self, index: usize) -> &Texture {
+        &self.colors[index]
+    }
+    pub fn colors(&self) -> &[Texture] {
+        &self.colors
+    }
+    pub fn depth(&self) -> Option<&Texture> {
+        self.depth.as_ref()
+    }
+    pub fn depth_format(&self) -> Option<TextureFormat> {
+        self.depth_format
+    }
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+}
+
+fn view_of(texture: &Texture) -> &wgpu::TextureView {
+    texture
+        .texture_view
+        .as_ref()
+        .expect("Texture view of the render target created before")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn textures_have_the_formats_and_the_size() {
+        let renderer = crate::testing::renderer();
+        let target = RenderTarget::new(
+            &renderer,
+            "Target",
+            (32, 16),
+            &[TextureFormat::Rgba8UnormSrgb, TextureFormat::R32Uint],
+            Some(TextureFormat::Depth32Float),
+        );
+        assert_eq!(target.colors().len(), 2);
+        assert_eq!(target.color(1).texture.format(), TextureFormat::R32Uint);
+        let depth = target.depth().unwrap();
+        assert_eq!(depth.texture.format(), TextureFormat::Depth32Float);
+        for texture in target.colors().iter().chain(target.depth()) {
+            assert_eq!(
+                (texture.texture.width(), texture.texture.height()),
+                (32, 16)
+            );
+            assert!(
+                texture
+                    .texture
+                    .usage()
+                    .contains(TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING)
+            );
+        }
+        let formats: Vec<_> = target
+            .targets()
+            .into_iter()
+            .map(|target| target.unwrap().format)
+            .collect();
+        assert_eq!(
+            formats,
+            [TextureFormat::Rgba8UnormSrgb, TextureFormat::R32Uint]
+        );
+        assert_eq!(
+            target
+                .color_attachments(RenderPassColorAttachmentBuilder::new())
+                .len(),
+            2
+        );
+        assert!(
+            target
+                .depth_attachment(RenderPassDepthStencilAttachmentBuilder::new())
+                .is_some()
+        );
+
+        let target = RenderTarget::new(&renderer, "Target", (8, 8), &[], None);
+        assert!(target.depth().is_none());
+        assert!(
+            target
+                .depth_attachment(RenderPassDepthStencilAttachmentBuilder::new())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn samplers_match_the_sample_type() {
+        let renderer = crate::testing::renderer();
+        let target = RenderTarget::new(
+            &renderer,
+            "Target",
+            (8, 8),
+            &[TextureFormat::Rgba8UnormSrgb, TextureFormat::R32Uint],
+            Some(TextureFormat::Depth24PlusStencil8),
+        );
+        let sampler = |texture: &Texture| texture.texture_sampler.clone().unwrap();
+        assert!(sampler(target.color(0)) == renderer.sampler_preset(SamplerPreset::BILINEAR));
+        assert!(sampler(target.color(1)) == renderer.sampler_preset(SamplerPreset::PIXEL_ART));
+        let depth = target.depth().unwrap();
+        assert!(sampler(depth) == renderer.sampler_preset(SamplerPreset::SHADOW));
+        // the default bind groups are valid with the samplers
+        for texture in target.colors().iter().chain(target.depth()) {
+            texture.default_bind_group("Target", &renderer);
+        }
+    }
+
+    #[test]
+    fn resize_keeps_the_depth_sampler() {
+        let renderer = crate::testing::renderer();
+        let mut target = RenderTarget::new(
+            &renderer,
+            "Target",
+            (8, 8),
+            &[TextureFormat::Rgba8UnormSrgb],
+            Some(TextureFormat::Depth32Float),
+        );
+        let nearest = SamplerPreset::PIXEL_ART;
+        target.depth_sampler_preset(nearest, &renderer);
+        let depth = |target: &RenderTarget| target.depth().unwrap().texture_sampler.clone();
+        assert!(depth(&target) == Some(renderer.sampler_preset(nearest)));
+
+        let texture = target.color(0).texture.clone();
+        target.resize(&renderer, (8, 8));
+        assert!(target.color(0).texture == texture);
+
+        target.resize(&renderer, (0, 24));
+        assert_eq!(target.size(), (0, 24));
+        // the textures have at least one pixel
+        assert_eq!(target.color(0).texture.width(), 1);
+        assert_eq!(target.depth().unwrap().texture.height(), 24);
+        assert!(depth(&target) == Some(renderer.sampler_preset(nearest)));
+    }
+}
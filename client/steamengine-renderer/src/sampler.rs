@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use tracing::*;
+use wgpu::{AddressMode, CompareFunction, FilterMode, Sampler, SamplerBorderColor};
+
+/// Filter of a sampler preset
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SamplerFilter {
+    /// nearest texel without mip levels blending, Ex: pixel art
+    Nearest,
+    /// linear filter, the nearest mip level
+    Bilinear,
+    /// linear filter, blends the mip levels
+    Trilinear,
+    /// trilinear with anisotropic filtering, the value is the max anisotropy from 1 to 16
+    Anisotropic(u16),
+    /// comparison sampler with a linear filter, Ex: shadow maps
+    Shadow(CompareFunction),
+}
+
+/// Named sampler descriptions, the samplers of the presets are shared by the `SamplerCache`
+/// ## Example
+/// ```rust
+/// use steamengine_renderer::sampler::SamplerPreset;
+///
+/// let descriptor = SamplerPreset::PIXEL_ART.repeat().descriptor();
+/// assert_eq!(descriptor.mag_filter, wgpu::FilterMode::Nearest);
+/// assert_eq!(descriptor.address_mode_u, wgpu::AddressMode::Repeat);
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerPreset {
+    pub filter: SamplerFilter,
+    pub address_mode: AddressMode,
+}
+impl SamplerPreset {
+    pub const PIXEL_ART: Self = Self::new(SamplerFilter::Nearest);
+    pub const BILINEAR: Self = Self::new(SamplerFilter::Bilinear);
+    pub const TRILINEAR: Self = Self::new(SamplerFilter::Trilinear);
+    pub const ANISOTROPIC_16: Self = Self::new(SamplerFilter::Anisotropic(16));
+    pub const SHADOW: Self = Self::new(SamplerFilter::Shadow(CompareFunction::LessEqual));
+
+    /// create a new preset that clamps to the edge
+    pub const fn new(filter: SamplerFilter) -> Self {
+        Self {
+            filter,
+            address_mode: AddressMode::ClampToEdge,
+        }
+    }
+    /// clamps the coordinates to the edge of the texture
+    pub const fn clamp(mut self) -> Self {
+        self.address_mode = AddressMode::ClampToEdge;
+        self
+    }
+    /// repeats the texture outside of 0..1
+    pub const fn repeat(mut self) -> Self {
+        self.address_mode = AddressMode::Repeat;
+        self
+    }
+    /// repeats the texture mirrored outside of 0..1
+    pub const fn mirror(mut self) -> Self {
+        self.address_mode = AddressMode::MirrorRepeat;
+        self
+    }
+    /// returns true if the preset samples the mip levels, the textures should have all of them
+    pub fn uses_mipmaps(&self) -> bool {
+        matches!(
+            self.filter,
+            SamplerFilter::Trilinear | SamplerFilter::Anisotropic(_)
+        )
+    }
+    /// Convert the preset into a wgpu descriptor
+    pub fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        let (filter, mipmap_filter) = match self.filter {
+            SamplerFilter::Nearest => (FilterMode::Nearest, FilterMode::Nearest),
+            SamplerFilter::Bilinear | SamplerFilter::Shadow(_) => {
+                (FilterMode::Linear, FilterMode::Nearest)
+            }
+            SamplerFilter::Trilinear | SamplerFilter::Anisotropic(_) => {
+                (FilterMode::Linear, FilterMode::Linear)
+            }
+        };
+        wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter,
+            compare: match self.filter {
+                SamplerFilter::Shadow(compare) => Some(compare),
+                _ => None,
+            },
+            anisotropy_clamp: match self.filter {
+                SamplerFilter::Anisotropic(anisotropy) => anisotropy.clamp(1, 16),
+                _ => 1,
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// Identifies a sampler inside the cache, every field of the descriptor except the label
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct SamplerKey {
+    address_modes: [AddressMode; 3],
+    filters: [FilterMode; 3],
+    lod_clamp: [u32; 2],
+    compare: Option<CompareFunction>,
+    anisotropy_clamp: u16,
+    border_color: Option<SamplerBorderColor>,
+}
+impl SamplerKey {
+    fn new(descriptor: &wgpu::SamplerDescriptor) -> Self {
+        Self {
+            address_modes: [
+                descriptor.address_mode_u,
+                descriptor.address_mode_v,
+                descriptor.address_mode_w,
+            ],
+            filters: [
+                descriptor.mag_filter,
+                descriptor.min_filter,
+                descriptor.mipmap_filter,
+            ],
+            lod_clamp: [
+                descriptor.lod_min_clamp.to_bits(),
+                descriptor.lod_max_clamp.to_bits(),
+            ],
+            compare: descriptor.compare,
+            anisotropy_clamp: descriptor.anisotropy_clamp,
+            border_color: descriptor.border_color,
+        }
+    }
+}
+
+/// This is a cache of samplers, the samplers with the same descriptor are shared
+pub struct SamplerCache {
+    samplers: RwLock<HashMap<SamplerKey, Sampler>>,
+}
+impl Default for SamplerCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl SamplerCache {
+    pub fn new() -> Self {
+        Self {
+            samplers: RwLock::new(HashMap::new()),
+        }
+    }
+    /// gets the sampler of the descriptor, creates it if it isn't in the cache
+    /// the label is only used when the sampler is created
+    pub fn get_or_create(
+        &self,
+        descriptor: &wgpu::SamplerDescriptor,
+        device: &wgpu::Device,
+    ) -> Sampler {
+        let key = SamplerKey::new(descriptor);
+        if let Some(sampler) = self
+            .samplers
+            .read()
+            .expect("Cannot read samplers")
+            .get(&key)
+        {
+            return sampler.clone();
+        }
+        trace!("Creating sampler -- {:?}", descriptor.label);
+        let sampler = device.create_sampler(descriptor);
+        self.samplers
+            .write()
+            .expect("Cannot write samplers")
+            .entry(key)
+            .or_insert(sampler)
+            .clone()
+    }
+    /// number of cached samplers
+    pub fn len(&self) -> usize {
+        self.samplers.read().expect("Cannot read samplers").len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,126 @@
+use std::borrow::Cow;
+
+use super::{errors::ReflectionError, reflection::ShaderReflection};
+
+/// This enum contrains the source of a shader module
+/// ## Example
+/// ```rust
+/// use steamengine_renderer::shader::ShaderSource;
+///
+/// let source: ShaderSource = "@vertex fn vs_main() -> @builtin(position) vec4<f32> { return vec4<f32>(); }".into();
+/// assert!(source.is_wgsl());
+/// ```
+#[derive(Clone, Debug, Hash)]
+pub enum ShaderSource<'a> {
+    /// WGSL code, may contrain every stage of the pipeline
+    Wgsl(Cow<'a, str>),
+    /// GLSL code of a single stage
+    /// Enable it with feature "glsl"
+    #[cfg(feature = "glsl")]
+    Glsl {
+        source: Cow<'a, str>,
+        stage: naga::ShaderStage,
+        /// `#define` of the preprocessor
+        defines: &'a [(&'a str, &'a str)],
+    },
+    /// Compiled SPIR-V bytes
+    /// Enable it with feature "spirv"
+    #[cfg(feature = "spirv")]
+    SpirV(Cow<'a, [u8]>),
+}
+impl<'a> ShaderSource<'a> {
+    /// Create a GLSL source without defines
+    #[cfg(feature = "glsl")]
+    pub fn glsl(source: impl Into<Cow<'a, str>>, stage: naga::ShaderStage) -> Self {
+        Self::Glsl {
+            source: source.into(),
+            stage,
+            defines: &[],
+        }
+    }
+    /// Create a SPIR-V source from the bytes of a `.spv` file
+    #[cfg(feature = "spirv")]
+    pub fn spirv(bytes: impl Into<Cow<'a, [u8]>>) -> Self {
+        Self::SpirV(bytes.into())
+    }
+    /// returns true if the source is WGSL
+    pub fn is_wgsl(&self) -> bool {
+        matches!(self, Self::Wgsl(_))
+    }
+    /// returns the name of the entry point for a stage when the pipeline doesn't set one
+    /// WGSL uses `vs_main` and `fs_main`, GLSL and SPIR-V modules use `main`
+    pub fn default_entry_point(&self, stage: naga::ShaderStage) -> &'static str {
+        match (self, stage) {
+            (Self::Wgsl(_), naga::ShaderStage::Vertex) => "vs_main",
+            (Self::Wgsl(_), naga::ShaderStage::Fragment) => "fs_main",
+            _ => "main",
+        }
+    }
+    /// Convert the source into the wgpu one
+    pub fn to_wgpu(&self) -> wgpu::ShaderSource<'_> {
+        match self {
+            Self::Wgsl(source) => wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+            #[cfg(feature = "glsl")]
+            Self::Glsl {
+                source,
+                stage,
+                defines,
+            } => wgpu::ShaderSource::Glsl {
+                shader: Cow::Borrowed(source),
+                stage: *stage,
+                defines,
+            },
+            #[cfg(feature = "spirv")]
+            Self::SpirV(bytes) => wgpu::util::make_spirv(bytes),
+        }
+    }
+    /// Parse the source with the naga front-end
+    pub fn to_naga(&self) -> Result<naga::Module, ReflectionError> {
+        match self {
+            Self::Wgsl(source) => naga::front::wgsl::parse_str(source)
+                .map_err(|err| ReflectionError::Parse(err.emit_to_string(source))),
+            #[cfg(feature = "glsl")]
+            Self::Glsl {
+                source,
+                stage,
+                defines,
+            } => {
+                let mut options = naga::front::glsl::Options::from(*stage);
+                options.defines.extend(
+                    defines
+                        .iter()
+                        .map(|(name, value)| (name.to_string(), value.to_string())),
+                );
+                naga::front::glsl::Frontend::default()
+                    .parse(&options, source)
+                    .map_err(|err| ReflectionError::Parse(err.emit_to_string(source)))
+            }
+            #[cfg(feature = "spirv")]
+            Self::SpirV(bytes) => {
+                naga::front::spv::parse_u8_slice(bytes, &naga::front::spv::Options::default())
+                    .map_err(|err| ReflectionError::Parse(err.to_string()))
+            }
+        }
+    }
+    /// Parse and reflect the source
+    pub fn reflect(&self) -> Result<ShaderReflection, ReflectionError> {
+        let module = self.to_naga()?;
+        match self {
+            Self::Wgsl(source) => ShaderReflection::from_module(module, source),
+            #[cfg(feature = "glsl")]
+            Self::Glsl { source, .. } => ShaderReflection::from_module(module, source),
+            #[cfg(feature = "spirv")]
+            Self::SpirV(_) => ShaderReflection::from_module(module, ""),
+        }
+    }
+}
+impl<'a> From<&'a str> for ShaderSource<'a> {
+    fn from(source: &'a str) -> Self {
+        Self::Wgsl(Cow::Borrowed(source))
+    }
+}
+impl From<String> for ShaderSource<'_> {
+    fn from(source: String) -> Self {
+        Self::Wgsl(Cow::Owned(source))
+    }
+}
@@ -0,0 +1,81 @@
+//! Helpers of the unit tests
+
+use std::sync::RwLock;
+
+fn instance() -> wgpu::Instance {
+    wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::NOOP,
+        backend_options: wgpu::BackendOptions {
+            noop: wgpu::NoopBackendOptions { enable: true },
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+}
+
+fn adapter(instance: &wgpu::Instance) -> wgpu::Adapter {
+    pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+        .expect("Cannot get the noop adapter")
+}
+
+/// Creates a device of the noop backend, it validates the calls without a GPU
+pub(crate) fn device() -> (wgpu::Device, wgpu::Queue) {
+    let adapter = adapter(&instance());
+    pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default()))
+        .expect("Cannot get the noop device")
+}
+
+/// Creates a renderer of the noop backend, the surface isn't attached to a window so it can't
+/// be configured or presented
+pub(crate) fn renderer() -> crate::Renderer<'static> {
+    renderer_with(wgpu::Features::empty(), wgpu::Limits::default())
+}
+
+/// Creates a renderer of the noop backend with the features and limits, the noop adapter
+/// supports every feature
+pub(crate) fn renderer_with(
+    required_features: wgpu::Features,
+    required_limits: wgpu::Limits,
+) -> crate::Renderer<'static> {
+    use wgpu::rwh::{RawDisplayHandle, RawWindowHandle, WebDisplayHandle, WebWindowHandle};
+
+    let instance = instance();
+    // the noop backend ignores the handles
+    let surface = unsafe {
+        instance.create_surface_unsafe(wgpu::SurfaceTargetUnsafe::RawHandle {
+            raw_display_handle: RawDisplayHandle::Web(WebDisplayHandle::new()),
+            raw_window_handle: RawWindowHandle::Web(WebWindowHandle::new(1)),
+        })
+    }
+    .expect("Cannot create the noop surface");
+    let adapter = adapter(&instance);
+    let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+        required_features,
+        required_limits,
+        ..Default::default()
+    }))
+    .expect("Cannot get the noop device");
+    let config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: wgpu::TextureFormat::Bgra8UnormSrgb,
+        width: 64,
+        height: 64,
+        present_mode: wgpu::PresentMode::Fifo,
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        view_formats: vec![],
+        desired_maximum_frame_latency: 2,
+    };
+    crate::Renderer {
+        surface: RwLock::new(surface),
+        device,
+        queue,
+        config: RwLock::new(config),
+        size: RwLock::new((64, 64)),
+        sample_count: RwLock::new(1),
+        downlevel: adapter.get_downlevel_capabilities(),
+        adapter,
+        pipelines: crate::pipeline_registry::PipelineRegistry::new(),
+        layouts: crate::bind_group::BindGroupLayoutCache::new(),
+        samplers: crate::sampler::SamplerCache::new(),
+    }
+}
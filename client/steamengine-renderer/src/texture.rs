@@ -1,17 +1,26 @@
 use tracing::*;
 use wgpu::{
     BindingType, Origin3d, Sampler, ShaderStages, TexelCopyBufferLayout, TexelCopyTextureInfo,
-    TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView,
-    TextureViewDescriptor,
+    TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType,
+    TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension,
 };
 
-use super::{Renderer, bind_group::BindGroupEntryBuilder};
+use super::{
+    Renderer, bind_group::BindGroupEntryBuilder, errors::TextureError, mipmap,
+    sampler::SamplerPreset,
+};
 
 /// This structure contrains the dimensions of the texture
 pub enum TextureDimensions {
     D3(u32, u32, u32),
     D2(u32, u32),
     D1(u32),
+    /// width, height and number of layers
+    D2Array(u32, u32, u32),
+    /// size of the faces, the faces are the layers +X, -X, +Y, -Y, +Z, -Z
+    Cube(u32),
+    /// size of the faces and number of cubes
+    CubeArray(u32, u32),
 }
 impl TextureDimensions {
     /// Create a new 3d texture dimension
@@ -26,16 +35,52 @@ impl TextureDimensions {
     pub fn new_1d(width: u32) -> Self {
         Self::D1(width)
     }
+    /// Create a new 2d array texture dimension
+    pub fn new_2d_array(width: u32, height: u32, layers: u32) -> Self {
+        Self::D2Array(width, height, layers)
+    }
+    /// Create a new cube texture dimension
+    pub fn new_cube(size: u32) -> Self {
+        Self::Cube(size)
+    }
+    /// Create a new cube array texture dimension
+    pub fn new_cube_array(size: u32, cubes: u32) -> Self {
+        Self::CubeArray(size, cubes)
+    }
 
     /// Convert the dimension to wgpu::TextureDimension
     pub fn wgpu_texture_dimension(&self) -> TextureDimension {
         match self {
             Self::D3(_, _, _) => TextureDimension::D3,
-            Self::D2(_, _) => TextureDimension::D2,
+            Self::D2(_, _) | Self::D2Array(_, _, _) | Self::Cube(_) | Self::CubeArray(_, _) => {
+                TextureDimension::D2
+            }
             Self::D1(_) => TextureDimension::D1,
         }
     }
 
+    /// Gets the dimension of the views of the texture
+    pub fn view_dimension(&self) -> TextureViewDimension {
+        match self {
+            Self::D3(_, _, _) => TextureViewDimension::D3,
+            Self::D2(_, _) => TextureViewDimension::D2,
+            Self::D1(_) => TextureViewDimension::D1,
+            Self::D2Array(_, _, _) => TextureViewDimension::D2Array,
+            Self::Cube(_) => TextureViewDimension::Cube,
+            Self::CubeArray(_, _) => TextureViewDimension::CubeArray,
+        }
+    }
+
+    /// Number of layers of the texture, 1 in the 3d textures
+    pub fn layers(&self) -> u32 {
+        match self {
+            Self::D2Array(_, _, layers) => *layers,
+            Self::Cube(_) => 6,
+            Self::CubeArray(_, cubes) => 6 * cubes,
+            _ => 1,
+        }
+    }
+
     /// Build the dimension to an Extent3d
     pub fn build(self) -> wgpu::Extent3d {
         match self {
@@ -54,10 +99,26 @@ impl TextureDimensions {
                 height: 1,
                 depth_or_array_layers: 1,
             },
+            Self::D2Array(width, height, layers) => wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: layers,
+            },
+            Self::Cube(size) => wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            Self::CubeArray(size, cubes) => wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6 * cubes,
+            },
         }
     }
 }
 /// This is the builder of the texture
+#[derive(Default)]
 pub struct TextureBuilder {
     // Texture Size
     /// this is the size of the texture
@@ -84,14 +145,18 @@ pub struct TextureBuilder {
     // buffer info
     /// default 0
     offset: Option<u64>,
-    /// default 4 * size.width
+    /// default the bytes of a row of blocks, size.width / block width * block size
     bytes_per_row: Option<u32>,
-    /// default size.height
+    /// default the rows of blocks, size.height / block height
     rows_per_image: Option<u32>,
 
     // image data
     /// this is the data of the texture
     data: Option<Vec<u8>>,
+    /// this is the data of every layer, uploaded from the layer 0
+    layers: Option<Vec<Vec<u8>>>,
+    /// fills the mip levels after the upload, default false
+    generate_mipmaps: bool,
 }
 impl TextureBuilder {
     /// create a new texture builder
@@ -109,6 +174,8 @@ impl TextureBuilder {
             bytes_per_row: None,
             rows_per_image: None,
             data: None,
+            layers: None,
+            generate_mipmaps: false,
         }
     }
 
@@ -172,26 +239,98 @@ impl TextureBuilder {
         self
     }
 
+    /// sets the data of every layer, Ex: the six faces of a cube
+    /// the layers are uploaded in order from the layer of the origin
+    pub fn layers(mut self, layers: Vec<Vec<u8>>) -> Self {
+        self.layers = Some(layers);
+        self
+    }
+
+    /// fills the mip levels from the data, the mip level count is the whole chain if it isn't set
+    /// the levels are rendered by the GPU, or generated in the CPU if the format isn't renderable
+    pub fn generate_mipmaps(mut self) -> Self {
+        self.generate_mipmaps = true;
+        self
+    }
+
+    /// build the texture, panics if the data doesn't match the layout, see `try_build`
     pub fn build(
         self,
         label: &'static str,
         view_formats: Option<&'static [TextureFormat]>,
         renderer: &Renderer,
     ) -> Texture {
+        self.try_build(label, view_formats, renderer)
+            .unwrap_or_else(|err| panic!("Cannot build texture {}, {}", label, err))
+    }
+
+    /// build the texture, checks that the data matches the format and the layout
+    /// the pitch and the rows default to the size of the copy in blocks of the format, so
+    /// block compressed formats use rows of blocks, Ex: 4x4 pixels in BC
+    pub fn try_build(
+        self,
+        label: &'static str,
+        view_formats: Option<&'static [TextureFormat]>,
+        renderer: &Renderer,
+    ) -> Result<Texture, TextureError> {
         trace!("Building -- {}", label);
         let dimensions = self.dimension.expect(
             "Unable to find texture dimensions on {}, please use function 'dimension' to set-it",
         );
         let dimension = dimensions.wgpu_texture_dimension();
+        let view_dimension = dimensions.view_dimension();
         let size = dimensions.build();
-        let mip_level_count = self.mip_level_count.unwrap_or(1);
+        let mip_level_count = match self.generate_mipmaps {
+            true => self
+                .mip_level_count
+                .unwrap_or_else(|| size.max_mips(dimension)),
+            false => self.mip_level_count.unwrap_or(1),
+        };
         let sample_count = self.sample_count.unwrap_or(1);
         let format = self.format.unwrap_or(TextureFormat::Rgba8UnormSrgb);
-        let usage = self
+        let mut usage = self
             .usage
             .unwrap_or(TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST);
-        let label = Some(label);
-        //let data = self.data.expect("Image don't uploaded to texture");
+        let render_mipmaps = self.generate_mipmaps
+            && mip_level_count > 1
+            && dimension == TextureDimension::D2
+            && mipmap::can_render(format, renderer);
+        if render_mipmaps {
+            usage |= TextureUsages::RENDER_ATTACHMENT;
+        }
+
+        let mip_level = self.mip_level.unwrap_or(0);
+        let origin = self.origin.unwrap_or(Origin3d::ZERO);
+        let aspect = self.aspect.unwrap_or(TextureAspect::All);
+        let offset = self.offset.unwrap_or(0);
+        let copy_size = size
+            .mip_level_size(mip_level, dimension)
+            .physical_size(format);
+        let uploaded = self.layers.is_some() || self.data.is_some();
+        // textures without data can use formats that can't be copied, Ex: Depth24Plus
+        let layout = match uploaded {
+            true => UploadLayout::new(
+                format,
+                aspect,
+                copy_size,
+                self.bytes_per_row,
+                self.rows_per_image,
+            )?,
+            false => UploadLayout::default(),
+        };
+        if let Some(layers) = &self.layers {
+            if origin.z + layers.len() as u32 > copy_size.depth_or_array_layers {
+                return Err(TextureError::LayerCount {
+                    layers: layers.len(),
+                    max: copy_size.depth_or_array_layers.saturating_sub(origin.z),
+                });
+            }
+            for data in layers {
+                layout.check(offset, data.len(), 1)?;
+            }
+        } else if let Some(data) = &self.data {
+            layout.check(offset, data.len(), copy_size.depth_or_array_layers)?;
+        }
 
         let texture = renderer.device().create_texture(&TextureDescriptor {
             size,
@@ -200,64 +339,401 @@ impl TextureBuilder {
             dimension,
             format,
             usage,
-            label,
+            label: Some(label),
             view_formats: view_formats.unwrap_or(&[]),
         });
 
-        if self.data.is_some() {
-            let data = self.data.unwrap();
+        let buffer_layout = TexelCopyBufferLayout {
+            offset,
+            bytes_per_row: Some(layout.bytes_per_row),
+            rows_per_image: Some(layout.rows_per_image),
+        };
+        if let Some(layers) = &self.layers {
+            for (layer, data) in layers.iter().enumerate() {
+                renderer.queue().write_texture(
+                    TexelCopyTextureInfo {
+                        texture: &texture,
+                        mip_level,
+                        origin: Origin3d {
+                            z: origin.z + layer as u32,
+                            ..origin
+                        },
+                        aspect,
+                    },
+                    data,
+                    buffer_layout,
+                    wgpu::Extent3d {
+                        depth_or_array_layers: 1,
+                        ..copy_size
+                    },
+                );
+            }
+        } else if let Some(data) = &self.data {
             renderer.queue().write_texture(
                 TexelCopyTextureInfo {
                     texture: &texture,
-                    mip_level: self.mip_level.unwrap_or(0),
-                    origin: self.origin.unwrap_or(Origin3d::ZERO),
-                    aspect: self.aspect.unwrap_or(TextureAspect::All),
-                },
-                &data,
-                TexelCopyBufferLayout {
-                    offset: self.offset.unwrap_or(0),
-                    bytes_per_row: self.bytes_per_row.or(Some(4 * size.width)),
-                    rows_per_image: self.rows_per_image.or(Some(size.height)),
+                    mip_level,
+                    origin,
+                    aspect,
                 },
-                size,
+                data,
+                buffer_layout,
+                copy_size,
             );
         }
 
-        Texture {
+        if self.generate_mipmaps && mip_level_count > 1 && uploaded {
+            if render_mipmaps {
+                mipmap::generate(renderer, &texture);
+            } else if dimension == TextureDimension::D2 && mipmap::can_downsample(format) {
+                // the data of every layer without padding
+                let layers: Vec<Vec<u8>> = match (&self.layers, &self.data) {
+                    (Some(layers), _) => layers
+                        .iter()
+                        .map(|data| layout.unpad(&data[offset as usize..]))
+                        .collect(),
+                    (None, Some(data)) => (0..copy_size.depth_or_array_layers)
+                        .map(|layer| layout.unpad(&data[layout.image_offset(offset, layer)..]))
+                        .collect(),
+                    (None, None) => Vec::new(),
+                };
+                let layer_size = wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..size
+                };
+                for (layer, data) in layers.iter().enumerate() {
+                    let levels = mipmap::downsample(format, layer_size, mip_level_count, data)
+                        .unwrap_or_default();
+                    for (level, data) in levels.iter().enumerate() {
+                        let level = level as u32 + 1;
+                        let size = layer_size.mip_level_size(level, dimension);
+                        renderer.queue().write_texture(
+                            TexelCopyTextureInfo {
+                                texture: &texture,
+                                mip_level: level,
+                                origin: Origin3d {
+                                    z: layer as u32,
+                                    ..Origin3d::ZERO
+                                },
+                                aspect: TextureAspect::All,
+                            },
+                            data,
+                            TexelCopyBufferLayout {
+                                offset: 0,
+                                bytes_per_row: Some(layout.block_size * size.width),
+                                rows_per_image: Some(size.height),
+                            },
+                            size,
+                        );
+                    }
+                }
+            } else {
+                warn!(
+                    "Cannot generate the mip levels of {:?} -- {}",
+                    format, label
+                );
+            }
+        }
+
+        Ok(Texture {
             texture,
+            view_dimension,
             texture_view: None,
             texture_sampler: None,
+        })
+    }
+}
+
+/// Layout of the data of an upload, the rows are rows of blocks of the format
+#[derive(Default)]
+struct UploadLayout {
+    /// bytes of a block, a pixel in the uncompressed formats
+    block_size: u32,
+    /// bytes of a row without padding
+    row_size: u32,
+    rows: u32,
+    bytes_per_row: u32,
+    rows_per_image: u32,
+}
+impl UploadLayout {
+    fn new(
+        format: TextureFormat,
+        aspect: TextureAspect,
+        copy_size: wgpu::Extent3d,
+        bytes_per_row: Option<u32>,
+        rows_per_image: Option<u32>,
+    ) -> Result<Self, TextureError> {
+        let block_size = format
+            .block_copy_size(Some(aspect))
+            .ok_or(TextureError::UnsupportedFormat(format))?;
+        let (block_width, block_height) = format.block_dimensions();
+        let row_size = copy_size.width / block_width * block_size;
+        let rows = copy_size.height / block_height;
+        let bytes_per_row = bytes_per_row.unwrap_or(row_size);
+        let rows_per_image = rows_per_image.unwrap_or(rows);
+        if bytes_per_row < row_size {
+            return Err(TextureError::RowPitch {
+                bytes_per_row,
+                min: row_size,
+            });
+        }
+        if rows_per_image < rows {
+            return Err(TextureError::RowsPerImage {
+                rows_per_image,
+                min: rows,
+            });
+        }
+        Ok(Self {
+            block_size,
+            row_size,
+            rows,
+            bytes_per_row,
+            rows_per_image,
+        })
+    }
+    /// bytes of an image with the padding
+    fn image_size(&self) -> u64 {
+        self.bytes_per_row as u64 * self.rows_per_image as u64
+    }
+    /// start of the image of a layer in data that starts at `offset`
+    fn image_offset(&self, offset: u64, layer: u32) -> usize {
+        (offset + self.image_size() * layer as u64) as usize
+    }
+    /// checks that the data has `layers` images after the offset
+    /// the padding after the last row is optional
+    fn check(&self, offset: u64, len: usize, layers: u32) -> Result<(), TextureError> {
+        let image = self.image_size();
+        let padded = offset + image * layers as u64;
+        let min = padded - image
+            + self.bytes_per_row as u64 * (self.rows as u64 - 1)
+            + self.row_size as u64;
+        let found = len as u64;
+        if found < min || found > padded {
+            return Err(TextureError::DataSize {
+                expected: min,
+                found,
+            });
         }
+        Ok(())
+    }
+    /// copies the rows of an image without the padding
+    fn unpad(&self, data: &[u8]) -> Vec<u8> {
+        (0..self.rows as usize)
+            .flat_map(|row| {
+                let start = row * self.bytes_per_row as usize;
+                &data[start..start + self.row_size as usize]
+            })
+            .copied()
+            .collect()
+    }
+    /// copies the rows of an image without padding into rows of `bytes_per_row`
+    fn pad(&self, data: &[u8], bytes_per_row: u32) -> Vec<u8> {
+        let mut padded = vec![0u8; (bytes_per_row * self.rows) as usize];
+        for row in 0..self.rows as usize {
+            let source = row * self.row_size as usize;
+            let target = row * bytes_per_row as usize;
+            padded[target..target + self.row_size as usize]
+                .copy_from_slice(&data[source..source + self.row_size as usize]);
+        }
+        padded
+    }
+}
+
+/// Region of a texture, a rectangle inside a mip level of a layer
+/// ## Example
+/// ```rust
+/// use steamengine_renderer::texture::TextureRegion;
+///
+/// let region = TextureRegion::new((16, 16), (64, 32)).mip_level(1);
+/// assert_eq!(region.layer, 0);
+/// ```
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextureRegion {
+    /// x and y of the first pixel
+    pub origin: (u32, u32),
+    /// width and height of the region
+    pub size: (u32, u32),
+    pub mip_level: u32,
+    pub layer: u32,
+}
+impl TextureRegion {
+    /// create a new region in the mip level 0 of the layer 0
+    pub fn new(origin: (u32, u32), size: (u32, u32)) -> Self {
+        Self {
+            origin,
+            size,
+            mip_level: 0,
+            layer: 0,
+        }
+    }
+    pub fn mip_level(mut self, mip_level: u32) -> Self {
+        self.mip_level = mip_level;
+        self
+    }
+    pub fn layer(mut self, layer: u32) -> Self {
+        self.layer = layer;
+        self
     }
 }
 
 pub struct Texture {
     pub texture: wgpu::Texture,
+    /// dimension of the views that don't set one, Ex: Cube in the cubemaps
+    pub view_dimension: TextureViewDimension,
     pub texture_view: Option<TextureView>,
     pub texture_sampler: Option<Sampler>,
 }
 impl Texture {
-    pub fn create_view(&self, descriptor: TextureViewDescriptor) -> TextureView {
+    /// create a new view, if the descriptor doesn't set the dimension it uses `view_dimension`
+    pub fn create_view(&self, mut descriptor: TextureViewDescriptor) -> TextureView {
+        descriptor.dimension = descriptor.dimension.or(Some(self.view_dimension));
         self.texture.create_view(&descriptor)
     }
+    /// gets a sampler from the cache of the renderer
     pub fn create_sampler(
         &self,
         descriptor: wgpu::SamplerDescriptor,
         renderer: &Renderer,
     ) -> Sampler {
-        renderer.device().create_sampler(&descriptor)
+        renderer.sampler(&descriptor)
     }
     pub fn texture_view(&mut self, descriptor: TextureViewDescriptor) {
-        self.texture_view = Some(self.texture.create_view(&descriptor));
+        self.texture_view = Some(self.create_view(descriptor));
     }
     pub fn texture_sampler(&mut self, descriptor: wgpu::SamplerDescriptor, renderer: &Renderer) {
         self.texture_sampler = Some(self.create_sampler(descriptor, renderer));
     }
+    /// sets the sampler of a preset, Ex: `SamplerPreset::PIXEL_ART.repeat()`
+    pub fn sampler_preset(&mut self, preset: SamplerPreset, renderer: &Renderer) {
+        self.texture_sampler = Some(renderer.sampler_preset(preset));
+    }
+    /// writes the data in a region of the texture, Ex: a frame of a video
+    /// the rows of the data are rows of blocks of the format without padding
+    pub fn write_region(
+        &self,
+        renderer: &Renderer,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<(), TextureError> {
+        let (copy_size, layout) = self.region_layout(region, data)?;
+        renderer.queue().write_texture(
+            self.region_info(region),
+            data,
+            TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(layout.bytes_per_row),
+                rows_per_image: Some(layout.rows_per_image),
+            },
+            copy_size,
+        );
+        Ok(())
+    }
+    /// records a copy of the data from a staging buffer into a region of the texture
+    /// the copy runs when the encoder is submitted, it's faster than `write_region` for big
+    /// regions that are updated inside a frame
+    pub fn write_region_staged(
+        &self,
+        renderer: &Renderer,
+        encoder: &mut wgpu::CommandEncoder,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<(), TextureError> {
+        let (copy_size, layout) = self.region_layout(region, data)?;
+        // the rows of the buffer copies are aligned to COPY_BYTES_PER_ROW_ALIGNMENT
+        let bytes_per_row = layout
+            .row_size
+            .next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let staging = layout.pad(data, bytes_per_row);
+        let buffer = renderer.init_buffer(
+            "Texture Staging Buffer",
+            wgpu::BufferUsages::COPY_SRC,
+            &staging,
+        );
+        encoder.copy_buffer_to_texture(
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(layout.rows),
+                },
+            },
+            self.region_info(region),
+            copy_size,
+        );
+        Ok(())
+    }
+    fn region_info(&self, region: TextureRegion) -> TexelCopyTextureInfo<'_> {
+        TexelCopyTextureInfo {
+            texture: &self.texture,
+            mip_level: region.mip_level,
+            origin: Origin3d {
+                x: region.origin.0,
+                y: region.origin.1,
+                z: region.layer,
+            },
+            aspect: TextureAspect::All,
+        }
+    }
+    /// checks that the region is inside the texture and aligned to the blocks of the format
+    /// returns the size of the copy and the layout of the data
+    fn region_layout(
+        &self,
+        region: TextureRegion,
+        data: &[u8],
+    ) -> Result<(wgpu::Extent3d, UploadLayout), TextureError> {
+        let format = self.texture.format();
+        if region.mip_level >= self.texture.mip_level_count()
+            || region.layer >= self.texture.depth_or_array_layers()
+        {
+            return Err(TextureError::RegionOutOfBounds);
+        }
+        let level = self
+            .texture
+            .size()
+            .mip_level_size(region.mip_level, self.texture.dimension());
+        let (x, y) = region.origin;
+        let (width, height) = region.size;
+        if x + width > level.width || y + height > level.height {
+            return Err(TextureError::RegionOutOfBounds);
+        }
+        // the regions of the block formats can end in the middle of a block at the edge
+        let (block_width, block_height) = format.block_dimensions();
+        let aligned = |start: u32, len: u32, end: u32, block: u32| {
+            start.is_multiple_of(block) && (len.is_multiple_of(block) || start + len == end)
+        };
+        if !aligned(x, width, level.width, block_width)
+            || !aligned(y, height, level.height, block_height)
+        {
+            return Err(TextureError::RegionAlignment(block_width, block_height));
+        }
+        let copy_size = wgpu::Extent3d {
+            width: width.next_multiple_of(block_width),
+            height: height.next_multiple_of(block_height),
+            depth_or_array_layers: 1,
+        };
+        let layout = UploadLayout::new(format, TextureAspect::All, copy_size, None, None)?;
+        layout.check(0, data.len(), 1)?;
+        Ok((copy_size, layout))
+    }
+    /// sample type of the texture in the shaders, the depth formats are sampled as depth, also
+    /// the depth-stencil ones, and the float 32 formats are filterable with `FLOAT32_FILTERABLE`
+    pub fn sample_type(&self, features: wgpu::Features) -> Option<TextureSampleType> {
+        let format = self.texture.format();
+        let aspect = format
+            .has_depth_aspect()
+            .then_some(TextureAspect::DepthOnly);
+        format.sample_type(aspect, Some(features))
+    }
+    /// create a bind group with the texture in the binding 0 and the sampler in the binding 1
+    /// the bindings match the sample type of the format, the depth formats use a
+    /// `sampler_comparison` and a view of the depth aspect, the formats that aren't filterable,
+    /// Ex: `R32Uint`, use a non filtering sampler, so the sampler must use nearest filters
     pub fn default_bind_group(
         &self,
         label: &str,
         renderer: &Renderer,
-    ) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
+    ) -> (std::sync::Arc<wgpu::BindGroupLayout>, wgpu::BindGroup) {
         let texture_view = self
             .texture_view
             .as_ref()
@@ -267,20 +743,36 @@ impl Texture {
             .as_ref()
             .expect("TextureSampler is None, use 'texture_sampler' function to set-it");
 
+        let sample_type = self
+            .sample_type(renderer.features())
+            .expect("The format of the texture can't be sampled");
+        // the depth-stencil formats can only be sampled through a view of one aspect
+        let depth_view = match sample_type {
+            TextureSampleType::Depth => Some(self.create_view(TextureViewDescriptor {
+                aspect: TextureAspect::DepthOnly,
+                ..Default::default()
+            })),
+            _ => None,
+        };
+        let sampler = match sample_type {
+            TextureSampleType::Depth => wgpu::SamplerBindingType::Comparison,
+            TextureSampleType::Float { filterable: true } => wgpu::SamplerBindingType::Filtering,
+            _ => wgpu::SamplerBindingType::NonFiltering,
+        };
         let (bind_group, bind_group_layout) = renderer.bind_group(
             label,
             &[
                 BindGroupEntryBuilder::new(0)
                     .on(ShaderStages::FRAGMENT)
-                    .of(BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    })
-                    .with(wgpu::BindingResource::TextureView(texture_view)),
+                    .texture()
+                    .sample_type(sample_type)
+                    .dimension(self.view_dimension)
+                    .with(wgpu::BindingResource::TextureView(
+                        depth_view.as_ref().unwrap_or(texture_view),
+                    )),
                 BindGroupEntryBuilder::new(1)
                     .on(ShaderStages::FRAGMENT)
-                    .of(BindingType::Sampler(wgpu::SamplerBindingType::Filtering))
+                    .of(BindingType::Sampler(sampler))
                     .with(wgpu::BindingResource::Sampler(texture_sampler)),
             ],
         );
@@ -288,3 +780,276 @@ impl Texture {
         (bind_group_layout, bind_group)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texture(renderer: &Renderer, builder: TextureBuilder) -> Result<Texture, TextureError> {
+        builder.try_build("Test Texture", None, renderer)
+    }
+
+    #[test]
+    fn array_and_cube_dimensions() {
+        let array = TextureDimensions::new_2d_array(16, 8, 3);
+        assert_eq!(array.view_dimension(), TextureViewDimension::D2Array);
+        assert_eq!(array.wgpu_texture_dimension(), TextureDimension::D2);
+        assert_eq!(array.layers(), 3);
+        assert_eq!(array.build().depth_or_array_layers, 3);
+
+        let cube = TextureDimensions::new_cube(32);
+        assert_eq!(cube.view_dimension(), TextureViewDimension::Cube);
+        assert_eq!(cube.wgpu_texture_dimension(), TextureDimension::D2);
+        assert_eq!(cube.layers(), 6);
+        let size = cube.build();
+        assert_eq!(
+            (size.width, size.height, size.depth_or_array_layers),
+            (32, 32, 6)
+        );
+
+        let cubes = TextureDimensions::new_cube_array(32, 2);
+        assert_eq!(cubes.view_dimension(), TextureViewDimension::CubeArray);
+        assert_eq!(cubes.layers(), 12);
+        assert_eq!(cubes.build().depth_or_array_layers, 12);
+
+        // the 3d textures have a single layer of depth slices
+        let volume = TextureDimensions::new_3d(8, 8, 4);
+        assert_eq!(volume.view_dimension(), TextureViewDimension::D3);
+        assert_eq!(volume.layers(), 1);
+    }
+
+    #[test]
+    fn layers_start_after_the_padded_image() {
+        let size = wgpu::Extent3d {
+            width: 4,
+            height: 2,
+            depth_or_array_layers: 3,
+        };
+        let layout = UploadLayout::new(
+            TextureFormat::Rgba8Unorm,
+            TextureAspect::All,
+            size,
+            Some(32),
+            Some(3),
+        )
+        .unwrap();
+        assert_eq!(layout.image_size(), 96);
+        assert_eq!(layout.image_offset(0, 0), 0);
+        assert_eq!(layout.image_offset(0, 2), 192);
+        assert_eq!(layout.image_offset(8, 1), 104);
+
+        // the padding after the last row of the last layer is optional
+        let min = 2 * 96 + 32 + 16;
+        assert!(layout.check(0, min, 3).is_ok());
+        assert!(layout.check(0, 3 * 96, 3).is_ok());
+        assert!(matches!(
+            layout.check(0, min - 1, 3),
+            Err(TextureError::DataSize { expected, .. }) if expected == min as u64
+        ));
+        assert!(layout.check(0, 3 * 96 + 1, 3).is_err());
+        assert!(layout.check(8, min + 8, 3).is_ok());
+
+        // the rows of a layer without the padding
+        let data: Vec<u8> = (0..96).collect();
+        let image = layout.unpad(&data);
+        assert_eq!(image.len(), 32);
+        assert_eq!(image[..16], data[..16]);
+        assert_eq!(image[16..], data[32..48]);
+    }
+
+    #[test]
+    fn layers_are_uploaded_one_per_layer() {
+        let renderer = crate::testing::renderer();
+        let face = vec![255; 4 * 4 * 4];
+        let cube = texture(
+            &renderer,
+            TextureBuilder::new()
+                .dimension(TextureDimensions::new_cube(4))
+                .layers(vec![face.clone(); 6]),
+        )
+        .unwrap();
+        assert_eq!(cube.view_dimension, TextureViewDimension::Cube);
+        assert_eq!(cube.texture.depth_or_array_layers(), 6);
+
+        // the layers from the origin
+        let array = texture(
+            &renderer,
+            TextureBuilder::new()
+                .dimension(TextureDimensions::new_2d_array(4, 4, 3))
+                .origin(Origin3d { x: 0, y: 0, z: 1 })
+                .layers(vec![face.clone(); 2]),
+        );
+        assert!(array.is_ok());
+        let array = texture(
+            &renderer,
+            TextureBuilder::new()
+                .dimension(TextureDimensions::new_2d_array(4, 4, 3))
+                .origin(Origin3d { x: 0, y: 0, z: 2 })
+                .layers(vec![face.clone(); 2]),
+        );
+        assert!(matches!(
+            array,
+            Err(TextureError::LayerCount { layers: 2, max: 1 })
+        ));
+
+        // every layer is checked
+        let array = texture(
+            &renderer,
+            TextureBuilder::new()
+                .dimension(TextureDimensions::new_2d_array(4, 4, 2))
+                .layers(vec![face.clone(), vec![255; 4]]),
+        );
+        assert!(matches!(array, Err(TextureError::DataSize { .. })));
+
+        // a single buffer with all the layers
+        let array = texture(
+            &renderer,
+            TextureBuilder::new()
+                .dimension(TextureDimensions::new_2d_array(4, 4, 2))
+                .data(vec![255; 2 * 64]),
+        );
+        assert!(array.is_ok());
+    }
+
+    #[test]
+    fn regions_are_checked_against_the_texture() {
+        let renderer = crate::testing::renderer();
+        let texture = texture(
+            &renderer,
+            TextureBuilder::new()
+                .dimension(TextureDimensions::new_2d_array(16, 8, 2))
+                .mip_level_count(2),
+        )
+        .unwrap();
+
+        let (size, layout) = texture
+            .region_layout(TextureRegion::new((4, 2), (8, 4)).layer(1), &[0; 8 * 4 * 4])
+            .unwrap();
+        assert_eq!(
+            (size.width, size.height, size.depth_or_array_layers),
+            (8, 4, 1)
+        );
+        assert_eq!((layout.row_size, layout.rows), (32, 4));
+
+        let out_of_bounds = [
+            TextureRegion::new((12, 0), (8, 4)),
+            TextureRegion::new((0, 6), (4, 4)),
+            TextureRegion::new((0, 0), (4, 4)).layer(2),
+            TextureRegion::new((0, 0), (4, 4)).mip_level(2),
+            // the mip level 1 is 8x4
+            TextureRegion::new((0, 0), (16, 8)).mip_level(1),
+        ];
+        for region in out_of_bounds {
+            assert!(
+                matches!(
+                    texture.region_layout(region, &[0; 1024]),
+                    Err(TextureError::RegionOutOfBounds)
+                ),
+                "{region:?}"
+            );
+        }
+        assert!(matches!(
+            texture.region_layout(TextureRegion::new((0, 0), (4, 4)), &[0; 60]),
+            Err(TextureError::DataSize { expected: 64, .. })
+        ));
+    }
+
+    #[test]
+    fn regions_of_block_formats_are_aligned_to_the_blocks() {
+        let renderer = crate::testing::renderer_with(
+            wgpu::Features::TEXTURE_COMPRESSION_BC,
+            Default::default(),
+        );
+        let texture = texture(
+            &renderer,
+            TextureBuilder::new()
+                .dimension(TextureDimensions::new_2d(16, 12))
+                .format(TextureFormat::Bc1RgbaUnorm)
+                .mip_level_count(2),
+        )
+        .unwrap();
+
+        // 2x1 blocks of 8 bytes
+        let (size, layout) = texture
+            .region_layout(TextureRegion::new((4, 0), (8, 4)), &[0; 16])
+            .unwrap();
+        assert_eq!((size.width, size.height), (8, 4));
+        assert_eq!((layout.row_size, layout.rows), (16, 1));
+
+        assert!(matches!(
+            texture.region_layout(TextureRegion::new((2, 0), (4, 4)), &[0; 8]),
+            Err(TextureError::RegionAlignment(4, 4))
+        ));
+        assert!(matches!(
+            texture.region_layout(TextureRegion::new((0, 0), (6, 4)), &[0; 16]),
+            Err(TextureError::RegionAlignment(4, 4))
+        ));
+        // the regions at the edge end in the middle of a block, the mip level 1 is 8x6
+        let (size, layout) = texture
+            .region_layout(TextureRegion::new((0, 4), (8, 2)).mip_level(1), &[0; 16])
+            .unwrap();
+        assert_eq!((size.width, size.height), (8, 4));
+        assert_eq!((layout.row_size, layout.rows), (16, 1));
+        assert!(matches!(
+            texture.region_layout(TextureRegion::new((0, 0), (8, 2)).mip_level(1), &[0; 16]),
+            Err(TextureError::RegionAlignment(4, 4))
+        ));
+        texture
+            .write_region(
+                &renderer,
+                TextureRegion::new((0, 4), (8, 2)).mip_level(1),
+                &[0; 16],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn staged_rows_are_aligned_to_the_copy_alignment() {
+        let size = wgpu::Extent3d {
+            width: 5,
+            height: 3,
+            depth_or_array_layers: 1,
+        };
+        let layout = UploadLayout::new(
+            TextureFormat::Rgba8Unorm,
+            TextureAspect::All,
+            size,
+            None,
+            None,
+        )
+        .unwrap();
+        let data: Vec<u8> = (1..=60).collect();
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded = layout.pad(&data, layout.row_size.next_multiple_of(alignment));
+        assert_eq!(padded.len(), 3 * alignment as usize);
+        for row in 0..3 {
+            let start = row * alignment as usize;
+            assert_eq!(padded[start..start + 20], data[row * 20..row * 20 + 20]);
+            assert!(
+                padded[start + 20..start + alignment as usize]
+                    .iter()
+                    .all(|byte| *byte == 0)
+            );
+        }
+
+        // the staged copy is recorded without validation errors
+        let renderer = crate::testing::renderer();
+        let texture = texture(
+            &renderer,
+            TextureBuilder::new().dimension(TextureDimensions::new_2d(8, 8)),
+        )
+        .unwrap();
+        let mut encoder = renderer
+            .device()
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        texture
+            .write_region_staged(
+                &renderer,
+                &mut encoder,
+                TextureRegion::new((1, 2), (5, 3)),
+                &data,
+            )
+            .unwrap();
+        renderer.queue().submit([encoder.finish()]);
+    }
+}
@@ -1,7 +1,11 @@
 use wgpu::VertexBufferLayout;
+
+/// Derive macro of `Vertex`, computes the layout from the fields of the struct
+pub use steamengine_renderer_derive::Vertex;
 /// This trait is the layout of one vertex
 /// ## Example
 /// ```rust
+/// use steamengine_renderer::vertex::Vertex;
 ///
 /// // The vertex has a position and color values
 /// #[repr(C)]
@@ -10,50 +14,57 @@ use wgpu::VertexBufferLayout;
 ///     pub position: [f32; 3],
 ///     pub color: [f32; 3],
 /// }
-/// impl Vertex for Vertex3DColor {
 /// // This is the description of the vertex
+/// const ATTRIBS: [wgpu::VertexAttribute; 2] =
+///     wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
 ///
-///   const ATTRIBS: [wgpu::VertexAttribute; 2] =
-///      wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
-///
+/// impl Vertex for Vertex3DColor {
 ///     fn desc() -> wgpu::VertexBufferLayout<'static> {
 ///        use std::mem;
 ///
 ///        wgpu::VertexBufferLayout {
 ///            array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
 ///            step_mode: wgpu::VertexStepMode::Vertex,
-///            attributes: &Self::ATTRIBS,
+///            attributes: &ATTRIBS,
 ///        }
 ///     }
 /// }
 /// ```
+/// The same layout can be derived, matrices use one location per column
+/// ```rust
+/// use steamengine_renderer::vertex::Vertex;
+///
+/// #[repr(C)]
+/// #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
+/// pub struct Vertex3DColor {
+///     pub position: [f32; 3],
+///     pub color: [f32; 3],
+/// }
+///
+/// #[repr(C)]
+/// #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
+/// #[vertex(instance)]
+/// pub struct RawInstance {
+///     #[location(5)]
+///     pub matrix: [[f32; 4]; 4],
+///     #[format(Unorm8x4)]
+///     pub color: [u8; 4],
+/// }
+///
+/// let layout = RawInstance::desc();
+/// assert_eq!(layout.step_mode, wgpu::VertexStepMode::Instance);
+/// assert_eq!(layout.attributes.len(), 5);
+/// assert_eq!(layout.attributes[3].offset, 48);
+/// assert_eq!(layout.attributes[4].shader_location, 9);
+/// assert_eq!(layout.attributes[4].format, wgpu::VertexFormat::Unorm8x4);
+/// ```
 pub trait Vertex: Copy + Clone + bytemuck::Pod + bytemuck::Zeroable {
     fn desc() -> VertexBufferLayout<'static>;
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
 pub struct VertexBasicWithTexture {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
 }
-impl Vertex for VertexBasicWithTexture {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        VertexBufferLayout {
-            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-            ],
-        }
-    }
-}